@@ -314,7 +314,8 @@ impl JsonSchema {
 /// If you make the array paths generic ([0-9]+ -> []) and find the unique values by the key
 /// using their (path, type), then you can find all the variations of the types at that
 /// path. Using this idea, you can generate a representation of all the possibilities of
-/// the types.
+/// the types. `JsonPath::generalize_indices` does the `sed` step, and sorting the paths
+/// gives the topological order used below.
 ///
 /// This function aims to do that explicitly.
 ///
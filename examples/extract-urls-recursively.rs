@@ -1,14 +1,15 @@
 #![warn(const_err, clippy::all)]
 
+use parser::path_stack::{PathStack, StackSegment};
 use parser::section::ByteSection;
+use parser::string::{self, StringError};
 use parser::tokenizer::{compress_next_token, utils::is_whitespace, Token};
 use parser::validator::{ValidationContext, ValidationError, ValidationState, Validator};
+use parser::writer::JsonWriter;
 use parser::{JsonPath, JsonPathSegment, JsonType};
 
 use log::*;
-use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fmt;
 use std::io::{self, stdin, Read, Write};
 
 use derive_more::From;
@@ -17,6 +18,8 @@ use derive_more::From;
 enum Error {
     Io(io::Error),
     Validation(ValidationError),
+    String(StringError),
+    Parser(parser::Error),
 }
 
 #[derive(Debug, Default)]
@@ -25,23 +28,37 @@ struct Output {
     title: Option<String>,
 }
 
-impl fmt::Display for Output {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            fmt,
-            r#"{{"url":{},"title":{}}}"#,
-            self.url.as_ref().unwrap(),
-            self.title.as_ref().unwrap()
-        )
+impl Output {
+    fn write<W: Write>(&self, writer: &mut JsonWriter<W>) -> Result<(), parser::Error> {
+        writer.begin_object()?;
+        writer.key("url")?;
+        writer.string(self.url.as_ref().unwrap())?;
+        writer.key("title")?;
+        writer.string(self.title.as_ref().unwrap())?;
+        writer.end()
     }
-}
 
-impl Output {
     fn is_invalid(&self) -> bool {
         self.url.is_none() || self.title.is_none()
     }
 }
 
+/// The path of the container holding the current value. Keys are kept as
+/// their raw tokens, so they're matched exactly as they were written.
+fn raw_parent_path(path: &PathStack<'_>) -> JsonPath<'static> {
+    let segments = path.segments()[..path.len() - 1]
+        .iter()
+        .map(|segment| match segment {
+            StackSegment::Index(n) => JsonPathSegment::Index(*n),
+            StackSegment::Key(_) => {
+                let raw = path.raw_key(segment).unwrap_or_default();
+                JsonPathSegment::Key(String::from_utf8_lossy(raw).into_owned().into())
+            }
+        })
+        .collect::<Vec<_>>();
+    JsonPath::new(segments.into())
+}
+
 fn entrypoint(input: &[u8]) -> Result<(), Error> {
     let mut validator = Validator::new();
     let mut last_state = ValidationState::Incomplete;
@@ -49,18 +66,23 @@ fn entrypoint(input: &[u8]) -> Result<(), Error> {
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
 
-    let mut path: Vec<JsonPathSegment> = Vec::new();
+    // Keys are kept as byte ranges into the input, and only decoded when a
+    // path needs to be stored as an output key.
+    let mut path = PathStack::new(input);
 
     // TODO(ashkan): make the output ordered.
-    let mut outputs: HashMap<JsonPath, Output> = Default::default();
-    let mut ordering: Vec<JsonPath> = Vec::new();
+    let mut outputs: HashMap<JsonPath<'static>, Output> = Default::default();
+    let mut ordering: Vec<JsonPath<'static>> = Vec::new();
 
     macro_rules! flush_outputs {
         () => {
             for key in ordering.drain(..) {
                 let output = &outputs[&key];
                 if !output.is_invalid() {
-                    writeln!(stdout, "{}", output)?;
+                    let mut writer = JsonWriter::new(&mut stdout);
+                    output.write(&mut writer)?;
+                    writer.finish()?;
+                    writeln!(stdout)?;
                 }
             }
             outputs.clear();
@@ -81,7 +103,12 @@ fn entrypoint(input: &[u8]) -> Result<(), Error> {
     }
 
     let mut section = ByteSection::new(input);
-    while let Ok(token) = compress_next_token(&mut section, is_whitespace) {
+    loop {
+        let token_start = section.n;
+        let token = match compress_next_token(&mut section, is_whitespace) {
+            Ok(token) => token,
+            Err(_) => break,
+        };
         if token.is_whitespace() {
             continue;
         }
@@ -151,7 +178,7 @@ fn entrypoint(input: &[u8]) -> Result<(), Error> {
             None
         };
 
-        let display_path = JsonPath::new(path.clone().into());
+        let display_path = &path;
 
         // PREORDER: Results in printing values, arrays, and objects at the start.
         if is_start_of_value {
@@ -180,16 +207,17 @@ fn entrypoint(input: &[u8]) -> Result<(), Error> {
             // At this point, you have access to the path and the value
             info!("{} = {:?}", display_path, token);
 
-            if let Some(key) = display_path.last().and_then(|p| p.as_key()) {
-                if key == r#""url""# {
-                    if let Some(url) = token.as_string() {
-                        // TODO(ashkan): check if url is already set?
-                        lookup_output!(display_path.parent()).url = Some(url.to_owned());
-                    }
-                } else if key == r#""title""# {
-                    if let Some(title) = token.as_string() {
-                        lookup_output!(display_path.parent()).title = Some(title.to_owned());
-                    }
+            let last_key = path.last().and_then(|segment| path.raw_key(segment));
+            if last_key == Some(&br#""url""#[..]) {
+                if let Token::String(ref raw) = token {
+                    // TODO(ashkan): check if url is already set?
+                    lookup_output!(raw_parent_path(&path)).url =
+                        Some(string::decode(raw)?.into_owned());
+                }
+            } else if last_key == Some(&br#""title""#[..]) {
+                if let Token::String(ref raw) = token {
+                    lookup_output!(raw_parent_path(&path)).title =
+                        Some(string::decode(raw)?.into_owned());
                 }
             }
         }
@@ -201,82 +229,32 @@ fn entrypoint(input: &[u8]) -> Result<(), Error> {
 
             match token_value_type {
                 Some(JsonType::Object) => {
-                    let last_key = container_last_segment.and_then(|p| p.as_key());
-                    info!("{} = object.end, lastkey={:?}", display_path, last_key,);
+                    let last_key = container_last_segment.and_then(|p| path.raw_key(&p));
+                    info!(
+                        "{} = object.end, lastkey={:?}",
+                        display_path,
+                        last_key.map(String::from_utf8_lossy)
+                    );
                     // TODO figure out how to make this work.
                     // if let Some(output) = outputs.get(&display_path) {
                     //     println!("{:?}", output);
                     // }
                 }
                 Some(JsonType::Array) => {
-                    let array_length = container_last_segment
-                        .and_then(|p| p.as_index())
-                        .expect("expected index at path segment");
+                    let array_length = match container_last_segment {
+                        Some(StackSegment::Index(n)) => n,
+                        _ => panic!("expected index at path segment"),
+                    };
                     info!("{} = array.end, length={}", display_path, array_length,);
                 }
                 _ => (),
             }
         }
 
-        /* Update the path.
-         * Path changes should occur:
-         * - At the start of an array, push 0
-         * - After a comma for an array element, increment 1
-         * - After array end, pop
-         * - At object start, push null
-         * - At an object key, change key
-         * - After an object close, pop
+        /* Update the path. Containers were already popped above, before the post-order visit.
+         * See `PathStack::update` for the rest of the rules.
          */
-        match validator.current_context() {
-            Some(ValidationContext::ObjectStart) => {
-                /*
-                 * SKELETON: Entrypoint to start of new object.
-                 */
-
-                // Push path for modification
-                path.push(parser::EMPTY_KEY);
-            }
-            Some(ValidationContext::ObjectEntryKey) => {
-                if let Token::String(new_key) = token {
-                    if let Some(ref mut segment) = path.last_mut() {
-                        if let Some(key) = segment.as_key_mut() {
-                            /*
-                             * SKELETON: Transition from old key to new key. Path is still pointing to the old
-                             */
-                            // *key = new_key;
-                            *key = match new_key {
-                                Cow::Borrowed(bytes) => {
-                                    Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(bytes) })
-                                }
-                                Cow::Owned(bytes) => {
-                                    Cow::Owned(unsafe { String::from_utf8_unchecked(bytes) })
-                                }
-                            }
-                        }
-                        /*
-                         * SKELETON: Entrypoint to new key. Path is updated
-                         */
-                    }
-                }
-            }
-            // Some(ValidationContext::ObjectEnd) | Some(ValidationContext::ArrayEnd) => { }
-            Some(ValidationContext::ArrayStart) => {
-                // Set up index path for new values.
-                // If the array is empty, then this shouldn't be used.
-                path.push(0.into());
-            }
-            Some(ValidationContext::ArrayValue) => {
-                // I wish I could use this instead.
-                // try {
-                //     *path.last_mut()?.as_index()? += 1;
-                // }
-                // if let Some(n) = path.last_mut().and_then(|p| p.as_index_mut()) {
-                if let Some(JsonPathSegment::Index(ref mut n)) = path.last_mut() {
-                    *n += 1;
-                }
-            }
-            _ => (),
-        }
+        path.update(&token, token_start, validator.current_context());
     }
     validator.finish()?;
 
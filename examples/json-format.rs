@@ -0,0 +1,79 @@
+#![warn(clippy::all)]
+
+//! Reformat JSON from stdin.
+//!
+//! Usage: json-format [--compact | --comma-first] [--width N] [--sort-keys]
+//! [--color]
+
+use parser::format::{FormatOptions, Formatter, Style};
+use parser::highlight::Palette;
+use parser::sort::{KeySorter, SortOptions};
+use parser::stream::ReaderStream;
+
+use std::io::{self, stdin, stdout};
+
+use derive_more::From;
+
+#[derive(Debug, From)]
+enum Error {
+    Io(io::Error),
+    Parser(parser::Error),
+    Usage(String),
+}
+
+struct Opt {
+    format: FormatOptions,
+    sort_keys: bool,
+}
+
+fn parse_options() -> Result<Opt, Error> {
+    let mut opt = Opt {
+        format: FormatOptions::default(),
+        sort_keys: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--compact" => {
+                opt.format = FormatOptions {
+                    colors: opt.format.colors,
+                    ..FormatOptions::compact()
+                }
+            }
+            "--comma-first" => opt.format.style = Style::CommaFirst,
+            "--width" => {
+                let width = args.next().and_then(|n| n.parse().ok());
+                if width.is_none() {
+                    return Err(Error::Usage("--width needs a number".into()));
+                }
+                opt.format.max_width = width;
+            }
+            "--sort-keys" => opt.sort_keys = true,
+            "--color" => opt.format.colors = Palette::from_env(),
+            _ => return Err(Error::Usage(format!("unknown argument {:?}", arg))),
+        }
+    }
+    Ok(opt)
+}
+
+fn main() -> Result<(), Error> {
+    let opt = parse_options()?;
+    let stdin = stdin();
+    let mut stream = ReaderStream::new(stdin.lock());
+    let stdout = stdout();
+    let formatter = Formatter::new(io::BufWriter::new(stdout.lock()), opt.format);
+    if opt.sort_keys {
+        let mut sorter = KeySorter::new(formatter, SortOptions::default());
+        while let Some(token) = stream.next_token()? {
+            sorter.write_token(&token.token)?;
+        }
+        sorter.finish()?;
+    } else {
+        let mut formatter = formatter;
+        while let Some(token) = stream.next_token()? {
+            formatter.write_token(&token.token)?;
+        }
+        formatter.finish()?;
+    }
+    Ok(())
+}
@@ -1,7 +1,16 @@
 #![warn(clippy::all)]
 #![warn(const_err)]
 
+//! Reformat JSON from stdin.
+//!
+//! Usage: json-reformat [--pretty | --comma-first]
+//!
+//! Without an argument, tokens are copied compactly as each chunk is read.
+//! With one, they're laid out by a `Formatter` in that style.
+
+use parser::format::{FormatOptions, Formatter, Style};
 use parser::section::{ByteSection, PeekSeek};
+use parser::stream::ReaderStream;
 use parser::tokenizer::{
     compress_next_token, utils, utils::is_whitespace, Token, TokenContext, TokenizeError,
 };
@@ -34,6 +43,8 @@ pub enum Error {
     UnexpectedEndOfInput,
     InvalidNumber(std::num::ParseFloatError),
     InvalidInt(std::num::ParseIntError),
+    Parser(parser::Error),
+    Usage(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -327,6 +338,33 @@ fn chunked_entrypoint() -> Result<()> {
     Ok(())
 }
 
+/// Reformat with a `Formatter` in `style` rather than copying tokens.
+fn formatted_entrypoint(style: Style) -> Result<()> {
+    let stdin = stdin();
+    let mut stream = ReaderStream::new(stdin.lock());
+    let stdout = stdout();
+    let options = FormatOptions {
+        style,
+        ..FormatOptions::default()
+    };
+    let mut formatter = Formatter::new(io::BufWriter::new(stdout.lock()), options);
+    let mut had_tokens = false;
+    while let Some(token) = stream.next_token()? {
+        formatter.write_token(&token.token)?;
+        had_tokens = true;
+    }
+    if !had_tokens {
+        return Err(Error::EmptyInput);
+    }
+    formatter.finish()?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    chunked_entrypoint()
+    match std::env::args().nth(1).as_deref() {
+        None => chunked_entrypoint(),
+        Some("--pretty") => formatted_entrypoint(Style::Pretty),
+        Some("--comma-first") => formatted_entrypoint(Style::CommaFirst),
+        Some(arg) => Err(Error::Usage(format!("unknown argument {:?}", arg))),
+    }
 }
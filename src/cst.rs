@@ -0,0 +1,531 @@
+//! A lossless concrete syntax tree.
+//!
+//! Every byte of the input is kept: the whitespace (and, with `relaxed`,
+//! comments) between tokens is stored as `Trivia` on the node it precedes, and
+//! scalars keep their source text. Printing an unmodified tree gives back the
+//! input byte for byte, and editing a node only changes the text it covers, so
+//! rewriting a checked-in file gives a minimal diff.
+
+use crate::section::{ByteSection, PeekSeek};
+use crate::string;
+use crate::tokenizer::{compress_next_token, utils::is_whitespace, Token};
+use crate::validator::Validator;
+use crate::{Error, JsonPathSegment, JsonType};
+
+use std::fmt;
+
+/// The text between two tokens: whitespace, and comments in relaxed mode.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trivia(pub String);
+
+impl Trivia {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    /// Trivia before the value.
+    pub leading: Trivia,
+    pub kind: NodeKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A string, number, boolean or null as its source text.
+    Scalar(String),
+    Array {
+        elements: Vec<Element>,
+        /// Trivia before the `]`.
+        end: Trivia,
+    },
+    Object {
+        members: Vec<Member>,
+        /// Trivia before the `}`.
+        end: Trivia,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+    pub value: Node,
+    /// Trivia between the value and the following comma. The last element's
+    /// trailing trivia belongs to the array's `end`.
+    pub trailing: Trivia,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub key_leading: Trivia,
+    /// The key's source text, including the quotation marks.
+    pub key: String,
+    pub before_colon: Trivia,
+    /// The value's leading trivia is the text after the colon.
+    pub value: Node,
+    /// Trivia between the value and the following comma.
+    pub trailing: Trivia,
+}
+
+impl Member {
+    /// The decoded key.
+    pub fn key(&self) -> Result<std::borrow::Cow<'_, str>, Error> {
+        Ok(string::decode(self.key.as_bytes())?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cst {
+    pub root: Node,
+    /// Trivia after the root value.
+    pub trailing: Trivia,
+}
+
+struct Lexer<'a> {
+    section: ByteSection<'a>,
+    validator: Validator,
+    relaxed: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn trivia(&mut self) -> Result<Trivia, Error> {
+        let src = self.section.src;
+        let start = self.section.n;
+        loop {
+            while self.section.check_next_pattern(is_whitespace) {}
+            let rest = &src[self.section.n..];
+            if !self.relaxed || !rest.starts_with(b"/") {
+                break;
+            }
+            let end = if rest.starts_with(b"//") {
+                rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len())
+            } else if rest.starts_with(b"/*") {
+                rest.windows(2)
+                    .position(|w| w == b"*/")
+                    .map(|n| n + 2)
+                    .ok_or(Error::UnexpectedEndOfInput)?
+            } else {
+                break;
+            };
+            self.section.skip(end);
+        }
+        Ok(Trivia(to_string(&src[start..self.section.n])?))
+    }
+
+    /// The trivia before the next token, and the token.
+    fn next(&mut self) -> Result<(Trivia, Token<'a>), Error> {
+        let trivia = self.trivia()?;
+        if self.section.is_empty() {
+            return Err(Error::UnexpectedEndOfInput);
+        }
+        let token = compress_next_token(&mut self.section, is_whitespace)?;
+        self.validator.process_token(&token)?;
+        Ok((trivia, token))
+    }
+
+    fn node(&mut self, leading: Trivia, token: Token<'a>) -> Result<Node, Error> {
+        let kind = match token {
+            Token::ArrayOpen => {
+                let mut elements = Vec::new();
+                let end = loop {
+                    let (trivia, token) = self.next()?;
+                    if token == Token::ArrayClose {
+                        break trivia;
+                    }
+                    let value = self.node(trivia, token)?;
+                    let (trailing, token) = self.next()?;
+                    if token == Token::ArrayClose {
+                        elements.push(Element {
+                            value,
+                            trailing: Trivia::default(),
+                        });
+                        break trailing;
+                    }
+                    elements.push(Element { value, trailing });
+                };
+                NodeKind::Array { elements, end }
+            }
+            Token::ObjectOpen => {
+                let mut members = Vec::new();
+                let end = loop {
+                    let (key_leading, token) = self.next()?;
+                    let key = match token {
+                        Token::ObjectClose => break key_leading,
+                        Token::String(raw) => to_string(&raw)?,
+                        // The validator rejects anything else.
+                        _ => unreachable!(),
+                    };
+                    let (before_colon, _colon) = self.next()?;
+                    let (value_leading, token) = self.next()?;
+                    let value = self.node(value_leading, token)?;
+                    let (trailing, token) = self.next()?;
+                    let member = Member {
+                        key_leading,
+                        key,
+                        before_colon,
+                        value,
+                        trailing: Trivia::default(),
+                    };
+                    if token == Token::ObjectClose {
+                        members.push(member);
+                        break trailing;
+                    }
+                    members.push(Member { trailing, ..member });
+                };
+                NodeKind::Object { members, end }
+            }
+            token => {
+                let mut raw = Vec::with_capacity(token.char_count());
+                token.print(&mut raw).expect("writing to a Vec can't fail");
+                NodeKind::Scalar(to_string(&raw)?)
+            }
+        };
+        Ok(Node { leading, kind })
+    }
+}
+
+fn to_string(bytes: &[u8]) -> Result<String, Error> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|e| Error::String(string::StringError::InvalidUtf8(e.utf8_error())))
+}
+
+impl Cst {
+    /// Parse a single document.
+    pub fn parse(input: &str) -> Result<Cst, Error> {
+        Cst::parse_with(input, false)
+    }
+
+    /// Parse a single document, allowing `//` and `/* */` comments wherever
+    /// whitespace is allowed. Comments are kept in the trivia.
+    pub fn parse_relaxed(input: &str) -> Result<Cst, Error> {
+        Cst::parse_with(input, true)
+    }
+
+    fn parse_with(input: &str, relaxed: bool) -> Result<Cst, Error> {
+        let mut lexer = Lexer {
+            section: ByteSection::new(input.as_bytes()),
+            validator: Validator::new(),
+            relaxed,
+        };
+        let (leading, token) = lexer.next()?;
+        let root = lexer.node(leading, token)?;
+        let trailing = lexer.trivia()?;
+        if !lexer.section.is_empty() {
+            return Err(Error::TrailingInput(lexer.section.n));
+        }
+        lexer.validator.finish()?;
+        Ok(Cst { root, trailing })
+    }
+
+    pub fn get(&self, path: &[JsonPathSegment<'_>]) -> Option<&Node> {
+        path.iter()
+            .try_fold(&self.root, |node, segment| node.get(segment))
+    }
+
+    pub fn get_mut(&mut self, path: &[JsonPathSegment<'_>]) -> Option<&mut Node> {
+        path.iter()
+            .try_fold(&mut self.root, |node, segment| node.get_mut(segment))
+    }
+}
+
+impl Node {
+    /// Parse a standalone value for inserting into a tree. Its leading
+    /// trivia is empty.
+    pub fn parse(json: &str) -> Result<Node, Error> {
+        let cst = Cst::parse(json.trim())?;
+        Ok(cst.root)
+    }
+
+    pub fn value_type(&self) -> JsonType {
+        match &self.kind {
+            NodeKind::Array { .. } => JsonType::Array,
+            NodeKind::Object { .. } => JsonType::Object,
+            NodeKind::Scalar(raw) => match raw.as_bytes().first() {
+                Some(b'"') => JsonType::String,
+                Some(b't') | Some(b'f') => JsonType::Bool,
+                Some(b'n') => JsonType::Null,
+                _ => JsonType::Number,
+            },
+        }
+    }
+
+    fn member_position(members: &[Member], key: &str) -> Option<usize> {
+        members
+            .iter()
+            .rposition(|m| matches!(m.key(), Ok(ref k) if k == key))
+    }
+
+    pub fn get(&self, segment: &JsonPathSegment<'_>) -> Option<&Node> {
+        match (&self.kind, segment) {
+            (NodeKind::Array { elements, .. }, JsonPathSegment::Index(i)) => {
+                elements.get(*i).map(|e| &e.value)
+            }
+            (NodeKind::Object { members, .. }, JsonPathSegment::Key(key)) => {
+                Node::member_position(members, key).map(|i| &members[i].value)
+            }
+            (NodeKind::Object { members, .. }, JsonPathSegment::Index(index)) => {
+                Node::member_position(members, &index.to_string()).map(|i| &members[i].value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, segment: &JsonPathSegment<'_>) -> Option<&mut Node> {
+        match (&mut self.kind, segment) {
+            (NodeKind::Array { elements, .. }, JsonPathSegment::Index(i)) => {
+                elements.get_mut(*i).map(|e| &mut e.value)
+            }
+            (NodeKind::Object { members, .. }, JsonPathSegment::Key(key)) => {
+                let i = Node::member_position(members, key)?;
+                Some(&mut members[i].value)
+            }
+            (NodeKind::Object { members, .. }, JsonPathSegment::Index(index)) => {
+                let i = Node::member_position(members, &index.to_string())?;
+                Some(&mut members[i].value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace the value, keeping the trivia before it.
+    pub fn set(&mut self, json: &str) -> Result<(), Error> {
+        self.kind = Node::parse(json)?.kind;
+        Ok(())
+    }
+
+    /// Set an object member, replacing the value if the key exists. New
+    /// members are added at the end, laid out like the existing last member.
+    pub fn insert(&mut self, key: &str, json: &str) -> Result<(), Error> {
+        let mut value = Node::parse(json)?;
+        let members = match self.kind {
+            NodeKind::Object {
+                ref mut members, ..
+            } => members,
+            _ => return Err(Error::WrongType(self.value_type())),
+        };
+        if let Some(i) = Node::member_position(members, key) {
+            members[i].value.kind = value.kind;
+            return Ok(());
+        }
+        let mut quoted = String::new();
+        string::write_escaped(&mut quoted, key).expect("writing to a String can't fail");
+        let member = match members.last() {
+            Some(last) => {
+                value.leading = last.value.leading.clone();
+                Member {
+                    key_leading: last.key_leading.clone(),
+                    key: quoted,
+                    before_colon: last.before_colon.clone(),
+                    value,
+                    trailing: Trivia::default(),
+                }
+            }
+            None => {
+                value.leading = Trivia(" ".into());
+                Member {
+                    key_leading: Trivia::default(),
+                    key: quoted,
+                    before_colon: Trivia::default(),
+                    value,
+                    trailing: Trivia::default(),
+                }
+            }
+        };
+        members.push(member);
+        Ok(())
+    }
+
+    /// Remove an object member, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<Node> {
+        match self.kind {
+            NodeKind::Object {
+                ref mut members, ..
+            } => {
+                let i = Node::member_position(members, key)?;
+                Some(remove_item(members, i, |m| &mut m.trailing).value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Append an array element, laid out like the existing last element.
+    pub fn push(&mut self, json: &str) -> Result<(), Error> {
+        let mut value = Node::parse(json)?;
+        match self.kind {
+            NodeKind::Array {
+                ref mut elements, ..
+            } => {
+                if let Some(last) = elements.last() {
+                    value.leading = last.value.leading.clone();
+                }
+                elements.push(Element {
+                    value,
+                    trailing: Trivia::default(),
+                });
+                Ok(())
+            }
+            _ => Err(Error::WrongType(self.value_type())),
+        }
+    }
+
+    /// Remove an array element, returning it.
+    pub fn remove_index(&mut self, index: usize) -> Option<Node> {
+        match self.kind {
+            NodeKind::Array {
+                ref mut elements, ..
+            } if index < elements.len() => {
+                Some(remove_item(elements, index, |e| &mut e.trailing).value)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Remove an item while keeping the trivia around its neighbours intact.
+/// When the last item goes, the one before it becomes last, and its
+/// trailing trivia (before the comma which is removed) is dropped.
+fn remove_item<T, F: Fn(&mut T) -> &mut Trivia>(
+    items: &mut Vec<T>,
+    index: usize,
+    trailing: F,
+) -> T {
+    let removed = items.remove(index);
+    if index == items.len() {
+        if let Some(last) = items.last_mut() {
+            *trailing(last) = Trivia::default();
+        }
+    }
+    removed
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.leading.as_str())?;
+        match &self.kind {
+            NodeKind::Scalar(raw) => f.write_str(raw),
+            NodeKind::Array { elements, end } => {
+                f.write_str("[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}{}", element.value, element.trailing.as_str())?;
+                }
+                write!(f, "{}]", end.as_str())
+            }
+            NodeKind::Object { members, end } => {
+                f.write_str("{")?;
+                for (i, member) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(
+                        f,
+                        "{}{}{}:{}{}",
+                        member.key_leading.as_str(),
+                        member.key,
+                        member.before_colon.as_str(),
+                        member.value,
+                        member.trailing.as_str()
+                    )?;
+                }
+                write!(f, "{}}}", end.as_str())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.root, self.trailing.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonPath;
+
+    const CONFIG: &str = r#"
+{
+  "name": "parser",
+  "deps" : [ "a",
+             "b" ],
+  "empty": {  },
+  "nested": {"x": 1.50, "y": "\u0041"}
+}
+"#;
+
+    fn path(pointer: &str) -> JsonPath<'static> {
+        JsonPath::from_pointer(pointer).unwrap()
+    }
+
+    #[test]
+    fn round_trips() -> Result<(), Error> {
+        assert_eq!(Cst::parse(CONFIG)?.to_string(), CONFIG);
+        for input in &["1", " [] ", "\"\\u00e9\"\n", "{\"a\":[{}]}"] {
+            assert_eq!(Cst::parse(input)?.to_string(), *input);
+        }
+        assert!(Cst::parse("[1,]").is_err());
+        assert!(Cst::parse("[1] 2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn comments() -> Result<(), Error> {
+        let input = "// header\n{\n  /* a */ \"a\": 1, // one\n  \"b\": [] /* end */\n}\n";
+        assert_eq!(Cst::parse_relaxed(input)?.to_string(), input);
+        assert!(Cst::parse(input).is_err());
+        assert!(Cst::parse_relaxed("[1 /* ]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn edits_are_local() -> Result<(), Error> {
+        let mut cst = Cst::parse(CONFIG)?;
+        cst.get_mut(&path("/nested/x")).unwrap().set("2")?;
+        cst.get_mut(&path("/deps")).unwrap().push("\"c\"")?;
+        cst.root.insert("version", "\"0.2.0\"")?;
+        cst.get_mut(&path("/empty")).unwrap().insert("k", "true")?;
+        assert!(cst.root.remove("name").is_some());
+        assert!(matches!(
+            cst.get_mut(&path("/deps")).unwrap().insert("k", "1"),
+            Err(Error::WrongType(JsonType::Array))
+        ));
+        assert!(matches!(
+            cst.get_mut(&path("/nested/y")).unwrap().push("1"),
+            Err(Error::WrongType(JsonType::String))
+        ));
+        assert_eq!(
+            cst.to_string(),
+            r#"
+{
+  "deps" : [ "a",
+             "b",
+             "c" ],
+  "empty": {"k": true  },
+  "nested": {"x": 2, "y": "\u0041"},
+  "version": "0.2.0"
+}
+"#
+        );
+
+        cst.get_mut(&path("/deps")).unwrap().remove_index(2);
+        assert!(cst.root.remove("version").is_some());
+        assert!(cst.to_string().ends_with("\"y\": \"\\u0041\"}\n}\n"));
+        assert_eq!(
+            cst.get(&path("/nested/y")),
+            Some(&Node {
+                leading: Trivia(" ".into()),
+                kind: NodeKind::Scalar(r#""\u0041""#.into())
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,550 @@
+//! A serde `Deserializer` driven directly by the tokenizer and `Validator`.
+//!
+//! Since the validator has already checked the grammar by the time a token
+//! reaches the deserializer, commas and colons are skipped rather than
+//! checked again. Strings without escapes are borrowed from the input, so
+//! `&'de str` fields work as long as the input doesn't escape them.
+//!
+//! Integers which don't fit in an `i64` or `u64` are visited as `f64`, and
+//! numbers too large for an `f64` are an error.
+
+use crate::raw;
+use crate::stream::{StreamToken, TokenStream};
+use crate::string;
+use crate::tokenizer::Token;
+
+use serde::de::{self, DeserializeSeed, Visitor};
+use serde::forward_to_deserialize_any;
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Where in the input an error occurred. Lines and columns start at 1, and
+/// columns count bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn from_offset(input: &[u8], offset: usize) -> Position {
+        let offset = offset.min(input.len());
+        let before = &input[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |n| n + 1);
+        Position {
+            offset,
+            line: before.iter().filter(|&&c| c == b'\n').count() + 1,
+            column: offset - line_start + 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    message: String,
+    position: Option<Position>,
+}
+
+impl Error {
+    fn new<T: fmt::Display>(message: T) -> Error {
+        Error {
+            message: message.to_string(),
+            position: None,
+        }
+    }
+
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    #[inline]
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(
+                f,
+                "{} at line {} column {}",
+                self.message, position.line, position.column
+            ),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Error::new(message)
+    }
+}
+
+impl From<crate::Error> for Error {
+    fn from(error: crate::Error) -> Self {
+        Error::new(error)
+    }
+}
+
+impl From<string::StringError> for Error {
+    fn from(error: string::StringError) -> Self {
+        Error::new(error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub struct Deserializer<'de> {
+    stream: TokenStream<'de>,
+    peeked: Option<StreamToken<'de>>,
+    /// The start of the last token read, for error positions.
+    offset: usize,
+}
+
+/// Deserialize a single document, which may be surrounded by whitespace.
+pub fn from_slice<'de, T: de::Deserialize<'de>>(input: &'de [u8]) -> Result<T> {
+    let mut deserializer = Deserializer::new(input);
+    let value = T::deserialize(&mut deserializer).and_then(|value| {
+        deserializer.end()?;
+        Ok(value)
+    });
+    value.map_err(|error| deserializer.locate(error))
+}
+
+pub fn from_str<'de, T: de::Deserialize<'de>>(input: &'de str) -> Result<T> {
+    from_slice(input.as_bytes())
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(input: &'de [u8]) -> Self {
+        Deserializer {
+            stream: TokenStream::new(input),
+            peeked: None,
+            offset: 0,
+        }
+    }
+
+    /// Check that only whitespace remains.
+    pub fn end(&mut self) -> Result<()> {
+        match self.peek_token()? {
+            None => Ok(()),
+            Some(_) => Err(Error::new("trailing characters")),
+        }
+    }
+
+    /// Attach the position of the last token read, if the error doesn't have one.
+    fn locate(&self, mut error: Error) -> Error {
+        if error.position.is_none() {
+            error.position = Some(Position::from_offset(self.stream.source(), self.offset));
+        }
+        error
+    }
+
+    /// The next token which isn't a comma or colon. The validator has already
+    /// checked those are in the right places.
+    fn peek_token(&mut self) -> Result<Option<&StreamToken<'de>>> {
+        if self.peeked.is_none() {
+            for token in self.stream.by_ref() {
+                let token = token?;
+                self.offset = token.start;
+                match token.token {
+                    Token::Comma | Token::Colon => continue,
+                    _ => {
+                        self.peeked = Some(token);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn peek(&mut self) -> Result<&StreamToken<'de>> {
+        self.peek_token()?
+            .ok_or_else(|| Error::new("unexpected end of input"))
+    }
+
+    fn next(&mut self) -> Result<StreamToken<'de>> {
+        self.peek()?;
+        Ok(self.peeked.take().unwrap())
+    }
+
+    fn expect_close(&mut self, close: Token<'static>) -> Result<()> {
+        if self.next()?.token == close {
+            Ok(())
+        } else {
+            Err(Error::new(format!("expected {:?}", close)))
+        }
+    }
+}
+
+fn visit_number<'de, V: Visitor<'de>>(raw: &[u8], visitor: V) -> Result<V::Value> {
+    let raw = std::str::from_utf8(raw).map_err(Error::new)?;
+    if !raw.contains(&['.', 'e', 'E'][..]) {
+        if raw.starts_with('-') {
+            if let Ok(n) = raw.parse::<i64>() {
+                return visitor.visit_i64(n);
+            }
+        } else if let Ok(n) = raw.parse::<u64>() {
+            return visitor.visit_u64(n);
+        }
+    }
+    let n: f64 = raw.parse().map_err(Error::new)?;
+    if !n.is_finite() {
+        return Err(Error::new("number out of range"));
+    }
+    visitor.visit_f64(n)
+}
+
+fn visit_string<'de, V: Visitor<'de>>(raw: Cow<'de, [u8]>, visitor: V) -> Result<V::Value> {
+    match string::decode_cow(raw)? {
+        Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+        Cow::Owned(s) => visitor.visit_string(s),
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let token = self.next()?;
+        match token.token {
+            Token::Null => visitor.visit_unit(),
+            Token::True => visitor.visit_bool(true),
+            Token::False => visitor.visit_bool(false),
+            Token::Number(raw) => visit_number(&raw, visitor),
+            Token::String(raw) => visit_string(raw, visitor),
+            Token::ArrayOpen => {
+                let value = visitor.visit_seq(Seq { de: &mut *self })?;
+                self.expect_close(Token::ArrayClose)?;
+                Ok(value)
+            }
+            Token::ObjectOpen => {
+                let value = visitor.visit_map(Map { de: &mut *self })?;
+                self.expect_close(Token::ObjectClose)?;
+                Ok(value)
+            }
+            token => Err(Error::new(format!("unexpected {:?}", token))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.peek()?.token == Token::Null {
+            self.next()?;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    /// `RawValue` asks for the source text of the next value.
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        if name == raw::TOKEN {
+            let token = self.next()?;
+            let end = self.stream.skip_value(&token)?;
+            let json = &self.stream.source()[token.start..end];
+            return visitor.visit_borrowed_str(std::str::from_utf8(json).map_err(Error::new)?);
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Skip the value without decoding strings or numbers, or building anything.
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let token = self.next()?;
+        self.stream.skip_value(&token)?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.peek()?.token {
+            // A unit variant.
+            Token::String(_) => visitor.visit_enum(Variant {
+                de: self,
+                wrapped: false,
+            }),
+            // `{"Variant": value}`
+            Token::ObjectOpen => {
+                self.next()?;
+                let value = visitor.visit_enum(Variant {
+                    de: &mut *self,
+                    wrapped: true,
+                })?;
+                self.expect_close(Token::ObjectClose)?;
+                Ok(value)
+            }
+            _ => Err(Error::new("expected a string or object for an enum")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct Seq<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Seq<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.de.peek()?.token == Token::ArrayClose {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+struct Map<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for Map<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.de.peek()?.token == Token::ObjectClose {
+            return Ok(None);
+        }
+        seed.deserialize(Key { de: &mut *self.de }).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// Deserializes an object key. Keys are always strings, but they can be
+/// parsed as numbers for maps with integer keys.
+struct Key<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'a, 'de> Key<'a, 'de> {
+    fn next_key(self) -> Result<Cow<'de, [u8]>> {
+        match self.de.next()?.token {
+            Token::String(raw) => Ok(raw),
+            token => Err(Error::new(format!("expected a key, found {:?}", token))),
+        }
+    }
+}
+
+macro_rules! deserialize_integer_key {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                let key = string::decode_cow(self.next_key()?)?;
+                visitor.$visit(key.parse().map_err(Error::new)?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Key<'a, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visit_string(self.next_key()?, visitor)
+    }
+
+    deserialize_integer_key! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct Variant<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    /// True for `{"Variant": value}`, false for a bare `"Variant"`.
+    wrapped: bool,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant = seed.deserialize(Key { de: &mut *self.de })?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for Variant<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        if self.wrapped {
+            de::Deserialize::deserialize(self.de)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        if self.wrapped {
+            seed.deserialize(self.de)
+        } else {
+            Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"newtype variant",
+            ))
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        if self.wrapped {
+            de::Deserializer::deserialize_seq(self.de, visitor)
+        } else {
+            Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"tuple variant",
+            ))
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if self.wrapped {
+            de::Deserializer::deserialize_map(self.de, visitor)
+        } else {
+            Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"struct variant",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Link<'a> {
+        url: &'a str,
+        title: String,
+        tags: Vec<String>,
+        #[serde(default)]
+        rank: Option<u32>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Point(i32, i32),
+        Rect { w: u8, h: u8 },
+    }
+
+    #[test]
+    fn structs_borrow_strings() {
+        let input = br#"{"url": "http://a", "ignored": [1, {"x": "\n"}], "title": "a\tb", "tags": [], "rank": null}"#;
+        let link: Link = from_slice(input).unwrap();
+        assert_eq!(
+            link,
+            Link {
+                url: "http://a",
+                title: "a\tb".into(),
+                tags: vec![],
+                rank: None,
+            }
+        );
+    }
+
+    #[test]
+    fn escaped_borrowed_str_fails() {
+        let input = br#"{"url": "a\/b", "title": "", "tags": []}"#;
+        assert!(from_slice::<Link>(input).is_err());
+    }
+
+    #[test]
+    fn enums() {
+        let shapes: Vec<Shape> = from_str(
+            r#"["Empty", {"Circle": 1.5}, {"Point": [-1, 2]}, {"Rect": {"w": 3, "h": 4}}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            shapes,
+            vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Point(-1, 2),
+                Shape::Rect { w: 3, h: 4 }
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_and_keys() {
+        let map: BTreeMap<u32, i64> = from_str(r#"{"1": -5, "20": 9007199254740993}"#).unwrap();
+        assert_eq!(map[&20], 9_007_199_254_740_993);
+        let big: f64 = from_str("18446744073709551616").unwrap();
+        assert_eq!(big, 18_446_744_073_709_551_616.0);
+        assert_eq!(
+            from_str::<f64>("1e400").unwrap_err().message(),
+            "number out of range"
+        );
+        assert!(from_str::<Vec<f64>>("[-1e400]").is_err());
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let error = from_str::<Vec<u8>>("[1,\n 2,\n 300]").unwrap_err();
+        let position = error.position().unwrap();
+        assert_eq!((position.line, position.column), (3, 2));
+        assert!(error.to_string().ends_with("at line 3 column 2"));
+
+        let error = from_str::<u8>("1 2").unwrap_err();
+        assert_eq!(error.message(), "trailing characters");
+        assert_eq!(error.position().map(|p| p.offset), Some(2));
+
+        assert!(from_str::<Vec<u8>>("[1, 2").is_err());
+        let error = from_str::<String>(r#""\ud800""#).unwrap_err();
+        assert_eq!(error.message(), "lone surrogate '\\ud800'");
+    }
+}
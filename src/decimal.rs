@@ -0,0 +1,564 @@
+//! Exact decimal numbers.
+//!
+//! `Decimal` holds a JSON number as a sign, an arbitrarily large integer
+//! coefficient and a power of ten, so numbers like
+//! `12345678901234567890.123456789` which `f64` would round keep every digit.
+//! It supports comparison and the arithmetic needed to aggregate values, and
+//! its `Display` writes JSON text with the same value as the input.
+//!
+//! Aligning two decimals multiplies the coefficient of the one with the larger
+//! exponent, so adding `1e-1000000` to `1e1000000` needs two million digits.
+//! The `checked_*` methods return `None` rather than align beyond
+//! `MAX_DIGITS` or leave the range of an `i64` exponent, and the operators
+//! panic in those cases, like integer overflow.
+
+use crate::jcs;
+use crate::tokenizer::Token;
+use crate::value::{InvalidNumber, Number};
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// The most digits an aligned coefficient may have.
+pub const MAX_DIGITS: u64 = 100_000_000;
+
+/// Each limb holds nine decimal digits.
+const BASE: u64 = 1_000_000_000;
+
+/// An unsigned integer as base 10^9 limbs, least significant first, with no
+/// zero limbs at the end.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn from_digits(digits: &str) -> BigUint {
+        let bytes = digits.as_bytes();
+        let mut limbs = Vec::with_capacity(bytes.len() / 9 + 1);
+        let mut end = bytes.len();
+        while end > 0 {
+            let start = end.saturating_sub(9);
+            let limb = bytes[start..end]
+                .iter()
+                .fold(0, |n, &c| n * 10 + u32::from(c - b'0'));
+            limbs.push(limb);
+            end = start;
+        }
+        BigUint(limbs).trimmed()
+    }
+
+    fn trimmed(mut self) -> BigUint {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    #[inline]
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn add(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let sum = u64::from(*self.0.get(i).unwrap_or(&0))
+                + u64::from(*other.0.get(i).unwrap_or(&0))
+                + carry;
+            limbs.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        BigUint(limbs)
+    }
+
+    /// `self - other`, which must not be negative.
+    fn sub(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for (i, &limb) in self.0.iter().enumerate() {
+            let subtrahend = i64::from(*other.0.get(i).unwrap_or(&0)) + borrow;
+            let mut difference = i64::from(limb) - subtrahend;
+            borrow = 0;
+            if difference < 0 {
+                difference += BASE as i64;
+                borrow = 1;
+            }
+            limbs.push(difference as u32);
+        }
+        debug_assert_eq!(borrow, 0);
+        BigUint(limbs).trimmed()
+    }
+
+    fn mul(&self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::default();
+        }
+        let mut limbs = vec![0u64; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.0.iter().enumerate() {
+                let n = limbs[i + j] + u64::from(a) * u64::from(b) + carry;
+                limbs[i + j] = n % BASE;
+                carry = n / BASE;
+            }
+            limbs[i + other.0.len()] += carry;
+        }
+        BigUint(limbs.into_iter().map(|n| n as u32).collect()).trimmed()
+    }
+
+    fn mul_pow10(&self, exponent: u64) -> BigUint {
+        if self.is_zero() {
+            return BigUint::default();
+        }
+        let small = BigUint(vec![10u32.pow((exponent % 9) as u32)]);
+        let mut scaled = self.mul(&small);
+        let shift = (exponent / 9) as usize;
+        scaled.0.splice(0..0, vec![0; shift]);
+        scaled
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.split_last() {
+            None => f.write_str("0"),
+            Some((most, rest)) => {
+                write!(f, "{}", most)?;
+                for limb in rest.iter().rev() {
+                    write!(f, "{:09}", limb)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An exact decimal: `coefficient * 10^exponent`.
+///
+/// Values are always normalized, with no trailing zeros in the coefficient
+/// and no negative zero, so `1.50`, `15e-1` and `0.15e1` are the same
+/// `Decimal`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Decimal {
+    negative: bool,
+    coefficient: BigUint,
+    exponent: i64,
+}
+
+impl Decimal {
+    /// `None` if the normalized exponent is out of range.
+    fn new(negative: bool, coefficient: BigUint, exponent: i64) -> Option<Decimal> {
+        let mut decimal = Decimal {
+            negative,
+            coefficient,
+            exponent,
+        };
+        decimal.normalize()?;
+        Some(decimal)
+    }
+
+    fn normalize(&mut self) -> Option<()> {
+        if self.coefficient.is_zero() {
+            *self = Decimal::default();
+            return Some(());
+        }
+        let digits = self.coefficient.to_string();
+        let significant = digits.trim_end_matches('0').len();
+        let trailing_zeros = digits.len() - significant;
+        if trailing_zeros > 0 {
+            self.coefficient = BigUint::from_digits(&digits[..significant]);
+            self.exponent = self.exponent.checked_add(trailing_zeros as i64)?;
+        }
+        Some(())
+    }
+
+    /// The position of the most significant digit: the value is
+    /// `0.coefficient * 10^adjusted_exponent`.
+    fn adjusted_exponent(&self, digits: usize) -> i128 {
+        i128::from(self.exponent) + digits as i128
+    }
+
+    /// The value of a `Token::Number`, or `None` for other tokens.
+    pub fn from_token(token: &Token<'_>) -> Option<Decimal> {
+        let raw = token.as_number()?;
+        std::str::from_utf8(raw).ok()?.parse().ok()
+    }
+
+    #[inline]
+    pub fn is_zero(&self) -> bool {
+        self.coefficient.is_zero()
+    }
+
+    #[inline]
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    #[inline]
+    pub fn is_integer(&self) -> bool {
+        self.exponent >= 0
+    }
+
+    /// The power of ten the coefficient is multiplied by.
+    #[inline]
+    pub fn exponent(&self) -> i64 {
+        self.exponent
+    }
+
+    /// The decimal digits of the coefficient, with no trailing zeros.
+    pub fn coefficient(&self) -> String {
+        self.coefficient.to_string()
+    }
+
+    pub fn abs(&self) -> Decimal {
+        Decimal {
+            negative: false,
+            ..self.clone()
+        }
+    }
+
+    /// Both coefficients scaled to the smaller exponent, or `None` if that
+    /// would need more than `MAX_DIGITS` digits.
+    fn aligned(&self, other: &Decimal) -> Option<(BigUint, BigUint, i64)> {
+        let exponent = self.exponent.min(other.exponent);
+        let scale = |d: &Decimal| {
+            if d.is_zero() {
+                return Some(BigUint::default());
+            }
+            let shift = (i128::from(d.exponent) - i128::from(exponent)) as u64;
+            let digits = d.coefficient.to_string().len() as u64;
+            if shift.checked_add(digits)? > MAX_DIGITS {
+                return None;
+            }
+            Some(d.coefficient.mul_pow10(shift))
+        };
+        Some((scale(self)?, scale(other)?, exponent))
+    }
+
+    /// `self + other`, or `None` if the result can't be computed within
+    /// `MAX_DIGITS` or its exponent is out of range.
+    pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return Some(self.clone());
+        }
+        if self.is_zero() {
+            return Some(other.clone());
+        }
+        let (a, b, exponent) = self.aligned(other)?;
+        if self.negative == other.negative {
+            return Decimal::new(self.negative, a.add(&b), exponent);
+        }
+        match a.cmp(&b) {
+            Ordering::Less => Decimal::new(other.negative, b.sub(&a), exponent),
+            _ => Decimal::new(self.negative, a.sub(&b), exponent),
+        }
+    }
+
+    /// `self - other`, with the same limits as `checked_add`.
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Decimal> {
+        self.checked_add(&-other)
+    }
+
+    /// `self * other`, or `None` if the exponent is out of range.
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+        Decimal::new(
+            self.negative != other.negative,
+            self.coefficient.mul(&other.coefficient),
+            self.exponent.checked_add(other.exponent)?,
+        )
+    }
+
+    pub fn to_number(&self) -> Number {
+        self.to_string()
+            .parse()
+            .expect("Decimal formats as a valid number")
+    }
+
+    /// The closest `f64`, which may lose precision.
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = InvalidNumber;
+
+    /// Parse JSON number text.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Number>()?;
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (mantissa, exponent) = match unsigned.find(&['e', 'E'][..]) {
+            // Checked after the coefficient, since zero takes any exponent.
+            Some(i) => (
+                &unsigned[..i],
+                unsigned[i + 1..].parse::<i64>().map_err(|_| InvalidNumber),
+            ),
+            None => (unsigned, Ok(0)),
+        };
+        let (int, frac) = match mantissa.find('.') {
+            Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+            None => (mantissa, ""),
+        };
+        let digits: String = int.chars().chain(frac.chars()).collect();
+        let coefficient = BigUint::from_digits(&digits);
+        if coefficient.is_zero() {
+            return Ok(Decimal::default());
+        }
+        let exponent = exponent?
+            .checked_sub(frac.len() as i64)
+            .ok_or(InvalidNumber)?;
+        Decimal::new(negative, coefficient, exponent).ok_or(InvalidNumber)
+    }
+}
+
+impl fmt::Display for Decimal {
+    /// Positional notation for moderate exponents and exponential notation
+    /// otherwise, following ECMAScript (see `jcs::format_number`).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+        let digits = self.coefficient.to_string();
+        let exponent = self.adjusted_exponent(digits.len());
+        f.write_str(&jcs::layout(self.negative, &digits, exponent))
+    }
+}
+
+impl Ord for Decimal {
+    /// Compares the sign, then the position of the most significant digit,
+    /// then the digits, so numbers are never scaled.
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let sign = |d: &Decimal| match (d.is_zero(), d.negative) {
+            (true, _) => 0,
+            (false, false) => 1,
+            (false, true) => -1,
+        };
+        let ordering = sign(self).cmp(&sign(other));
+        if ordering != Ordering::Equal || self.is_zero() {
+            return ordering;
+        }
+        let (a, b) = (self.coefficient.to_string(), other.coefficient.to_string());
+        // Without trailing zeros, a shorter digit string which is a prefix of
+        // the other is the smaller number.
+        let magnitude = self
+            .adjusted_exponent(a.len())
+            .cmp(&other.adjusted_exponent(b.len()))
+            .then_with(|| a.cmp(&b));
+        if self.negative {
+            magnitude.reverse()
+        } else {
+            magnitude
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Add<&'a Decimal> for &'a Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: &Decimal) -> Decimal {
+        self.checked_add(other)
+            .expect("Decimal addition overflowed")
+    }
+}
+
+impl<'a> Sub<&'a Decimal> for &'a Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: &Decimal) -> Decimal {
+        self.checked_sub(other)
+            .expect("Decimal subtraction overflowed")
+    }
+}
+
+impl<'a> Mul<&'a Decimal> for &'a Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: &Decimal) -> Decimal {
+        self.checked_mul(other)
+            .expect("Decimal multiplication overflowed")
+    }
+}
+
+impl Neg for &Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal {
+            negative: !self.negative && !self.is_zero(),
+            ..self.clone()
+        }
+    }
+}
+
+macro_rules! by_value_ops {
+    ($($trait:ident $method:ident),*) => {
+        $(
+            impl $trait for Decimal {
+                type Output = Decimal;
+
+                fn $method(self, other: Decimal) -> Decimal {
+                    (&self).$method(&other)
+                }
+            }
+        )*
+    };
+}
+
+by_value_ops!(Add add, Sub sub, Mul mul);
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        -&self
+    }
+}
+
+impl<'a> Sum<&'a Decimal> for Decimal {
+    fn sum<I: Iterator<Item = &'a Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::default(), |sum, n| &sum + n)
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::default(), |sum, n| &sum + &n)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(n: i64) -> Decimal {
+        Decimal::new(
+            n < 0,
+            BigUint::from_digits(&n.unsigned_abs().to_string()),
+            0,
+        )
+        .expect("an integer's exponent is in range")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_exactly() {
+        let n = d("12345678901234567890.123456789");
+        assert_eq!(n.coefficient(), "12345678901234567890123456789");
+        assert_eq!(n.exponent(), -9);
+        assert_eq!(n.to_string(), "12345678901234567890.123456789");
+        assert_eq!(d("-1.2300e+2"), d("-123"));
+        assert_eq!(d("1.0E+02").to_string(), "100");
+        assert_eq!(d("-0.0"), Decimal::default());
+        assert_eq!(d("1e-7").to_string(), "1e-7");
+        assert_eq!(d("123e30").to_string(), "1.23e+32");
+        assert!("1.".parse::<Decimal>().is_err());
+        assert!("abc".parse::<Decimal>().is_err());
+        assert_eq!(
+            Decimal::from_token(&Token::Number(b"2.50"[..].into())),
+            Some(d("2.5"))
+        );
+    }
+
+    #[test]
+    fn compares() {
+        assert!(d("0.1") < d("0.10000000000000000001"));
+        assert!(d("-2") < d("-1.5"));
+        assert!(d("-1") < d("0"));
+        assert!(d("1e3") > d("999.999999999999999999"));
+        assert_eq!(d("1.5").cmp(&d("15e-1")), Ordering::Equal);
+        let mut numbers = vec![d("3"), d("-1e10"), d("0.5"), d("2.25")];
+        numbers.sort();
+        assert_eq!(numbers, vec![d("-1e10"), d("0.5"), d("2.25"), d("3")]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(d("0.1") + d("0.2"), d("0.3"));
+        assert_eq!(d("1") - d("1.000000001"), d("-0.000000001"));
+        assert_eq!(d("-2.5") + d("2.5"), Decimal::default());
+        assert_eq!(d("1.5") * d("-2"), d("-3"));
+        assert_eq!(
+            d("99999999999999999999") * d("99999999999999999999"),
+            d("9999999999999999999800000000000000000001")
+        );
+        assert_eq!(-d("4"), Decimal::from(-4));
+        let prices = [
+            "12345678901234567890.123456789",
+            "0.000000001",
+            "-0.123456790",
+        ];
+        let total: Decimal = prices.iter().map(|s| d(s)).sum();
+        assert_eq!(total.to_string(), "12345678901234567890");
+        assert_eq!(total.to_number().as_str(), "12345678901234567890");
+    }
+
+    #[test]
+    fn extreme_exponents() {
+        let max = d("1e9223372036854775807");
+        let min = d("1e-9223372036854775808");
+        assert_eq!(max.to_string(), "1e+9223372036854775807");
+        assert_eq!(min.to_string(), "1e-9223372036854775808");
+        assert_eq!(d("0e99999999999999999999"), Decimal::default());
+        assert!("10e9223372036854775807".parse::<Decimal>().is_err());
+        assert_eq!(
+            d("12e9223372036854775806").to_string(),
+            "1.2e+9223372036854775807"
+        );
+        assert!("1e99999999999999999999".parse::<Decimal>().is_err());
+
+        assert!(max > min);
+        assert!(min > Decimal::default());
+        assert!(-&max < d("-1"));
+        assert!(d("9e-9223372036854775808") < d("8.99e-9223372036854775806"));
+        assert_eq!(d("12e5").cmp(&d("1.2e6")), Ordering::Equal);
+
+        assert_eq!(max.checked_mul(&min), Some(d("0.1")));
+        assert_eq!(max.checked_mul(&d("10")), None);
+        assert_eq!(min.checked_mul(&d("0.1")), None);
+        assert_eq!(max.checked_add(&d("1")), None);
+        assert_eq!(max.checked_sub(&Decimal::default()), Some(max.clone()));
+        assert_eq!(
+            d("1e100")
+                .checked_add(&d("1e-100"))
+                .unwrap()
+                .coefficient()
+                .len(),
+            201
+        );
+    }
+}
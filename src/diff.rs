@@ -0,0 +1,769 @@
+//! Structural diffs between two documents.
+//!
+//! `diff` reports every value which was added, removed or changed, by path,
+//! along with a `Patch` which turns the first document into the second.
+//! Values are compared with `Value::equivalent`, so `1.0` and `1` are the
+//! same and object members can be in any order.
+//!
+//! The paths of changes refer to the documents being compared: the first
+//! one for removals and the second for additions and changes. The patch's
+//! paths instead refer to the document as it is partway through patching,
+//! so array indices can differ between the two.
+//!
+//! `ArrayStrategy::Lcs` needs a table with a cell per pair of elements, so
+//! arrays which would need more than `MAX_LCS_CELLS` (after their common
+//! start and end are skipped) are compared by index instead.
+
+use crate::patch::{Operation, Patch};
+use crate::string;
+use crate::value::Value;
+use crate::{JsonPath, JsonPathSegment};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io;
+use std::ops::Range;
+
+/// The largest table `ArrayStrategy::Lcs` builds.
+pub const MAX_LCS_CELLS: usize = 1 << 24;
+
+/// How elements of two arrays are matched up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArrayStrategy {
+    /// Compare elements at the same index. Inserting an element near the
+    /// start shows up as a change to every element after it.
+    Index,
+    /// Find the longest common subsequence of equal elements, and compare
+    /// the elements between them by index.
+    Lcs,
+    /// Match objects which have the same value for this member, such as
+    /// `id`, wherever they are in the arrays, and report anything else as
+    /// removed or added. Elements without the member match equivalent elements.
+    /// The patch moves as few elements as it can.
+    Key(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffOptions {
+    pub arrays: ArrayStrategy,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            arrays: ArrayStrategy::Index,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added {
+        path: JsonPath<'static>,
+        value: Value,
+    },
+    Removed {
+        path: JsonPath<'static>,
+        value: Value,
+    },
+    /// A scalar changed, or a value changed type.
+    Changed {
+        path: JsonPath<'static>,
+        from: Value,
+        to: Value,
+    },
+    /// An array element matched by `ArrayStrategy::Key` moved from this path
+    /// in the first document to `path` in the second.
+    Moved {
+        from: JsonPath<'static>,
+        path: JsonPath<'static>,
+    },
+}
+
+impl Change {
+    pub fn path(&self) -> &JsonPath<'static> {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. }
+            | Change::Moved { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    /// One line in the style of a unified diff: `+ @.a: 1`, `- @.a: 1`,
+    /// `~ @.a: 1 -> 2` or `> @.0 -> @.2`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added { path, value } => write!(f, "+ {}: {}", path, value),
+            Change::Removed { path, value } => write!(f, "- {}: {}", path, value),
+            Change::Changed { path, from, to } => write!(f, "~ {}: {} -> {}", path, from, to),
+            Change::Moved { from, path } => write!(f, "> {} -> {}", from, path),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+    /// An RFC 6902 patch which turns the first document into the second.
+    pub patch: Patch,
+}
+
+impl Diff {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Write one line per change, colored green, red, yellow or cyan for
+    /// added, removed, changed or moved values if `color` is set. Pass
+    /// `Palette::from_env().is_some()` to respect `NO_COLOR`.
+    pub fn write_report<W: io::Write>(&self, mut out: W, color: bool) -> io::Result<()> {
+        for change in &self.changes {
+            if !color {
+                writeln!(out, "{}", change)?;
+                continue;
+            }
+            let code = match change {
+                Change::Added { .. } => "32",
+                Change::Removed { .. } => "31",
+                Change::Changed { .. } => "33",
+                Change::Moved { .. } => "36",
+            };
+            writeln!(out, "\x1b[{}m{}\x1b[0m", code, change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two documents.
+pub fn diff(from: &Value, to: &Value, options: &DiffOptions) -> Diff {
+    let mut differ = Differ {
+        options,
+        path: Vec::new(),
+        patch_path: Vec::new(),
+        diff: Diff::default(),
+    };
+    differ.compare(from, to);
+    differ.diff
+}
+
+enum Step {
+    /// Compare these elements of the first and second arrays.
+    Pair(usize, usize),
+    Remove(usize),
+    Add(usize),
+}
+
+struct Differ<'o> {
+    options: &'o DiffOptions,
+    /// The path in the documents being compared.
+    path: Vec<JsonPathSegment<'static>>,
+    /// The path in the document being patched.
+    patch_path: Vec<JsonPathSegment<'static>>,
+    diff: Diff,
+}
+
+fn to_path(segments: &[JsonPathSegment<'static>]) -> JsonPath<'static> {
+    JsonPath::new(Cow::Owned(segments.to_vec()))
+}
+
+impl<'o> Differ<'o> {
+    fn enter(
+        &mut self,
+        segment: JsonPathSegment<'static>,
+        patch_segment: JsonPathSegment<'static>,
+    ) {
+        self.path.push(segment);
+        self.patch_path.push(patch_segment);
+    }
+
+    fn leave(&mut self) {
+        self.path.pop();
+        self.patch_path.pop();
+    }
+
+    fn compare(&mut self, from: &Value, to: &Value) {
+        match (from, to) {
+            (Value::Object(from), Value::Object(to)) => {
+                for (key, old) in from.iter() {
+                    let segment = JsonPathSegment::Key(Cow::Owned(key.clone()));
+                    self.enter(segment.clone(), segment);
+                    match to.get(key) {
+                        Some(new) => self.compare(old, new),
+                        None => self.removed(old),
+                    }
+                    self.leave();
+                }
+                for (key, new) in to.iter() {
+                    if !from.contains_key(key) {
+                        let segment = JsonPathSegment::Key(Cow::Owned(key.clone()));
+                        self.enter(segment.clone(), segment);
+                        self.added(new);
+                        self.leave();
+                    }
+                }
+            }
+            (Value::Array(from), Value::Array(to)) => self.compare_arrays(from, to),
+            (from, to) if from.equivalent(to) => (),
+            (from, to) => {
+                self.diff.changes.push(Change::Changed {
+                    path: to_path(&self.path),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+                self.diff.patch.0.push(Operation::Replace {
+                    path: to_path(&self.patch_path),
+                    value: to.clone(),
+                });
+            }
+        }
+    }
+
+    fn added(&mut self, value: &Value) {
+        self.diff.changes.push(Change::Added {
+            path: to_path(&self.path),
+            value: value.clone(),
+        });
+        self.diff.patch.0.push(Operation::Add {
+            path: to_path(&self.patch_path),
+            value: value.clone(),
+        });
+    }
+
+    fn removed(&mut self, value: &Value) {
+        self.diff.changes.push(Change::Removed {
+            path: to_path(&self.path),
+            value: value.clone(),
+        });
+        self.diff.patch.0.push(Operation::Remove {
+            path: to_path(&self.patch_path),
+        });
+    }
+
+    fn compare_arrays(&mut self, from: &[Value], to: &[Value]) {
+        let steps = match &self.options.arrays {
+            ArrayStrategy::Index => {
+                let mut steps = Vec::new();
+                gap(0..from.len(), 0..to.len(), &mut steps);
+                steps
+            }
+            ArrayStrategy::Lcs => align(from, to),
+            ArrayStrategy::Key(key) => return self.compare_by_key(from, to, key),
+        };
+        // The index in the array being patched, where the elements before it
+        // are already those of `to`.
+        let mut position = 0;
+        for step in steps {
+            match step {
+                Step::Pair(i, j) => {
+                    self.enter(JsonPathSegment::Index(j), JsonPathSegment::Index(position));
+                    self.compare(&from[i], &to[j]);
+                    position += 1;
+                }
+                Step::Remove(i) => {
+                    self.enter(JsonPathSegment::Index(i), JsonPathSegment::Index(position));
+                    self.removed(&from[i]);
+                }
+                Step::Add(j) => {
+                    self.enter(JsonPathSegment::Index(j), JsonPathSegment::Index(position));
+                    self.added(&to[j]);
+                    position += 1;
+                }
+            }
+            self.leave();
+        }
+    }
+
+    /// Match elements by `key`. Unmatched elements of `from` are removed
+    /// first. Then each element of `to` is added, or moved, after the one
+    /// before it, except for the longest run of matched elements which are
+    /// already in order, which stay where they are.
+    fn compare_by_key(&mut self, from: &[Value], to: &[Value], key: &str) {
+        let mut unmatched: HashMap<(bool, String), Vec<usize>> = HashMap::new();
+        for (i, value) in from.iter().enumerate().rev() {
+            unmatched.entry(identity(value, key)).or_default().push(i);
+        }
+        // The element of `from` which each element of `to` matches.
+        let sources: Vec<Option<usize>> = to
+            .iter()
+            .map(|value| unmatched.get_mut(&identity(value, key)).and_then(Vec::pop))
+            .collect();
+        let matched: Vec<usize> = sources.iter().flatten().copied().collect();
+        let mut is_matched = vec![false; from.len()];
+        let mut stays = vec![false; from.len()];
+        for (&i, keep) in matched.iter().zip(increasing_subsequence(&matched)) {
+            is_matched[i] = true;
+            stays[i] = keep;
+        }
+        let stays_at = |j: usize| sources[j].filter(|&i| stays[i]);
+
+        // Every place an element is in while patching, in array order: each
+        // element of `from` where it starts, followed by the elements of `to`
+        // which are added or moved after it, one after another.
+        let mut to_index = vec![None; from.len()];
+        for (j, source) in sources.iter().enumerate() {
+            if let Some(i) = *source {
+                to_index[i] = Some(j);
+            }
+        }
+        let mut end = vec![0; to.len()];
+        // Give places to the elements of `to` from `j` up to the next one
+        // which stays.
+        let mut run_from = |mut j: usize, count: &mut usize| {
+            while j < to.len() && stays_at(j).is_none() {
+                end[j] = *count;
+                *count += 1;
+                j += 1;
+            }
+        };
+        let mut start = vec![0; from.len()];
+        let mut count = 0;
+        run_from(0, &mut count);
+        for i in 0..from.len() {
+            start[i] = count;
+            count += 1;
+            if let (true, Some(j)) = (stays[i], to_index[i]) {
+                run_from(j + 1, &mut count);
+            }
+        }
+        for (j, end) in end.iter_mut().enumerate() {
+            if let Some(i) = stays_at(j) {
+                *end = start[i];
+            }
+        }
+
+        let mut places = Places::new(count);
+        for &place in &start {
+            places.fill(place);
+        }
+        for i in (0..from.len()).filter(|&i| !is_matched[i]) {
+            let position = places.before(start[i]);
+            self.enter(JsonPathSegment::Index(i), JsonPathSegment::Index(position));
+            self.removed(&from[i]);
+            self.leave();
+            places.clear(start[i]);
+        }
+        for (j, value) in to.iter().enumerate() {
+            let old = sources[j].map(|i| places.before(start[i]));
+            if stays_at(j).is_none() {
+                if let Some(i) = sources[j] {
+                    places.clear(start[i]);
+                }
+                places.fill(end[j]);
+            }
+            let position = places.before(end[j]);
+            match sources[j] {
+                Some(i) => {
+                    if let Some(old) = old.filter(|&old| old != position) {
+                        self.moved(i, j, old, position);
+                    }
+                    self.enter(JsonPathSegment::Index(j), JsonPathSegment::Index(position));
+                    self.compare(&from[i], value);
+                }
+                None => {
+                    self.enter(JsonPathSegment::Index(j), JsonPathSegment::Index(position));
+                    self.added(value);
+                }
+            }
+            self.leave();
+        }
+    }
+
+    /// Record that element `i` of the first array is element `j` of the
+    /// second, moving it from `old` to `new` in the array being patched.
+    fn moved(&mut self, i: usize, j: usize, old: usize, new: usize) {
+        let child = |parent: &[JsonPathSegment<'static>], index| {
+            let mut path = parent.to_vec();
+            path.push(JsonPathSegment::Index(index));
+            JsonPath::new(Cow::Owned(path))
+        };
+        self.diff.changes.push(Change::Moved {
+            from: child(&self.path, i),
+            path: child(&self.path, j),
+        });
+        self.diff.patch.0.push(Operation::Move {
+            from: child(&self.patch_path, old),
+            path: child(&self.patch_path, new),
+        });
+    }
+}
+
+/// Which places in the array being patched by `compare_by_key` hold an
+/// element, as a Fenwick tree so that an element's index is found in
+/// logarithmic time.
+struct Places {
+    tree: Vec<usize>,
+}
+
+impl Places {
+    fn new(len: usize) -> Self {
+        Places {
+            tree: vec![0; len + 1],
+        }
+    }
+
+    fn fill(&mut self, place: usize) {
+        let mut k = place + 1;
+        while k < self.tree.len() {
+            self.tree[k] += 1;
+            k += k & k.wrapping_neg();
+        }
+    }
+
+    fn clear(&mut self, place: usize) {
+        let mut k = place + 1;
+        while k < self.tree.len() {
+            self.tree[k] -= 1;
+            k += k & k.wrapping_neg();
+        }
+    }
+
+    /// The number of places before `place` which hold an element.
+    fn before(&self, place: usize) -> usize {
+        let (mut k, mut n) = (place, 0);
+        while k > 0 {
+            n += self.tree[k];
+            k &= k - 1;
+        }
+        n
+    }
+}
+
+/// What `ArrayStrategy::Key` matches an element by: its key, or the whole
+/// element if it has none, written so that equivalent values are written
+/// the same.
+fn identity(value: &Value, key: &str) -> (bool, String) {
+    let mut out = String::new();
+    match member(value, key) {
+        Some(id) => {
+            canonical(id, &mut out);
+            (true, out)
+        }
+        None => {
+            canonical(value, &mut out);
+            (false, out)
+        }
+    }
+}
+
+/// Write `value` with normalized numbers and object members sorted by key,
+/// so that two values are written the same if they are equivalent.
+fn canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Number(n) => match n.to_decimal() {
+            Ok(d) => out.push_str(&d.to_string()),
+            Err(_) => out.push_str(n.as_str()),
+        },
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                canonical(value, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut members: Vec<_> = map.iter().collect();
+            members.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, value)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                string::write_escaped(out, key).expect("writing to a String can't fail");
+                out.push(':');
+                canonical(value, out);
+            }
+            out.push('}');
+        }
+        value => write!(out, "{}", value).expect("writing to a String can't fail"),
+    }
+}
+
+fn member<'v>(value: &'v Value, key: &str) -> Option<&'v Value> {
+    value.as_object().and_then(|map| map.get(key))
+}
+
+/// Flags the elements of the longest strictly increasing subsequence of
+/// `values`.
+fn increasing_subsequence(values: &[usize]) -> Vec<bool> {
+    // `tails[k]` is the position of the smallest value which ends an
+    // increasing subsequence of length `k + 1`.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+    for (position, &value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&tail| values[tail] < value);
+        previous[position] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(position);
+        } else {
+            tails[k] = position;
+        }
+    }
+    let mut flags = vec![false; values.len()];
+    let mut next = tails.last().copied();
+    while let Some(position) = next {
+        flags[position] = true;
+        next = previous[position];
+    }
+    flags
+}
+
+/// Match up the elements of two arrays along their longest common
+/// subsequence of equivalent elements, or by index if that would need a
+/// table of more than `MAX_LCS_CELLS`.
+fn align(from: &[Value], to: &[Value]) -> Vec<Step> {
+    let prefix = from
+        .iter()
+        .zip(to)
+        .take_while(|(a, b)| a.equivalent(b))
+        .count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(a, b)| a.equivalent(b))
+        .count();
+    let (n, m) = (from.len() - suffix, to.len() - suffix);
+    let mut steps: Vec<Step> = (0..prefix).map(|k| Step::Pair(k, k)).collect();
+    match (n - prefix + 1).checked_mul(m - prefix + 1) {
+        Some(cells) if cells <= MAX_LCS_CELLS => lcs(from, to, prefix..n, prefix..m, &mut steps),
+        _ => gap(prefix..n, prefix..m, &mut steps),
+    }
+    steps.extend((0..suffix).map(|k| Step::Pair(n + k, m + k)));
+    steps
+}
+
+/// Steps for `from[rows]` and `to[columns]` along their longest common
+/// subsequence, comparing the elements between by position.
+fn lcs(
+    from: &[Value],
+    to: &[Value],
+    rows: Range<usize>,
+    columns: Range<usize>,
+    steps: &mut Vec<Step>,
+) {
+    let (start_i, start_j) = (rows.start, columns.start);
+    let (n, m) = (rows.len(), columns.len());
+    let same = |i: usize, j: usize| from[start_i + i].equivalent(&to[start_j + j]);
+    // `lengths[i][j]` is the length of the LCS of the rows from `i` and the
+    // columns from `j`.
+    let mut lengths = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if same(i, j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    // Where the elements not in the subsequence so far start.
+    let (mut gap_i, mut gap_j) = (0, 0);
+    while i < n && j < m {
+        if same(i, j) && lengths[i][j] == lengths[i + 1][j + 1] + 1 {
+            gap(
+                start_i + gap_i..start_i + i,
+                start_j + gap_j..start_j + j,
+                steps,
+            );
+            steps.push(Step::Pair(start_i + i, start_j + j));
+            i += 1;
+            j += 1;
+            gap_i = i;
+            gap_j = j;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    gap(
+        start_i + gap_i..rows.end,
+        start_j + gap_j..columns.end,
+        steps,
+    );
+}
+
+/// Steps for elements which don't match anything: compared by position,
+/// with the remainder removed or added.
+fn gap(from: Range<usize>, to: Range<usize>, steps: &mut Vec<Step>) {
+    let paired = from.len().min(to.len());
+    steps.extend((0..paired).map(|k| Step::Pair(from.start + k, to.start + k)));
+    steps.extend((from.start + paired..from.end).map(Step::Remove));
+    steps.extend((to.start + paired..to.end).map(Step::Add));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(json: &str) -> Value {
+        Value::parse(json.as_bytes()).unwrap()
+    }
+
+    fn check(from: &str, to: &str, arrays: ArrayStrategy) -> Vec<String> {
+        let (from, to) = (value(from), value(to));
+        let diff = diff(&from, &to, &DiffOptions { arrays });
+        let mut patched = from.clone();
+        diff.patch.apply(&mut patched).unwrap();
+        assert!(patched.equivalent(&to), "{} gave {}", diff.patch, patched);
+        diff.changes.iter().map(Change::to_string).collect()
+    }
+
+    #[test]
+    fn objects() {
+        assert_eq!(
+            check(
+                r#"{"a": 1, "b": {"c": "x", "d": [true]}, "e": null}"#,
+                r#"{"b": {"d": {}, "c": "y"}, "a": 1.0, "f": 2}"#,
+                ArrayStrategy::Index
+            ),
+            vec![
+                r#"~ @.b.c: "x" -> "y""#,
+                "~ @.b.d: [true] -> {}",
+                "- @.e: null",
+                "+ @.f: 2",
+            ]
+        );
+        assert!(check("[1, 2.50]", "[1.0, 2.5]", ArrayStrategy::Index).is_empty());
+        assert_eq!(check("1", "[]", ArrayStrategy::Index), vec!["~ @: 1 -> []"]);
+    }
+
+    #[test]
+    fn array_strategies() {
+        let (from, to) = ("[1, 2, 3, 4]", "[1, 3, 4, 5]");
+        assert_eq!(
+            check(from, to, ArrayStrategy::Index),
+            vec!["~ @.1: 2 -> 3", "~ @.2: 3 -> 4", "~ @.3: 4 -> 5"]
+        );
+        assert_eq!(
+            check(from, to, ArrayStrategy::Lcs),
+            vec!["- @.1: 2", "+ @.3: 5"]
+        );
+        assert_eq!(
+            check(
+                r#"[[1, 2, 3], "x"]"#,
+                r#"[[1, 2, 4, 3], "x", "y"]"#,
+                ArrayStrategy::Lcs
+            ),
+            vec!["+ @.0.2: 4", "+ @.2: \"y\""]
+        );
+        assert_eq!(
+            check(
+                r#"[{"id": 1, "v": "a"}, {"id": 2, "v": "b"}]"#,
+                r#"[{"id": 2, "v": "B"}, {"id": 3}]"#,
+                ArrayStrategy::Key("id".into())
+            ),
+            vec![
+                r#"- @.0: {"id":1,"v":"a"}"#,
+                r#"~ @.0.v: "b" -> "B""#,
+                r#"+ @.1: {"id":3}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_match_anywhere() {
+        assert_eq!(
+            check(
+                r#"[{"id": 1, "v": "a"}, {"id": 2}, {"id": 3}]"#,
+                r#"[{"id": 3}, {"id": 1, "v": "b"}, {"id": 2}]"#,
+                ArrayStrategy::Key("id".into())
+            ),
+            vec!["> @.2 -> @.0", r#"~ @.1.v: "a" -> "b""#]
+        );
+        assert_eq!(
+            check(
+                r#"[1, "x"]"#,
+                r#"["x", 1.0]"#,
+                ArrayStrategy::Key("id".into())
+            ),
+            vec!["> @.1 -> @.0"]
+        );
+        assert_eq!(
+            check(
+                r#"[{"id": 1}, {"id": 2}, {"id": 3}, {"id": 4}]"#,
+                r#"[{"id": 2}, {"id": 4}, {"id": 5}, {"id": 1}, {"id": 3}]"#,
+                ArrayStrategy::Key("id".into())
+            )
+            .len(),
+            3
+        );
+        // Equivalent elements without the key match whatever their member
+        // order or number formatting.
+        assert!(check(
+            r#"[{"a": 1.0, "b": [2e0]}, {"c": 3}]"#,
+            r#"[{"c": 3}, {"b": [2], "a": 1}]"#,
+            ArrayStrategy::Key("id".into())
+        )
+        .iter()
+        .all(|change| change.starts_with('>')));
+    }
+
+    #[test]
+    fn large_arrays() {
+        let numbers = |range: Range<usize>| {
+            let numbers: Vec<String> = range.map(|n| n.to_string()).collect();
+            format!("[{}]", numbers.join(","))
+        };
+        // The common start and end are skipped before building the table.
+        let inserted = numbers(0..5000).replacen(",2500,", ",2500,-1,", 1);
+        assert_eq!(
+            check(&numbers(0..5000), &inserted, ArrayStrategy::Lcs),
+            vec!["+ @.2501: -1"]
+        );
+        // Too large a table compares by index.
+        let shifted = numbers(1..5001);
+        assert_eq!(
+            check(&numbers(0..5000), &shifted, ArrayStrategy::Lcs).len(),
+            5000
+        );
+        // Matching by key doesn't search the array for each element.
+        let reversed = |range: Range<usize>| {
+            let numbers: Vec<String> = range.rev().map(|n| n.to_string()).collect();
+            format!("[{}]", numbers.join(","))
+        };
+        let key = ArrayStrategy::Key("id".into());
+        assert_eq!(
+            check(&numbers(0..200), &reversed(0..200), key.clone()).len(),
+            199
+        );
+        let options = DiffOptions { arrays: key };
+        let diff = diff(
+            &value(&numbers(0..40_000)),
+            &value(&reversed(0..40_000)),
+            &options,
+        );
+        assert_eq!(diff.changes.len(), 39_999);
+    }
+
+    #[test]
+    fn report() {
+        let diff = diff(
+            &value(r#"{"a": [true]}"#),
+            &value(r#"{"a": [false], "b": null}"#),
+            &DiffOptions::default(),
+        );
+        assert_eq!(
+            diff.patch.to_string(),
+            r#"[{"op":"replace","path":"/a/0","value":false},{"op":"add","path":"/b","value":null}]"#
+        );
+        let mut out = Vec::new();
+        diff.write_report(&mut out, false).unwrap();
+        assert_eq!(out, b"~ @.a.0: true -> false\n+ @.b: null\n".to_vec());
+        let mut out = Vec::new();
+        diff.write_report(&mut out, true).unwrap();
+        assert_eq!(
+            out,
+            b"\x1b[33m~ @.a.0: true -> false\x1b[0m\n\x1b[32m+ @.b: null\x1b[0m\n".to_vec()
+        );
+    }
+}
@@ -0,0 +1,611 @@
+//! A streaming pretty-printer.
+//!
+//! `Formatter` is fed tokens one at a time and writes them out with the
+//! configured layout as it goes, so it only holds a constant amount of
+//! state however large the input is (plus a bounded lookahead when fitting
+//! containers to a line width). It doesn't validate: run the tokens
+//! through a `Validator` first (as `TokenStream` does).
+//!
+//! With `FormatOptions::colors` set, tokens are wrapped in ANSI escape
+//! sequences (see the `highlight` module), in either layout.
+
+use crate::highlight::{self, Palette, TokenClass};
+use crate::number::NumberFormat;
+use crate::stream::TokenStream;
+use crate::string::{self, EscapeMode};
+use crate::tokenizer::Token;
+use crate::validator::Validator;
+use crate::Error;
+
+use std::borrow::Cow;
+use std::io;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    #[inline]
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// One member or element per line, or fitted to `max_width`.
+    Pretty,
+    /// Each document on a single line, with no space after commas. `indent`
+    /// and `max_width` are ignored.
+    Compact,
+    /// Like `Pretty` without `max_width`, but with commas at the start of
+    /// the line of the member or element after them:
+    ///
+    /// ```text
+    /// {
+    ///   "a": [
+    ///     1
+    ///     , 2
+    ///   ]
+    ///   , "b": null
+    /// }
+    /// ```
+    ///
+    /// Adding, removing or changing a member after the first then touches
+    /// exactly one line in a diff.
+    CommaFirst,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub style: Style,
+    pub indent: Indent,
+    pub line_ending: LineEnding,
+    /// Write `"key": value` rather than `"key":value`.
+    pub space_after_colon: bool,
+    /// End the output with a line ending. Documents after the first always
+    /// start on a new line.
+    pub trailing_newline: bool,
+    /// Write a container on one line if it fits within this many columns,
+    /// and expand it otherwise. With `None`, every non-empty container is
+    /// expanded.
+    pub max_width: Option<usize>,
+    /// Decode strings and escape them again in this mode, rather than
+    /// copying them as they are.
+    pub escape: Option<EscapeMode>,
+    /// Rewrite numbers into this canonical form, rather than copying them as
+    /// they are.
+    pub numbers: Option<NumberFormat>,
+    /// Highlight tokens with ANSI colors from this palette.
+    pub colors: Option<Palette>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            style: Style::Pretty,
+            indent: Indent::Spaces(2),
+            line_ending: LineEnding::Lf,
+            space_after_colon: true,
+            trailing_newline: true,
+            max_width: None,
+            escape: None,
+            numbers: None,
+            colors: None,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// One document per line with no insignificant whitespace.
+    pub fn compact() -> Self {
+        FormatOptions {
+            style: Style::Compact,
+            space_after_colon: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// The number of columns a tab is counted as when measuring line width.
+const TAB_WIDTH: usize = 4;
+
+pub struct Formatter<W> {
+    writer: W,
+    options: FormatOptions,
+    depth: usize,
+    /// The column the next byte will be written at.
+    column: usize,
+    /// An open token has been written but not the line break after it, which
+    /// is skipped if the container turns out to be empty.
+    pending_open: bool,
+    /// A complete document has been written.
+    document_ended: bool,
+    /// Tokens held back while deciding whether a container fits on one line.
+    lookahead: Vec<Token<'static>>,
+    /// The width of `lookahead` written on one line.
+    lookahead_width: usize,
+    /// The number of containers left open in `lookahead`.
+    lookahead_depth: usize,
+    /// Tracks where tokens are, to tell keys from values when highlighting.
+    validator: Validator,
+}
+
+impl<W: io::Write> Formatter<W> {
+    pub fn new(writer: W, options: FormatOptions) -> Self {
+        Formatter {
+            writer,
+            options,
+            depth: 0,
+            column: 0,
+            pending_open: false,
+            document_ended: false,
+            lookahead: Vec::new(),
+            lookahead_width: 0,
+            lookahead_depth: 0,
+            validator: Validator::new(),
+        }
+    }
+
+    #[inline]
+    pub fn options(&self) -> &FormatOptions {
+        &self.options
+    }
+
+    fn indent_width(&self) -> usize {
+        match self.options.indent {
+            Indent::Spaces(n) => n,
+            Indent::Tabs => TAB_WIDTH,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.column += bytes.len();
+        self.writer.write_all(bytes)
+    }
+
+    fn print(&mut self, token: &Token<'_>, class: Option<TokenClass>) -> io::Result<()> {
+        self.column += width(token);
+        match (self.options.colors, class) {
+            (Some(palette), Some(class)) => palette.print(&mut self.writer, token, class),
+            _ => token.print(&mut self.writer),
+        }
+    }
+
+    fn line_ending(&mut self) -> io::Result<()> {
+        self.column = 0;
+        self.writer.write_all(self.options.line_ending.as_bytes())
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        self.line_ending()?;
+        for _ in 0..self.depth {
+            match self.options.indent {
+                Indent::Spaces(n) => write!(self.writer, "{:1$}", "", n)?,
+                Indent::Tabs => self.writer.write_all(b"\t")?,
+            }
+        }
+        self.column = self.depth * self.indent_width();
+        Ok(())
+    }
+
+    /// The column the next token will start at, after any pending line break.
+    fn next_column(&self) -> usize {
+        if self.document_ended {
+            0
+        } else if self.pending_open {
+            self.depth * self.indent_width()
+        } else {
+            self.column
+        }
+    }
+
+    /// Write the next token. Whitespace tokens are ignored.
+    ///
+    /// With `max_width` set, the tokens of a container are held back until
+    /// either the container and any comma after it fit within the width, and
+    /// it's written on one line, or the width is exceeded, and it's expanded.
+    /// At most `max_width` columns' worth of tokens are held at once.
+    pub fn write_token(&mut self, token: &Token<'_>) -> io::Result<()> {
+        if token.is_whitespace() {
+            return Ok(());
+        }
+        match (token, self.options.escape, self.options.numbers) {
+            (Token::String(raw), Some(mode), _) => {
+                let decoded = string::decode(raw).map_err(|e| invalid_data(format!("{:?}", e)))?;
+                let escaped = string::escape(&decoded, mode);
+                self.layout_token(&Token::String(Cow::Owned(escaped.into_bytes())))
+            }
+            (Token::Number(raw), _, Some(format)) => {
+                let normalized = std::str::from_utf8(raw)
+                    .ok()
+                    .and_then(|text| format.apply(text))
+                    .ok_or_else(|| invalid_data("invalid number".into()))?;
+                self.layout_token(&Token::Number(Cow::Owned(normalized.into_bytes())))
+            }
+            _ => self.layout_token(token),
+        }
+    }
+
+    fn layout_token(&mut self, token: &Token<'_>) -> io::Result<()> {
+        let max_width = match (self.options.style, self.options.max_width) {
+            (Style::Compact, _) => return self.layout(token, true),
+            (Style::Pretty, Some(max_width)) => max_width,
+            _ => return self.layout(token, false),
+        };
+        if self.lookahead.is_empty() && !token.is_open() {
+            return self.layout(token, false);
+        }
+        if self.lookahead_depth == 0 && !self.lookahead.is_empty() {
+            // A container which fit is held until the token after it, since
+            // a comma after it has to fit too.
+            let comma = if *token == Token::Comma { 1 } else { 0 };
+            if self.next_column() + self.lookahead_width + comma <= max_width {
+                self.write_lookahead()?;
+            } else {
+                self.expand_lookahead()?;
+            }
+            return self.layout_token(token);
+        }
+        if token.is_open() {
+            self.lookahead_depth += 1;
+        } else if token.is_close() {
+            self.lookahead_depth -= 1;
+        }
+        self.lookahead_width += inline_width(token, &self.options);
+        self.lookahead.push(token.clone().into_owned());
+        let fits = self.next_column() + self.lookahead_width <= max_width;
+        if !fits {
+            self.expand_lookahead()?;
+        } else if self.lookahead_depth == 0 && self.depth == 0 {
+            // Nothing follows a whole document on its line.
+            self.write_lookahead()?;
+        }
+        Ok(())
+    }
+
+    /// Write the held back container on one line.
+    fn write_lookahead(&mut self) -> io::Result<()> {
+        for token in std::mem::take(&mut self.lookahead) {
+            self.layout(&token, true)?;
+        }
+        self.lookahead_width = 0;
+        Ok(())
+    }
+
+    /// Write the first held back container expanded, and feed the tokens
+    /// after its open token through again.
+    fn expand_lookahead(&mut self) -> io::Result<()> {
+        let mut tokens = std::mem::take(&mut self.lookahead).into_iter();
+        self.lookahead_width = 0;
+        self.lookahead_depth = 0;
+        if let Some(open) = tokens.next() {
+            self.layout(&open, false)?;
+        }
+        for token in tokens {
+            self.write_token(&token)?;
+        }
+        Ok(())
+    }
+
+    /// The class to highlight `token` with, if colors are on. Tokens reach
+    /// here in their original order, whatever the lookahead held back.
+    fn classify(&mut self, token: &Token<'_>) -> io::Result<Option<TokenClass>> {
+        if self.options.colors.is_none() {
+            return Ok(None);
+        }
+        self.validator
+            .process_token(token)
+            .map_err(|e| invalid_data(format!("{:?}", e)))?;
+        Ok(highlight::classify(token, self.validator.current_context()))
+    }
+
+    fn layout(&mut self, token: &Token<'_>, inline: bool) -> io::Result<()> {
+        let class = self.classify(token)?;
+        if self.document_ended {
+            self.document_ended = false;
+            self.line_ending()?;
+        }
+        if self.pending_open {
+            self.pending_open = false;
+            if token.is_close() {
+                self.depth -= 1;
+                self.print(token, class)?;
+                return self.end_value();
+            }
+            self.newline()?;
+        }
+        match token {
+            Token::ObjectOpen | Token::ArrayOpen => {
+                self.print(token, class)?;
+                self.depth += 1;
+                self.pending_open = !inline;
+                Ok(())
+            }
+            Token::ObjectClose | Token::ArrayClose => {
+                self.depth -= 1;
+                if !inline {
+                    self.newline()?;
+                }
+                self.print(token, class)?;
+                self.end_value()
+            }
+            Token::Comma if self.options.style == Style::CommaFirst => {
+                self.newline()?;
+                self.print(token, class)?;
+                self.write(b" ")
+            }
+            Token::Comma => {
+                self.print(token, class)?;
+                if !inline {
+                    self.newline()
+                } else if self.options.style == Style::Pretty {
+                    self.write(b" ")
+                } else {
+                    Ok(())
+                }
+            }
+            Token::Colon => {
+                self.print(token, class)?;
+                if self.options.space_after_colon {
+                    self.write(b" ")?;
+                }
+                Ok(())
+            }
+            _ => {
+                self.print(token, class)?;
+                self.end_value()
+            }
+        }
+    }
+
+    fn end_value(&mut self) -> io::Result<()> {
+        if self.depth == 0 {
+            self.document_ended = true;
+        }
+        Ok(())
+    }
+
+    /// Write anything held back and the trailing newline if configured, and
+    /// return the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.lookahead_depth == 0 && !self.lookahead.is_empty() {
+            self.write_lookahead()?;
+        }
+        for token in std::mem::take(&mut self.lookahead) {
+            self.layout(&token, false)?;
+        }
+        if self.document_ended && self.options.trailing_newline {
+            self.line_ending()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The number of columns a token takes up when its container is written on
+/// one line, including the space after a comma or colon.
+fn inline_width(token: &Token<'_>, options: &FormatOptions) -> usize {
+    match token {
+        Token::Comma => 2,
+        Token::Colon if options.space_after_colon => 2,
+        token => width(token),
+    }
+}
+
+/// The number of columns a token takes up by itself.
+fn width(token: &Token<'_>) -> usize {
+    match token {
+        Token::String(s) => std::str::from_utf8(s).map_or(s.len(), |s| s.chars().count()),
+        token => token.char_count(),
+    }
+}
+
+/// Reformat every document in `input`.
+pub fn format(input: &[u8], options: FormatOptions) -> Result<Vec<u8>, Error> {
+    let mut formatter = Formatter::new(Vec::with_capacity(input.len()), options);
+    for token in TokenStream::new(input) {
+        formatter.write_token(&token?.token)?;
+    }
+    Ok(formatter.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::NumberStyle;
+
+    const INPUT: &[u8] = br#"{"a": [1, 2, {}], "b": {"c": [ ], "d": null}} [true]"#;
+
+    fn format_str(options: FormatOptions) -> String {
+        String::from_utf8(format(INPUT, options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn default_layout() {
+        assert_eq!(
+            format_str(FormatOptions::default()),
+            r#"{
+  "a": [
+    1,
+    2,
+    {}
+  ],
+  "b": {
+    "c": [],
+    "d": null
+  }
+}
+[
+  true
+]
+"#
+        );
+    }
+
+    #[test]
+    fn options() {
+        let options = FormatOptions {
+            style: Style::Pretty,
+            indent: Indent::Tabs,
+            line_ending: LineEnding::CrLf,
+            space_after_colon: false,
+            trailing_newline: false,
+            max_width: None,
+            escape: None,
+            numbers: None,
+            colors: None,
+        };
+        assert_eq!(
+            format_str(options),
+            "{\r\n\t\"a\":[\r\n\t\t1,\r\n\t\t2,\r\n\t\t{}\r\n\t],\r\n\t\"b\":{\r\n\t\t\"c\":[],\
+             \r\n\t\t\"d\":null\r\n\t}\r\n}\r\n[\r\n\ttrue\r\n]"
+        );
+        let options = FormatOptions {
+            indent: Indent::Spaces(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            format(b"[[]]", options).unwrap(),
+            b"[\n    []\n]\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn compact() {
+        assert_eq!(
+            format_str(FormatOptions::compact()),
+            "{\"a\":[1,2,{}],\"b\":{\"c\":[],\"d\":null}}\n[true]\n"
+        );
+    }
+
+    #[test]
+    fn comma_first() {
+        let options = FormatOptions {
+            style: Style::CommaFirst,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_str(options),
+            r#"{
+  "a": [
+    1
+    , 2
+    , {}
+  ]
+  , "b": {
+    "c": []
+    , "d": null
+  }
+}
+[
+  true
+]
+"#
+        );
+    }
+
+    #[test]
+    fn fits_width() {
+        let options = FormatOptions {
+            max_width: Some(24),
+            ..Default::default()
+        };
+        let input = br#"{"short": [1, 2, 3], "long": ["abcdef", "ghijkl", "mnopqr"],
+            "nested": {"a": [], "b": {"c": null}}, "e": {}}"#;
+        assert_eq!(
+            String::from_utf8(format(input, options.clone()).unwrap()).unwrap(),
+            r#"{
+  "short": [1, 2, 3],
+  "long": [
+    "abcdef",
+    "ghijkl",
+    "mnopqr"
+  ],
+  "nested": {
+    "a": [],
+    "b": {"c": null}
+  },
+  "e": {}
+}
+"#
+        );
+        assert_eq!(
+            format(b"[1, [2]] 3", options).unwrap(),
+            b"[1, [2]]\n3\n".to_vec()
+        );
+
+        // `  "a": [1, 2]` is 13 columns, and 14 with the comma after it.
+        let input = br#"{"a": [1, 2], "b": [1, 2]}"#;
+        let at = |max_width| {
+            let options = FormatOptions {
+                max_width: Some(max_width),
+                ..Default::default()
+            };
+            String::from_utf8(format(input, options).unwrap()).unwrap()
+        };
+        assert_eq!(at(14), "{\n  \"a\": [1, 2],\n  \"b\": [1, 2]\n}\n");
+        assert_eq!(
+            at(13),
+            "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": [1, 2]\n}\n"
+        );
+    }
+
+    #[test]
+    fn reescapes_strings() {
+        let options = FormatOptions {
+            escape: Some(EscapeMode::Ascii),
+            max_width: Some(80),
+            ..Default::default()
+        };
+        assert_eq!(
+            format(r#"{"caf\u00e9": "<\/b>"}"#.as_bytes(), options).unwrap(),
+            br#"{"caf\u00e9": "</b>"}
+"#
+            .to_vec()
+        );
+        let options = FormatOptions {
+            escape: Some(EscapeMode::Html),
+            ..Default::default()
+        };
+        assert_eq!(
+            format(r#"["caf\u00e9", "<\/b>"]"#.as_bytes(), options).unwrap(),
+            "[\n  \"caf\u{e9}\",\n  \"\\u003c/b\\u003e\"\n]\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn normalizes_numbers() {
+        let options = FormatOptions {
+            numbers: Some(NumberFormat {
+                style: NumberStyle::Shortest,
+                negative_zero: false,
+            }),
+            max_width: Some(80),
+            ..Default::default()
+        };
+        assert_eq!(
+            format(
+                b"[1.0E+02, -0, 0.30000000000000004441, 18446744073709551617]",
+                options
+            )
+            .unwrap(),
+            b"[100, 0, 0.30000000000000004, 18446744073709551617]\n".to_vec()
+        );
+    }
+}
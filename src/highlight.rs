@@ -0,0 +1,149 @@
+//! ANSI syntax highlighting.
+//!
+//! `Formatter` colors each token by its `TokenClass` when
+//! `FormatOptions::colors` is set. Telling keys apart from string values
+//! needs the `Validator`'s context, so `classify` takes the context after
+//! the token was processed.
+
+use crate::tokenizer::Token;
+use crate::validator::ValidationContext;
+
+use std::ffi::OsString;
+use std::io;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    Key,
+    String,
+    Number,
+    Boolean,
+    Null,
+    /// Brackets, braces, commas and colons.
+    Punctuation,
+}
+
+/// The class of a non-whitespace token, given the validator's context after
+/// processing it.
+pub fn classify(token: &Token<'_>, context: Option<ValidationContext>) -> Option<TokenClass> {
+    Some(match token {
+        Token::String(_) if context == Some(ValidationContext::ObjectEntryKey) => TokenClass::Key,
+        Token::String(_) => TokenClass::String,
+        Token::Number(_) => TokenClass::Number,
+        Token::True | Token::False => TokenClass::Boolean,
+        Token::Null => TokenClass::Null,
+        Token::Spaces(_) | Token::Whitespace(_) => return None,
+        _ => TokenClass::Punctuation,
+    })
+}
+
+/// SGR parameters (the part between `ESC [` and `m`) for each class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub key: &'static str,
+    pub string: &'static str,
+    pub number: &'static str,
+    pub boolean: &'static str,
+    pub null: &'static str,
+    pub punctuation: &'static str,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            key: "1;34",
+            string: "32",
+            number: "36",
+            boolean: "33",
+            null: "1;30",
+            punctuation: "1",
+        }
+    }
+}
+
+impl Palette {
+    /// The default palette, unless the `NO_COLOR` environment variable is set
+    /// to a non-empty value.
+    pub fn from_env() -> Option<Palette> {
+        Palette::unless_no_color(std::env::var_os("NO_COLOR"))
+    }
+
+    /// `from_env`, given the value of `NO_COLOR`.
+    fn unless_no_color(no_color: Option<OsString>) -> Option<Palette> {
+        match no_color {
+            Some(ref value) if !value.is_empty() => None,
+            _ => Some(Palette::default()),
+        }
+    }
+
+    pub fn get(&self, class: TokenClass) -> &'static str {
+        match class {
+            TokenClass::Key => self.key,
+            TokenClass::String => self.string,
+            TokenClass::Number => self.number,
+            TokenClass::Boolean => self.boolean,
+            TokenClass::Null => self.null,
+            TokenClass::Punctuation => self.punctuation,
+        }
+    }
+
+    /// Write a token wrapped in the escape sequences for its class.
+    pub fn print<W: io::Write>(
+        &self,
+        mut writer: W,
+        token: &Token<'_>,
+        class: TokenClass,
+    ) -> io::Result<()> {
+        write!(writer, "\x1b[{}m", self.get(class))?;
+        token.print(&mut writer)?;
+        writer.write_all(b"\x1b[0m")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{format, FormatOptions};
+
+    #[test]
+    fn colors_keys_and_values() {
+        let palette = Palette {
+            key: "K",
+            string: "S",
+            number: "N",
+            boolean: "B",
+            null: "Z",
+            punctuation: "P",
+        };
+        let options = FormatOptions {
+            colors: Some(palette),
+            ..FormatOptions::compact()
+        };
+        let output = format(br#"{"a": ["b", 1, true, null]}"#, options).unwrap();
+        let expected = concat!(
+            "\x1b[Pm{\x1b[0m\x1b[Km\"a\"\x1b[0m\x1b[Pm:\x1b[0m",
+            "\x1b[Pm[\x1b[0m\x1b[Sm\"b\"\x1b[0m\x1b[Pm,\x1b[0m",
+            "\x1b[Nm1\x1b[0m\x1b[Pm,\x1b[0m\x1b[Bmtrue\x1b[0m\x1b[Pm,\x1b[0m",
+            "\x1b[Zmnull\x1b[0m\x1b[Pm]\x1b[0m\x1b[Pm}\x1b[0m\n",
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let pretty = FormatOptions {
+            colors: Some(Palette::default()),
+            ..Default::default()
+        };
+        let output = format(b"{\"a\": \"b\"}", pretty).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("\x1b[1;34m\"a\"\x1b[0m\x1b[1m:\x1b[0m \x1b[32m\"b\"\x1b[0m"));
+    }
+
+    #[test]
+    fn respects_no_color() {
+        assert_eq!(Palette::unless_no_color(Some("1".into())), None);
+        assert_eq!(
+            Palette::unless_no_color(Some("".into())),
+            Some(Palette::default())
+        );
+        assert_eq!(Palette::unless_no_color(None), Some(Palette::default()));
+    }
+}
@@ -0,0 +1,358 @@
+//! RFC 8785 JSON Canonicalization Scheme.
+//!
+//! The canonical form has no insignificant whitespace, object members sorted
+//! by the UTF-16 code units of their keys, numbers written the way
+//! ECMAScript's `Number.prototype.toString` writes them, and strings with
+//! only the escapes JSON requires. Two documents with the same data
+//! canonicalize to the same bytes, so the output can be hashed and signed.
+//!
+//! `Canonicalizer` is driven by tokens. Arrays are written as they stream
+//! past, but an object has to be held in memory until it closes so its
+//! members can be sorted. The input must be I-JSON, so an object with two
+//! members with the same key is an error.
+
+use crate::stream::TokenStream;
+use crate::string;
+use crate::tokenizer::Token;
+use crate::value::InvalidNumber;
+use crate::Error;
+
+use std::fmt::Write as _;
+use std::io;
+
+/// Write a number the way ECMAScript does: the shortest digits which
+/// round-trip, in positional notation for exponents from -7 to 20 and in
+/// exponential notation otherwise. Returns `None` for NaN and infinities.
+pub fn format_number(n: f64) -> Option<String> {
+    if !n.is_finite() {
+        return None;
+    }
+    if n == 0.0 {
+        // Including negative zero.
+        return Some("0".into());
+    }
+    // `{:e}` gives the shortest round-trip digits as `d[.ddd]e[-]x`.
+    let exponential = format!("{:e}", n.abs());
+    let (mantissa, exponent) = exponential.split_at(exponential.find('e')?);
+    let mut digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+    round_half_even(n.abs(), &mut digits, exponent);
+    let exponent = exponent[1..].parse::<i128>().ok()? + 1;
+    Some(layout(n < 0.0, &digits, exponent))
+}
+
+/// When the exact value is halfway between two shortest candidates, Rust
+/// rounds the last digit up but ECMAScript picks the even one.
+fn round_half_even(n: f64, digits: &mut String, exponent: &str) {
+    let k = digits.len();
+    // Cheap check first: the next digit has to be a 5 for a tie.
+    let next = format!("{:.*e}", k, n);
+    if !next.split('e').next().unwrap_or("").ends_with('5') {
+        return;
+    }
+    // A double's exact decimal expansion has at most 767 significant digits.
+    let exact = format!("{:.800e}", n);
+    let (mantissa, exact_exponent) = exact.split_at(exact.find('e').unwrap_or(0));
+    let exact_digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+    let exact_digits = exact_digits.trim_end_matches('0');
+    if exact_exponent != exponent || exact_digits.len() != k + 1 || !exact_digits.ends_with('5') {
+        return;
+    }
+    let lower = &exact_digits[..k];
+    let is_even = lower.ends_with(&['0', '2', '4', '6', '8'][..]);
+    let round_trips =
+        format!("{}.{}{}", &lower[..1], &lower[1..], exponent).parse::<f64>() == Ok(n);
+    if is_even && round_trips && lower != digits.as_str() {
+        *digits = lower.to_owned();
+    }
+}
+
+/// Write `0.digits * 10^exponent` with ECMAScript's choice between
+/// positional and exponential notation. `digits` must be non-empty with no
+/// leading or trailing zeros.
+pub(crate) fn layout(negative: bool, digits: &str, exponent: i128) -> String {
+    let k = digits.len() as i128;
+    let mut out = String::with_capacity(digits.len() + 8);
+    if negative {
+        out.push('-');
+    }
+    if k <= exponent && exponent <= 21 {
+        out.push_str(digits);
+        out.extend((0..exponent - k).map(|_| '0'));
+    } else if 0 < exponent && exponent <= 21 {
+        out.push_str(&digits[..exponent as usize]);
+        out.push('.');
+        out.push_str(&digits[exponent as usize..]);
+    } else if -6 < exponent && exponent <= 0 {
+        out.push_str("0.");
+        out.extend((0..-exponent).map(|_| '0'));
+        out.push_str(digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let sign = if exponent > 0 { '+' } else { '-' };
+        write!(out, "e{}{}", sign, (exponent - 1).unsigned_abs())
+            .expect("writing to a String can't fail");
+    }
+    out
+}
+
+enum Frame {
+    Array,
+    Object {
+        /// The UTF-16 sort key, the escaped key and the canonical value of
+        /// each member so far.
+        members: Vec<(Vec<u16>, String, Vec<u8>)>,
+        expect_key: bool,
+    },
+}
+
+pub struct Canonicalizer<W> {
+    writer: W,
+    stack: Vec<Frame>,
+}
+
+impl<W: io::Write> Canonicalizer<W> {
+    pub fn new(writer: W) -> Self {
+        Canonicalizer {
+            writer,
+            stack: Vec::new(),
+        }
+    }
+
+    /// True if no container is open, i.e. between documents.
+    #[inline]
+    pub fn is_idle(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Write bytes to the value of the innermost object's last member, or
+    /// straight to the writer when no object is open.
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let object = self.stack.iter_mut().rev().find_map(|frame| match frame {
+            Frame::Object { members, .. } => Some(members),
+            Frame::Array => None,
+        });
+        match object.and_then(|members| members.last_mut()) {
+            Some((_, _, value)) => {
+                value.extend_from_slice(bytes);
+                Ok(())
+            }
+            None => self.writer.write_all(bytes),
+        }
+    }
+
+    /// Process the next token, which must already have been validated.
+    pub fn write_token(&mut self, token: &Token<'_>) -> Result<(), Error> {
+        match token {
+            Token::ObjectOpen => self.stack.push(Frame::Object {
+                members: Vec::new(),
+                expect_key: true,
+            }),
+            Token::ArrayOpen => {
+                self.emit(b"[")?;
+                self.stack.push(Frame::Array);
+            }
+            Token::ObjectClose => {
+                if let Some(Frame::Object { mut members, .. }) = self.stack.pop() {
+                    members.sort_by(|a, b| a.0.cmp(&b.0));
+                    if let Some(pair) = members.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                        let key = String::from_utf16_lossy(&pair[0].0);
+                        return Err(Error::DuplicateKey(key));
+                    }
+                    let mut out = vec![b'{'];
+                    for (i, (_, key, value)) in members.iter().enumerate() {
+                        if i > 0 {
+                            out.push(b',');
+                        }
+                        out.extend_from_slice(key.as_bytes());
+                        out.push(b':');
+                        out.extend_from_slice(value);
+                    }
+                    out.push(b'}');
+                    self.emit(&out)?;
+                }
+            }
+            Token::ArrayClose => {
+                self.stack.pop();
+                self.emit(b"]")?;
+            }
+            Token::Comma => match self.stack.last_mut() {
+                Some(Frame::Object { expect_key, .. }) => *expect_key = true,
+                _ => self.emit(b",")?,
+            },
+            Token::String(raw) => {
+                let decoded = string::decode(raw)?;
+                let mut escaped = String::with_capacity(decoded.len() + 2);
+                string::write_escaped(&mut escaped, &decoded)
+                    .expect("writing to a String can't fail");
+                match self.stack.last_mut() {
+                    Some(Frame::Object {
+                        members,
+                        expect_key,
+                    }) if *expect_key => {
+                        *expect_key = false;
+                        members.push((decoded.encode_utf16().collect(), escaped, Vec::new()));
+                    }
+                    _ => self.emit(escaped.as_bytes())?,
+                }
+            }
+            Token::Number(raw) => {
+                let n = std::str::from_utf8(raw)
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .and_then(format_number)
+                    .ok_or(InvalidNumber)?;
+                self.emit(n.as_bytes())?;
+            }
+            Token::True | Token::False | Token::Null => {
+                let mut out = Vec::with_capacity(5);
+                token.print(&mut out)?;
+                self.emit(&out)?;
+            }
+            Token::Colon | Token::Spaces(_) | Token::Whitespace(_) => (),
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Canonicalize a single document.
+pub fn canonicalize(input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut canonicalizer = Canonicalizer::new(Vec::with_capacity(input.len()));
+    let mut complete = false;
+    for token in TokenStream::new(input) {
+        let token = token?;
+        if complete {
+            return Err(Error::TrailingInput(token.start));
+        }
+        canonicalizer.write_token(&token.token)?;
+        complete = canonicalizer.is_idle();
+    }
+    if !complete {
+        return Err(Error::UnexpectedEndOfInput);
+    }
+    Ok(canonicalizer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    fn canonical(input: &str) -> String {
+        String::from_utf8(canonicalize(input.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rfc_numbers() {
+        // RFC 8785 appendix B.
+        let vectors: &[(u64, &str)] = &[
+            (0x0000_0000_0000_0000, "0"),
+            (0x8000_0000_0000_0000, "0"),
+            (0x0000_0000_0000_0001, "5e-324"),
+            (0x8000_0000_0000_0001, "-5e-324"),
+            (0x7fef_ffff_ffff_ffff, "1.7976931348623157e+308"),
+            (0xffef_ffff_ffff_ffff, "-1.7976931348623157e+308"),
+            (0x4340_0000_0000_0000, "9007199254740992"),
+            (0xc340_0000_0000_0000, "-9007199254740992"),
+            (0x4430_0000_0000_0000, "295147905179352830000"),
+            (0x44b5_2d02_c7e1_4af5, "9.999999999999997e+22"),
+            (0x44b5_2d02_c7e1_4af6, "1e+23"),
+            (0x44b5_2d02_c7e1_4af7, "1.0000000000000001e+23"),
+            (0x444b_1ae4_d6e2_ef4e, "999999999999999700000"),
+            (0x444b_1ae4_d6e2_ef4f, "999999999999999900000"),
+            (0x444b_1ae4_d6e2_ef50, "1e+21"),
+            (0x3eb0_c6f7_a0b5_ed8c, "9.999999999999997e-7"),
+            (0x3eb0_c6f7_a0b5_ed8d, "0.000001"),
+            (0x41b3_de43_5555_5553, "333333333.3333332"),
+            (0x41b3_de43_5555_5554, "333333333.33333325"),
+            (0x41b3_de43_5555_5555, "333333333.3333333"),
+            (0x41b3_de43_5555_5556, "333333333.3333334"),
+            (0x41b3_de43_5555_5557, "333333333.33333343"),
+            (0xbecb_f647_612f_3696, "-0.0000033333333333333333"),
+            (0x4314_3ff3_c1cb_0959, "1424953923781206.2"),
+        ];
+        for &(bits, expected) in vectors {
+            assert_eq!(format_number(f64::from_bits(bits)).unwrap(), expected);
+        }
+        assert_eq!(format_number(f64::NAN), None);
+        assert!(canonicalize(b"1e400").is_err());
+    }
+
+    #[test]
+    fn rfc_examples() {
+        // RFC 8785 section 3.2.2.
+        let input = r#"{
+  "numbers": [333333333.33333329, 1E30, 4.50,
+              2e-3, 0.000000000000000000000000001],
+  "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+  "literals": [null, true, false]
+}"#;
+        assert_eq!(
+            canonical(input),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+
+        // RFC 8785 section 3.2.3.
+        let input = r#"{
+  "\u20ac": "Euro Sign",
+  "\r": "Carriage Return",
+  "\ufb33": "Hebrew Letter Dalet With Dagesh",
+  "1": "One",
+  "\ud83d\ude00": "Emoji: Grinning Face",
+  "\u0080": "Control",
+  "\u00f6": "Latin Small Letter O With Diaeresis"
+}"#;
+        let order: Vec<_> = Value::parse(&canonicalize(input.as_bytes()).unwrap())
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|v| v.as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "Carriage Return",
+                "One",
+                "Control",
+                "Latin Small Letter O With Diaeresis",
+                "Euro Sign",
+                "Emoji: Grinning Face",
+                "Hebrew Letter Dalet With Dagesh",
+            ]
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        assert_eq!(
+            canonical(r#" [ {"b": [1, {"d": 0, "c": []}], "a": {}}, "x" ] "#),
+            r#"[{"a":{},"b":[1,{"c":[],"d":0}]},"x"]"#
+        );
+        assert!(canonicalize(b"{} {}").is_err());
+        assert!(canonicalize(b"[1").is_err());
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        for input in &[r#"{"a":1,"a":2}"#, r#"[{"b":0,"a":1,"\u0061":1}]"#] {
+            let result = canonicalize(input.as_bytes());
+            assert!(
+                matches!(result, Err(Error::DuplicateKey(ref key)) if key == "a"),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            canonical(r#"{"a":{"a":1},"b":{"a":2}}"#),
+            r#"{"a":{"a":1},"b":{"a":2}}"#
+        );
+    }
+}
@@ -2,13 +2,105 @@
 
 mod lookup_tables;
 mod utf8;
+pub mod cst;
+#[cfg(feature = "serde")]
+pub mod de;
+pub mod decimal;
+pub mod diff;
+pub mod format;
+pub mod highlight;
+pub mod jcs;
+pub mod merge_patch;
+pub mod number;
+pub mod patch;
+pub mod path_stack;
+pub mod pattern;
+pub mod pointer;
+pub mod query;
+pub mod raw;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod sort;
+pub mod stream;
+pub mod string;
+pub mod tape;
 pub mod tokenizer;
 pub mod validator;
+pub mod value;
+pub mod value_ref;
+pub mod writer;
 
 use std::borrow::Cow;
 use std::fmt;
 pub use section;
 
+use patch::PatchError;
+use pattern::{PathPattern, PatternSegment};
+use pointer::PointerError;
+use string::StringError;
+use tokenizer::TokenizeError;
+use validator::ValidationError;
+
+#[derive(Debug, derive_more::From)]
+pub enum Error {
+    Tokenize(TokenizeError),
+    Validation(ValidationError),
+    String(StringError),
+    Pointer(PointerError),
+    UnexpectedEndOfInput,
+    /// Non-whitespace input at this offset after a complete document.
+    TrailingInput(usize),
+    /// No value starts at this offset: it's whitespace, punctuation or past
+    /// the end of the input.
+    NotAValue(usize),
+    Io(std::io::Error),
+    /// A number which can't be converted where a conversion was required:
+    /// one outside the range of an `f64`, a non-finite `f64`, which JSON
+    /// can't represent, or one whose exponent doesn't fit in a `Decimal`.
+    InvalidNumber(value::InvalidNumber),
+    Patch(PatchError),
+    /// Input which doesn't fit in a fixed-width field, such as a `Tape` with
+    /// 2^32 or more entries or a string of 4 GiB or more.
+    TooLarge,
+    /// An edit which needs an array or an object was applied to a value of
+    /// this type.
+    WrongType(JsonType),
+    /// An object has two members with this key, which canonical JSON doesn't
+    /// allow.
+    DuplicateKey(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Tokenize(error) => write!(f, "invalid token: {}", error),
+            Error::Validation(error) => write!(f, "invalid JSON: {}", error),
+            Error::String(error) => write!(f, "invalid string: {}", error),
+            Error::Pointer(error) => write!(f, "invalid JSON pointer: {}", error),
+            Error::UnexpectedEndOfInput => f.write_str("unexpected end of input"),
+            Error::TrailingInput(offset) => write!(f, "trailing input at offset {}", offset),
+            Error::NotAValue(offset) => write!(f, "no value starts at offset {}", offset),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::InvalidNumber(_) => f.write_str("number out of range"),
+            Error::Patch(error) => write!(f, "patch failed: {}", error),
+            Error::TooLarge => f.write_str("input too large"),
+            Error::WrongType(found) => {
+                write!(f, "expected an array or an object, found {:?}", found)
+            }
+            Error::DuplicateKey(key) => write!(f, "duplicate key {:?}", key),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
 pub enum JsonType {
     Array,
@@ -19,7 +111,11 @@ pub enum JsonType {
     Null,
 }
 
-#[derive(Hash, Eq, PartialEq, Clone, Debug, derive_more::Display, derive_more::From)]
+/// Segments are ordered with indices before keys, indices numerically and keys
+/// by their bytes.
+#[derive(
+    Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Debug, derive_more::Display, derive_more::From,
+)]
 pub enum JsonPathSegment<'a> {
     // Root,
     #[display(fmt = "{}", _0)]
@@ -73,16 +169,29 @@ impl<'a> JsonPathSegment<'a> {
     pub fn is_key(&self) -> bool {
         !self.is_index()
     }
+
+    pub fn into_owned(self) -> JsonPathSegment<'static> {
+        match self {
+            JsonPathSegment::Index(index) => JsonPathSegment::Index(index),
+            JsonPathSegment::Key(key) => JsonPathSegment::Key(Cow::Owned(key.into_owned())),
+        }
+    }
 }
 
 pub const EMPTY_KEY: JsonPathSegment<'static> = JsonPathSegment::Key(Cow::Borrowed(""));
 // pub const EMPTY_INDEX: JsonPathSegment<'static> = JsonPathSegment::Index(std::usize::MAX);
 
+/// Paths are ordered lexicographically by segment, so sorting a list of paths
+/// puts every ancestor before its descendants (a topological order of the tree)
+/// and keeps siblings in index order.
 #[derive(
     Hash,
     Clone,
+    Debug,
     Eq,
     PartialEq,
+    Ord,
+    PartialOrd,
     derive_more::From,
     derive_more::Constructor,
     derive_deref::Deref,
@@ -91,12 +200,104 @@ pub const EMPTY_KEY: JsonPathSegment<'static> = JsonPathSegment::Key(Cow::Borrow
 pub struct JsonPath<'a>(Cow<'a, [JsonPathSegment<'a>]>);
 
 impl<'a> JsonPath<'a> {
-    // TODO optimize
+    /// The path without its last segment. This only clones the segments if the
+    /// path owns them; use `PathStack` to avoid that when walking a document.
     pub fn parent(&self) -> Self {
-        let mut slice = self.0.clone().into_owned();
-        slice.pop();
-        JsonPath::new(slice.into())
+        let len = self.0.len().saturating_sub(1);
+        match self.0 {
+            Cow::Borrowed(segments) => JsonPath::new(Cow::Borrowed(&segments[..len])),
+            Cow::Owned(ref segments) => JsonPath::new(Cow::Owned(segments[..len].to_vec())),
+        }
+    }
+
+    /// The path without its last segment, borrowing from this one.
+    pub fn parent_ref(&self) -> JsonPath<'_> {
+        let len = self.0.len().saturating_sub(1);
+        JsonPath::new(Cow::Borrowed(&self.0[..len]))
+    }
+
+    /// True if `prefix` is an ancestor of this path or equal to it.
+    pub fn starts_with(&self, prefix: &[JsonPathSegment<'_>]) -> bool {
+        self.0.len() >= prefix.len() && self.common_prefix_len(prefix) == prefix.len()
+    }
+
+    /// True if this path is a strict ancestor of `other`.
+    pub fn is_ancestor_of(&self, other: &[JsonPathSegment<'_>]) -> bool {
+        other.len() > self.0.len() && self.common_prefix_len(other) == self.0.len()
+    }
+
+    /// True if this path is a strict descendant of `other`.
+    pub fn is_descendant_of(&self, other: &[JsonPathSegment<'_>]) -> bool {
+        self.0.len() > other.len() && self.starts_with(other)
     }
+
+    /// The number of leading segments shared with `other`.
+    pub fn common_prefix_len(&self, other: &[JsonPathSegment<'_>]) -> usize {
+        self.0
+            .iter()
+            .zip(other.iter())
+            .take_while(|(a, b)| segment_eq(a, b))
+            .count()
+    }
+
+    /// The deepest path which is an ancestor of (or equal to) both paths.
+    pub fn common_ancestor(&self, other: &[JsonPathSegment<'_>]) -> JsonPath<'_> {
+        JsonPath::new(Cow::Borrowed(&self.0[..self.common_prefix_len(other)]))
+    }
+
+    /// The deepest common ancestor of every path, or `None` if there are no paths.
+    pub fn common_ancestor_of_all<'p, 'q: 'p, I>(paths: I) -> Option<JsonPath<'p>>
+    where
+        I: IntoIterator<Item = &'p JsonPath<'q>>,
+    {
+        let mut paths = paths.into_iter();
+        let first = paths.next()?;
+        let len = paths.fold(first.len(), |len, path| {
+            len.min(first.common_prefix_len(path))
+        });
+        Some(JsonPath::new(Cow::Borrowed(&first.0[..len])))
+    }
+
+    /// Replace every index with a wildcard, so that paths which only differ by
+    /// array position are equal. This is the library version of
+    /// `sed -r 's/\.[0-9]+/[]/g'` over the output of `print-paths`.
+    pub fn generalize_indices(&self) -> PathPattern {
+        PathPattern::new(
+            self.0
+                .iter()
+                .map(|segment| match segment {
+                    JsonPathSegment::Index(_) => PatternSegment::AnyIndex,
+                    JsonPathSegment::Key(key) => PatternSegment::Key(key.to_string()),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn into_owned(self) -> JsonPath<'static> {
+        JsonPath::new(Cow::Owned(
+            self.0
+                .into_owned()
+                .into_iter()
+                .map(JsonPathSegment::into_owned)
+                .collect(),
+        ))
+    }
+}
+
+/// Compare segments with different lifetimes.
+#[inline]
+fn segment_eq(a: &JsonPathSegment<'_>, b: &JsonPathSegment<'_>) -> bool {
+    match (a, b) {
+        (JsonPathSegment::Index(a), JsonPathSegment::Index(b)) => a == b,
+        (JsonPathSegment::Key(a), JsonPathSegment::Key(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Sort paths so that ancestors come before their descendants, and remove duplicates.
+pub fn sort_topologically(paths: &mut Vec<JsonPath<'_>>) {
+    paths.sort();
+    paths.dedup();
 }
 
 impl<'a> fmt::Display for JsonPath<'a> {
@@ -108,3 +309,73 @@ impl<'a> fmt::Display for JsonPath<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(pointer: &str) -> JsonPath<'static> {
+        JsonPath::from_pointer(pointer).unwrap()
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(Error::TrailingInput(3).to_string(), "trailing input at offset 3");
+        let io = Error::from(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed"));
+        assert_eq!(io.to_string(), "closed");
+        assert!(std::error::Error::source(&io).is_some());
+        assert_eq!(
+            value::Value::parse(b"[1,]").unwrap_err().to_string(),
+            "invalid JSON: unexpected token after a comma in an array"
+        );
+        assert_eq!(
+            value::Value::parse(b"[1, tru]").unwrap_err().to_string(),
+            "invalid token: unexpected byte ']' in `true`"
+        );
+    }
+
+    #[test]
+    fn ancestry() {
+        let a = path("/a/0/b");
+        assert!(a.starts_with(&path("/a/0")));
+        assert!(a.starts_with(&a));
+        assert!(path("/a").is_ancestor_of(&a));
+        assert!(!a.is_ancestor_of(&a));
+        assert!(a.is_descendant_of(&path("")));
+        assert!(!a.is_descendant_of(&path("/a/1")));
+        assert_eq!(
+            a.common_ancestor(&path("/a/0/c")).into_owned(),
+            path("/a/0")
+        );
+        assert_eq!(a.common_ancestor(&path("/b")).into_owned(), path(""));
+
+        let paths = vec![path("/a/0/b"), path("/a/1"), path("/a/0/c")];
+        let ancestor = JsonPath::common_ancestor_of_all(&paths).map(JsonPath::into_owned);
+        assert_eq!(ancestor, Some(path("/a")));
+        assert_eq!(JsonPath::common_ancestor_of_all(&Vec::new()), None);
+    }
+
+    #[test]
+    fn generalize() {
+        let a = path("/a/0/b/12").generalize_indices();
+        assert_eq!(a.to_string(), "a[].b[]");
+        assert_eq!(a, path("/a/3/b/0").generalize_indices());
+        assert!(a.matches(&path("/a/7/b/1")));
+    }
+
+    #[test]
+    fn topological_order() {
+        let mut paths = vec![
+            path("/b"),
+            path("/a/10"),
+            path("/a/2/x"),
+            path("/a"),
+            path(""),
+            path("/a/2"),
+            path("/a/10"),
+        ];
+        sort_topologically(&mut paths);
+        let pointers: Vec<_> = paths.iter().map(|p| p.to_pointer()).collect();
+        assert_eq!(pointers, vec!["", "/a", "/a/2", "/a/2/x", "/a/10", "/b"]);
+    }
+}
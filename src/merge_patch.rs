@@ -0,0 +1,178 @@
+//! RFC 7396 JSON Merge Patch.
+//!
+//! A merge patch looks like the document it changes: members of an object
+//! patch are merged recursively into the target, `null` removes a member and
+//! anything else replaces the target outright. Layering configuration, a base
+//! with environment overrides on top, is applying each override in turn.
+//!
+//! Merge patches can't set a member to `null` or change part of an array, so
+//! `diff` replaces arrays whole, and a `null` added by the change is lost.
+
+use crate::value::{Map, Value};
+use crate::{JsonPath, JsonPathSegment};
+
+use std::borrow::Cow;
+
+/// Apply `patch` to `target`.
+pub fn apply(target: &mut Value, patch: &Value) {
+    let members = match patch {
+        Value::Object(members) => members,
+        patch => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if target.as_object().is_none() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = target {
+        for (key, value) in members.iter() {
+            if value.is_null() {
+                map.remove(key);
+                continue;
+            }
+            match map.get_mut(key) {
+                Some(existing) => apply(existing, value),
+                None => {
+                    let mut added = Value::Null;
+                    apply(&mut added, value);
+                    map.insert(key.clone(), added);
+                }
+            }
+        }
+    }
+}
+
+/// The merge patch which turns `from` into `to`. Equivalent objects (see
+/// `Value::equivalent`) give `{}`. Any other `to` which isn't an object is
+/// the whole patch, since `{}` would turn a scalar or an array into an
+/// empty object.
+pub fn diff(from: &Value, to: &Value) -> Value {
+    match (from, to) {
+        (Value::Object(from), Value::Object(to)) => {
+            let mut patch = Map::new();
+            for (key, old) in from.iter() {
+                match to.get(key) {
+                    None => {
+                        patch.insert(key.clone(), Value::Null);
+                    }
+                    Some(new) if !old.equivalent(new) => {
+                        patch.insert(key.clone(), diff(old, new));
+                    }
+                    Some(_) => (),
+                }
+            }
+            for (key, new) in to.iter() {
+                if !from.contains_key(key) {
+                    patch.insert(key.clone(), new.clone());
+                }
+            }
+            Value::Object(patch)
+        }
+        (_, to) => to.clone(),
+    }
+}
+
+/// The paths which applying `patch` sets or removes, in patch order.
+pub fn paths(patch: &Value) -> Vec<JsonPath<'static>> {
+    fn walk(
+        patch: &Value,
+        prefix: &mut Vec<JsonPathSegment<'static>>,
+        out: &mut Vec<JsonPath<'static>>,
+    ) {
+        match patch {
+            Value::Object(members) => {
+                for (key, value) in members.iter() {
+                    prefix.push(JsonPathSegment::Key(Cow::Owned(key.clone())));
+                    walk(value, prefix, out);
+                    prefix.pop();
+                }
+            }
+            _ => out.push(JsonPath::new(Cow::Owned(prefix.clone()))),
+        }
+    }
+    let mut out = Vec::new();
+    walk(patch, &mut Vec::new(), &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(json: &str) -> Value {
+        Value::parse(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn rfc_examples() {
+        // RFC 7396 appendix A.
+        for &(target, patch, expected) in &[
+            (r#"{"a":"b"}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+            (r#"{"a":"b"}"#, r#"{"b":"c"}"#, r#"{"a":"b","b":"c"}"#),
+            (r#"{"a":"b"}"#, r#"{"a":null}"#, r#"{}"#),
+            (r#"{"a":"b","b":"c"}"#, r#"{"a":null}"#, r#"{"b":"c"}"#),
+            (r#"{"a":["b"]}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+            (r#"{"a":"c"}"#, r#"{"a":["b"]}"#, r#"{"a":["b"]}"#),
+            (
+                r#"{"a":{"b":"c"}}"#,
+                r#"{"a":{"b":"d","c":null}}"#,
+                r#"{"a":{"b":"d"}}"#,
+            ),
+            (r#"{"a":[{"b":"c"}]}"#, r#"{"a":[1]}"#, r#"{"a":[1]}"#),
+            (r#"["a","b"]"#, r#"["c","d"]"#, r#"["c","d"]"#),
+            (r#"{"a":"b"}"#, r#"["c"]"#, r#"["c"]"#),
+            (r#"{"a":"foo"}"#, r#"null"#, r#"null"#),
+            (r#"{"a":"foo"}"#, r#""bar""#, r#""bar""#),
+            (r#"{"e":null}"#, r#"{"a":1}"#, r#"{"e":null,"a":1}"#),
+            (r#"[1,2]"#, r#"{"a":"b","c":null}"#, r#"{"a":"b"}"#),
+            (
+                r#"{}"#,
+                r#"{"a":{"bb":{"ccc":null}}}"#,
+                r#"{"a":{"bb":{}}}"#,
+            ),
+        ] {
+            let mut doc = value(target);
+            apply(&mut doc, &value(patch));
+            assert_eq!(doc, value(expected), "{} + {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn diffs() {
+        let base =
+            value(r#"{"port": 80, "tls": {"cert": "a", "key": "b"}, "hosts": ["x"], "n": 1}"#);
+        let prod = value(
+            r#"{"port": 443, "tls": {"cert": "a"}, "hosts": ["x", "y"], "n": 1.0, "debug": false}"#,
+        );
+        let patch = diff(&base, &prod);
+        assert_eq!(
+            patch,
+            value(r#"{"port": 443, "tls": {"key": null}, "hosts": ["x", "y"], "debug": false}"#)
+        );
+        let mut layered = base.clone();
+        apply(&mut layered, &patch);
+        assert!(layered.equivalent(&prod));
+        assert_eq!(diff(&prod, &prod), value("{}"));
+
+        for &(from, to) in &[
+            ("1", "1"),
+            ("1", "1.0"),
+            ("[1, 2]", "[1, 2]"),
+            ("[1]", "[2]"),
+            ("{\"a\": 1}", "[1]"),
+            ("[1]", "{\"a\": 1}"),
+            ("\"a\"", "{}"),
+            ("null", "null"),
+        ] {
+            let (from, to) = (value(from), value(to));
+            let mut patched = from.clone();
+            apply(&mut patched, &diff(&from, &to));
+            assert!(patched.equivalent(&to), "{} -> {}", from, to);
+        }
+        assert_eq!(diff(&value("[1, 2]"), &value("[1, 2]")), value("[1, 2]"));
+
+        let pointers: Vec<_> = paths(&patch).iter().map(JsonPath::to_pointer).collect();
+        assert_eq!(pointers, vec!["/port", "/tls/key", "/hosts", "/debug"]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Formatting and normalizing number text.
+//!
+//! Different producers write the same number differently (`100`, `1e2`,
+//! `1.0E+02`). `NumberFormat` rewrites the text of a `Token::Number` into one
+//! canonical form, so output from different producers can be diffed.
+//! Normalization works on the exact `Decimal` value, so integers of any size
+//! and exact decimals never lose precision.
+
+use crate::decimal::Decimal;
+use crate::jcs::format_number;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberStyle {
+    /// Keep every significant digit, only changing the notation.
+    Exact,
+    /// Non-integers are rounded to the nearest `f64` and written with the
+    /// fewest digits which round-trip. Integers are kept exact, however large.
+    Shortest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub style: NumberStyle,
+    /// Write negative zero as `-0` rather than `0`.
+    pub negative_zero: bool,
+}
+
+impl NumberFormat {
+    /// Rewrite the text of a `Token::Number`. Integers which end in a nonzero
+    /// digit are written in full, however long. Other numbers use positional
+    /// notation for exponents from -7 to 20 and exponential notation
+    /// (`1e+21`) otherwise, following ECMAScript. Returns `None` if `text`
+    /// isn't a JSON number or doesn't fit in a `Decimal`.
+    pub fn apply(&self, text: &str) -> Option<String> {
+        let decimal = text.parse::<Decimal>().ok()?;
+        if decimal.is_zero() {
+            let zero = if text.starts_with('-') && self.negative_zero {
+                "-0"
+            } else {
+                "0"
+            };
+            return Some(zero.into());
+        }
+        if decimal.exponent() == 0 {
+            // An integer with no trailing zeros: every digit is significant,
+            // so positional notation is never longer than exponential.
+            let sign = if decimal.is_negative() { "-" } else { "" };
+            return Some(format!("{}{}", sign, decimal.coefficient()));
+        }
+        if self.style == NumberStyle::Shortest && !decimal.is_integer() {
+            let n = decimal.to_f64();
+            // Values out of range keep their exact digits rather than
+            // becoming zero or infinity.
+            if n != 0.0 {
+                if let Some(shortest) = format_number(n) {
+                    return Some(shortest);
+                }
+            }
+        }
+        Some(decimal.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes() {
+        let exact = NumberFormat {
+            style: NumberStyle::Exact,
+            negative_zero: false,
+        };
+        let shortest = NumberFormat {
+            style: NumberStyle::Shortest,
+            negative_zero: true,
+        };
+        for &(input, expected_exact, expected_shortest) in &[
+            ("1.0E+02", "100", "100"),
+            ("100", "100", "100"),
+            ("-0", "0", "-0"),
+            ("-0.000", "0", "-0"),
+            ("0.50", "0.5", "0.5"),
+            (
+                "12345678901234567890123",
+                "12345678901234567890123",
+                "12345678901234567890123",
+            ),
+            (
+                "-1.2345678901234567890123e22",
+                "-12345678901234567890123",
+                "-12345678901234567890123",
+            ),
+            ("1000000000000000000000", "1e+21", "1e+21"),
+            ("9007199254740993", "9007199254740993", "9007199254740993"),
+            (
+                "0.1000000000000000055511151231257827",
+                "0.1000000000000000055511151231257827",
+                "0.1",
+            ),
+            ("1E-7", "1e-7", "1e-7"),
+            ("2.5e-400", "2.5e-400", "2.5e-400"),
+            ("1e400", "1e+400", "1e+400"),
+        ] {
+            assert_eq!(exact.apply(input).unwrap(), expected_exact, "{}", input);
+            assert_eq!(
+                shortest.apply(input).unwrap(),
+                expected_shortest,
+                "{}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,733 @@
+//! RFC 6902 JSON Patch.
+//!
+//! `Patch::apply` works on a `Value` and is atomic: the operations are
+//! applied to a copy, which only replaces the document once every one of
+//! them has succeeded.
+//!
+//! `apply_bytes` patches serialized JSON. When every operation is a
+//! `replace`, a `test`, or an `add` or `remove` of an object member, and no
+//! operation's path is inside another's, it rewrites the input in one
+//! streaming pass without building a `Value`, leaving everything outside the
+//! patched values byte for byte as it was. If a patched path passes through
+//! a key which appears twice in the same object, the document is parsed
+//! instead, so that the last member wins as it does for `Value`.
+
+use crate::path_stack::PathStack;
+use crate::stream::TokenStream;
+use crate::string;
+use crate::tokenizer::Token;
+use crate::value::{Map, Value};
+use crate::{Error, JsonPath, JsonPathSegment};
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch document isn't an array.
+    NotAnArray,
+    /// The operation at this index is malformed.
+    Invalid(usize),
+    /// The path (or `from`) of the operation at this index doesn't exist.
+    NotFound(usize),
+    /// The `test` operation at this index failed.
+    TestFailed(usize),
+    /// The `move` operation at this index would move a value into itself.
+    MoveIntoChild(usize),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::NotAnArray => f.write_str("the patch isn't an array"),
+            PatchError::Invalid(i) => write!(f, "operation {} is malformed", i),
+            PatchError::NotFound(i) => write!(f, "the path of operation {} doesn't exist", i),
+            PatchError::TestFailed(i) => write!(f, "test operation {} failed", i),
+            PatchError::MoveIntoChild(i) => {
+                write!(f, "operation {} moves a value into itself", i)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    Add {
+        path: JsonPath<'static>,
+        value: Value,
+    },
+    Remove {
+        path: JsonPath<'static>,
+    },
+    Replace {
+        path: JsonPath<'static>,
+        value: Value,
+    },
+    Move {
+        from: JsonPath<'static>,
+        path: JsonPath<'static>,
+    },
+    Copy {
+        from: JsonPath<'static>,
+        path: JsonPath<'static>,
+    },
+    Test {
+        path: JsonPath<'static>,
+        value: Value,
+    },
+}
+
+impl Operation {
+    /// The `op` member of the operation.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Add { .. } => "add",
+            Operation::Remove { .. } => "remove",
+            Operation::Replace { .. } => "replace",
+            Operation::Move { .. } => "move",
+            Operation::Copy { .. } => "copy",
+            Operation::Test { .. } => "test",
+        }
+    }
+
+    pub fn path(&self) -> &JsonPath<'static> {
+        match self {
+            Operation::Add { path, .. }
+            | Operation::Remove { path }
+            | Operation::Replace { path, .. }
+            | Operation::Move { path, .. }
+            | Operation::Copy { path, .. }
+            | Operation::Test { path, .. } => path,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let (from, value) = match self {
+            Operation::Add { value, .. }
+            | Operation::Replace { value, .. }
+            | Operation::Test { value, .. } => (None, Some(value)),
+            Operation::Move { from, .. } | Operation::Copy { from, .. } => (Some(from), None),
+            Operation::Remove { .. } => (None, None),
+        };
+        let mut map = Map::new();
+        map.insert("op".into(), self.name().into());
+        if let Some(from) = from {
+            map.insert("from".into(), from.to_pointer().into());
+        }
+        map.insert("path".into(), self.path().to_pointer().into());
+        if let Some(value) = value {
+            map.insert("value".into(), value.clone());
+        }
+        Value::Object(map)
+    }
+
+    fn from_value(value: &Value, index: usize) -> Result<Operation, PatchError> {
+        let invalid = || PatchError::Invalid(index);
+        let object = value.as_object().ok_or_else(invalid)?;
+        let pointer = |name: &str| {
+            let pointer = object
+                .get(name)
+                .and_then(Value::as_str)
+                .ok_or_else(invalid)?;
+            JsonPath::from_pointer(pointer).map_err(|_| invalid())
+        };
+        let value = || object.get("value").cloned().ok_or_else(invalid);
+        Ok(match object.get("op").and_then(Value::as_str) {
+            Some("add") => Operation::Add {
+                path: pointer("path")?,
+                value: value()?,
+            },
+            Some("remove") => Operation::Remove {
+                path: pointer("path")?,
+            },
+            Some("replace") => Operation::Replace {
+                path: pointer("path")?,
+                value: value()?,
+            },
+            Some("move") => Operation::Move {
+                from: pointer("from")?,
+                path: pointer("path")?,
+            },
+            Some("copy") => Operation::Copy {
+                from: pointer("from")?,
+                path: pointer("path")?,
+            },
+            Some("test") => Operation::Test {
+                path: pointer("path")?,
+                value: value()?,
+            },
+            _ => return Err(invalid()),
+        })
+    }
+
+    fn apply(&self, doc: &mut Value, index: usize) -> Result<(), PatchError> {
+        let not_found = || PatchError::NotFound(index);
+        match self {
+            Operation::Add { path, value } => add(doc, path, value.clone()).ok_or_else(not_found),
+            Operation::Remove { path } => remove(doc, path).map(drop).ok_or_else(not_found),
+            Operation::Replace { path, value } => {
+                *doc.get_path_mut(path).ok_or_else(not_found)? = value.clone();
+                Ok(())
+            }
+            Operation::Move { from, path } => {
+                if from.is_ancestor_of(path) {
+                    return Err(PatchError::MoveIntoChild(index));
+                }
+                let value = remove(doc, from).ok_or_else(not_found)?;
+                add(doc, path, value).ok_or_else(not_found)
+            }
+            Operation::Copy { from, path } => {
+                let value = doc.get_path(from).ok_or_else(not_found)?.clone();
+                add(doc, path, value).ok_or_else(not_found)
+            }
+            Operation::Test { path, value } => match doc.get_path(path) {
+                Some(actual) if actual.equivalent(value) => Ok(()),
+                Some(_) => Err(PatchError::TestFailed(index)),
+                None => Err(not_found()),
+            },
+        }
+    }
+
+    /// True if `apply_bytes` can apply this operation while streaming.
+    fn is_streamable(&self) -> bool {
+        match self {
+            Operation::Replace { .. } | Operation::Test { .. } => true,
+            Operation::Add { path, .. } | Operation::Remove { path } => match path.last() {
+                Some(JsonPathSegment::Key(key)) => key != "-",
+                _ => false,
+            },
+            Operation::Move { .. } | Operation::Copy { .. } => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patch(pub Vec<Operation>);
+
+impl Patch {
+    pub fn parse(input: &[u8]) -> Result<Patch, Error> {
+        Ok(Patch::from_value(&Value::parse(input)?)?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<Patch, PatchError> {
+        let operations = value.as_array().ok_or(PatchError::NotAnArray)?;
+        operations
+            .iter()
+            .enumerate()
+            .map(|(i, operation)| Operation::from_value(operation, i))
+            .collect::<Result<_, _>>()
+            .map(Patch)
+    }
+
+    pub fn to_value(&self) -> Value {
+        Value::Array(self.0.iter().map(Operation::to_value).collect())
+    }
+
+    /// Apply every operation in order. If one fails, `doc` is left as it was.
+    pub fn apply(&self, doc: &mut Value) -> Result<(), PatchError> {
+        let mut patched = doc.clone();
+        for (i, operation) in self.0.iter().enumerate() {
+            operation.apply(&mut patched, i)?;
+        }
+        *doc = patched;
+        Ok(())
+    }
+
+    /// True if `apply_bytes` applies this patch in a single streaming pass:
+    /// see the module documentation.
+    pub fn is_streamable(&self) -> bool {
+        if !self.0.iter().all(Operation::is_streamable) {
+            return false;
+        }
+        // Sorting puts descendants straight after their ancestors.
+        let mut paths: Vec<_> = self.0.iter().map(Operation::path).collect();
+        paths.sort();
+        paths.windows(2).all(|pair| !pair[1].starts_with(pair[0]))
+    }
+}
+
+impl fmt::Display for Patch {
+    /// Writes compact JSON.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.to_value().fmt(f)
+    }
+}
+
+fn key_string(segment: &JsonPathSegment<'_>) -> String {
+    match segment {
+        JsonPathSegment::Key(key) => key.to_string(),
+        JsonPathSegment::Index(index) => index.to_string(),
+    }
+}
+
+/// Add or replace an object member, or insert an array element (`-` appends).
+/// Returns `None` if the parent doesn't exist or the index is out of range.
+fn add(doc: &mut Value, path: &[JsonPathSegment<'_>], value: Value) -> Option<()> {
+    let (last, parent) = match path.split_last() {
+        Some(split) => split,
+        None => {
+            *doc = value;
+            return Some(());
+        }
+    };
+    match doc.get_path_mut(parent)? {
+        Value::Object(map) => {
+            map.insert(key_string(last), value);
+        }
+        Value::Array(values) => match last {
+            JsonPathSegment::Index(index) if *index <= values.len() => values.insert(*index, value),
+            JsonPathSegment::Key(key) if key == "-" => values.push(value),
+            _ => return None,
+        },
+        _ => return None,
+    }
+    Some(())
+}
+
+fn remove(doc: &mut Value, path: &[JsonPathSegment<'_>]) -> Option<Value> {
+    let (last, parent) = path.split_last()?;
+    match doc.get_path_mut(parent)? {
+        Value::Object(map) => map.remove(&key_string(last)),
+        Value::Array(values) => match last {
+            JsonPathSegment::Index(index) if *index < values.len() => Some(values.remove(*index)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Apply a patch to the single document in `input`, returning the patched
+/// document. This streams if `patch.is_streamable()`; otherwise the document
+/// is parsed into a `Value` and written back out as compact JSON.
+pub fn apply_bytes(input: &[u8], patch: &Patch) -> Result<Vec<u8>, Error> {
+    if patch.is_streamable() {
+        if let Some(out) = apply_streaming(input, patch)? {
+            return Ok(out);
+        }
+    }
+    let mut doc = Value::parse(input)?;
+    patch.apply(&mut doc)?;
+    Ok(doc.to_string().into_bytes())
+}
+
+/// True if the first `len` segments of the stack match those of `path`.
+fn prefix_matches(
+    stack: &PathStack<'_>,
+    path: &[JsonPathSegment<'_>],
+    len: usize,
+) -> Result<bool, Error> {
+    for (segment, expected) in stack.segments()[..len].iter().zip(path) {
+        let matches = match stack.decode_segment(segment)? {
+            JsonPathSegment::Index(index) => expected.matches_index(index),
+            JsonPathSegment::Key(key) => expected.matches_key(&key),
+        };
+        if !matches {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn is_at(stack: &PathStack<'_>, path: &[JsonPathSegment<'_>]) -> Result<bool, Error> {
+    Ok(path.len() == stack.len() && prefix_matches(stack, path, path.len())?)
+}
+
+struct Container {
+    is_object: bool,
+    /// A member of the object has been kept so far.
+    has_members: bool,
+    /// The offset of the last comma in the container.
+    last_comma: Option<usize>,
+    /// The object's first kept member was removed, so the comma after it has
+    /// to be removed too.
+    skip_comma: bool,
+    /// The keys read so far which lead to a patched path.
+    patched_keys: Vec<String>,
+}
+
+/// The operation acting on the value being read.
+struct Active {
+    operation: usize,
+    /// Where the bytes to replace start: the value, or for a removed member,
+    /// its key or the comma before it.
+    start: usize,
+    depth: usize,
+}
+
+/// `None` if a patched path passes through a duplicate key.
+fn apply_streaming(input: &[u8], patch: &Patch) -> Result<Option<Vec<u8>>, Error> {
+    let operations = &patch.0;
+    let mut done = vec![false; operations.len()];
+    // Test failures are reported after the whole document has been checked
+    // for duplicate keys, in operation order.
+    let mut failed = vec![false; operations.len()];
+    let mut out = Vec::with_capacity(input.len());
+    // Input before this offset has been copied to `out`.
+    let mut copied = 0;
+    let mut stack = PathStack::new(input);
+    let mut containers: Vec<Container> = Vec::new();
+    let mut active: Option<Active> = None;
+    // A member removal which starts at this offset, waiting for its value.
+    let mut removal: Option<(usize, usize)> = None;
+    let mut complete = false;
+
+    for token in TokenStream::new(input) {
+        let token = token?;
+        if complete {
+            return Err(Error::TrailingInput(token.start));
+        }
+        if token.token.is_close() {
+            if let Some(mut container) = containers.pop() {
+                if container.is_object {
+                    // Members added to the object which it didn't have go at
+                    // its end.
+                    for (i, operation) in operations.iter().enumerate() {
+                        if let Operation::Add { path, value } = operation {
+                            if done[i]
+                                || path.len() != stack.len()
+                                || !prefix_matches(&stack, path, path.len() - 1)?
+                            {
+                                continue;
+                            }
+                            out.extend_from_slice(&input[copied..token.start]);
+                            copied = token.start;
+                            if container.has_members {
+                                out.push(b',');
+                            }
+                            let mut member = String::new();
+                            string::write_escaped(&mut member, &key_string(&path[path.len() - 1]))
+                                .expect("writing to a String can't fail");
+                            out.extend_from_slice(member.as_bytes());
+                            out.push(b':');
+                            out.extend_from_slice(value.to_string().as_bytes());
+                            container.has_members = true;
+                            done[i] = true;
+                        }
+                    }
+                }
+            }
+            stack.pop();
+        }
+        if token.is_value_start() && active.is_none() {
+            if let Some((operation, start)) = removal.take() {
+                active = Some(Active {
+                    operation,
+                    start,
+                    depth: token.depth,
+                });
+            } else {
+                for (i, operation) in operations.iter().enumerate() {
+                    let matches = match operation {
+                        Operation::Add { path, .. }
+                        | Operation::Replace { path, .. }
+                        | Operation::Test { path, .. } => is_at(&stack, path)?,
+                        _ => false,
+                    };
+                    if matches {
+                        active = Some(Active {
+                            operation: i,
+                            start: token.start,
+                            depth: token.depth,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+        if let (Token::Comma, Some(container)) = (&token.token, containers.last_mut()) {
+            if container.skip_comma {
+                container.skip_comma = false;
+                out.extend_from_slice(&input[copied..token.start]);
+                copied = token.end;
+            } else {
+                container.last_comma = Some(token.start);
+            }
+        }
+        stack.update(&token.token, token.start, token.context);
+        if token.is_key() {
+            let mut patched = false;
+            for operation in operations {
+                let path = operation.path();
+                if path.len() >= stack.len() && prefix_matches(&stack, path, stack.len())? {
+                    patched = true;
+                    break;
+                }
+            }
+            if let (true, Some(container), Some(segment)) =
+                (patched, containers.last_mut(), stack.last())
+            {
+                let key = key_string(&stack.decode_segment(segment)?);
+                if container.patched_keys.contains(&key) {
+                    return Ok(None);
+                }
+                container.patched_keys.push(key);
+            }
+            let removed =
+                operations
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, operation)| match operation {
+                        Operation::Remove { path } => match is_at(&stack, path) {
+                            Ok(true) => Some(Ok(i)),
+                            Ok(false) => None,
+                            Err(e) => Some(Err(e)),
+                        },
+                        _ => None,
+                    });
+            if let Some(container) = containers.last_mut() {
+                match removed.transpose()? {
+                    Some(i) if container.has_members => {
+                        removal = Some((i, container.last_comma.unwrap_or(token.start)));
+                    }
+                    Some(i) => {
+                        container.skip_comma = true;
+                        removal = Some((i, token.start));
+                    }
+                    None => container.has_members = true,
+                }
+            }
+        }
+        if token.token.is_open() {
+            containers.push(Container {
+                is_object: matches!(token.token, Token::ObjectOpen),
+                has_members: false,
+                last_comma: None,
+                skip_comma: false,
+                patched_keys: Vec::new(),
+            });
+        }
+        if token.is_value_end() {
+            if let Some(Active {
+                operation: i,
+                start,
+                depth,
+            }) = active
+            {
+                if depth == token.depth {
+                    active = None;
+                    done[i] = true;
+                    match &operations[i] {
+                        Operation::Test { value, .. } => {
+                            if !Value::parse(&input[start..token.end])?.equivalent(value) {
+                                failed[i] = true;
+                            }
+                        }
+                        Operation::Add { value, .. } | Operation::Replace { value, .. } => {
+                            out.extend_from_slice(&input[copied..start]);
+                            out.extend_from_slice(value.to_string().as_bytes());
+                            copied = token.end;
+                        }
+                        _ => {
+                            out.extend_from_slice(&input[copied..start]);
+                            copied = token.end;
+                        }
+                    }
+                }
+            }
+            complete = token.depth == 0;
+        }
+    }
+    if !complete {
+        return Err(Error::UnexpectedEndOfInput);
+    }
+    for i in 0..operations.len() {
+        if !done[i] {
+            return Err(PatchError::NotFound(i).into());
+        }
+        if failed[i] {
+            return Err(PatchError::TestFailed(i).into());
+        }
+    }
+    out.extend_from_slice(&input[copied..]);
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patched(doc: &str, patch: &str) -> Result<Value, PatchError> {
+        let mut doc = Value::parse(doc.as_bytes()).unwrap();
+        Patch::parse(patch.as_bytes()).unwrap().apply(&mut doc)?;
+        Ok(doc)
+    }
+
+    fn value(json: &str) -> Value {
+        Value::parse(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn rfc_examples() {
+        // RFC 6902 appendix A.
+        for &(doc, patch, expected) in &[
+            (
+                r#"{"foo": "bar"}"#,
+                r#"[{"op": "add", "path": "/baz", "value": "qux"}]"#,
+                r#"{"baz": "qux", "foo": "bar"}"#,
+            ),
+            (
+                r#"{"foo": ["bar", "baz"]}"#,
+                r#"[{"op": "add", "path": "/foo/1", "value": "qux"}]"#,
+                r#"{"foo": ["bar", "qux", "baz"]}"#,
+            ),
+            (
+                r#"{"foo": ["bar", "qux", "baz"]}"#,
+                r#"[{"op": "remove", "path": "/foo/1"}]"#,
+                r#"{"foo": ["bar", "baz"]}"#,
+            ),
+            (
+                r#"{"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}}"#,
+                r#"[{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"}]"#,
+                r#"{"foo": {"bar": "baz"}, "qux": {"corge": "grault", "thud": "fred"}}"#,
+            ),
+            (
+                r#"{"foo": ["all", "grass", "cows", "eat"]}"#,
+                r#"[{"op": "move", "from": "/foo/1", "path": "/foo/3"}]"#,
+                r#"{"foo": ["all", "cows", "eat", "grass"]}"#,
+            ),
+            (
+                r#"{"baz": "qux", "foo": ["a", 2, "c"]}"#,
+                r#"[{"op": "test", "path": "/baz", "value": "qux"},
+                    {"op": "test", "path": "/foo/1", "value": 2.0}]"#,
+                r#"{"baz": "qux", "foo": ["a", 2, "c"]}"#,
+            ),
+            (
+                r#"{"foo": "bar"}"#,
+                r#"[{"op": "add", "path": "/child", "value": {"grandchild": {}}}]"#,
+                r#"{"foo": "bar", "child": {"grandchild": {}}}"#,
+            ),
+            (
+                r#"{"foo": ["bar"]}"#,
+                r#"[{"op": "add", "path": "/foo/-", "value": ["abc", "def"]}]"#,
+                r#"{"foo": ["bar", ["abc", "def"]]}"#,
+            ),
+            (
+                r#"{"/": 9, "~1": 10}"#,
+                r#"[{"op": "test", "path": "/~01", "value": 10},
+                    {"op": "copy", "from": "/~1", "path": "/0"}]"#,
+                r#"{"/": 9, "~1": 10, "0": 9}"#,
+            ),
+        ] {
+            assert!(patched(doc, patch).unwrap().equivalent(&value(expected)));
+        }
+        assert_eq!(
+            patched(
+                r#"{"baz": "qux"}"#,
+                r#"[{"op": "test", "path": "/baz", "value": "bar"}]"#
+            ),
+            Err(PatchError::TestFailed(0))
+        );
+        assert_eq!(
+            patched(
+                r#"{"foo": "bar"}"#,
+                r#"[{"op": "add", "path": "/baz/bat", "value": "qux"}]"#
+            ),
+            Err(PatchError::NotFound(0))
+        );
+        assert!(matches!(
+            Patch::parse(br#"[{"op": "add", "path": "/a"}]"#),
+            Err(Error::Patch(PatchError::Invalid(0)))
+        ));
+    }
+
+    #[test]
+    fn atomic() {
+        let patch = Patch::parse(
+            br#"[{"op": "replace", "path": "/a", "value": 2},
+                 {"op": "move", "from": "/a", "path": "/a/b"}]"#,
+        )
+        .unwrap();
+        let mut doc = value(r#"{"a": 1}"#);
+        assert_eq!(patch.apply(&mut doc), Err(PatchError::MoveIntoChild(1)));
+        assert_eq!(doc, value(r#"{"a": 1}"#));
+        assert_eq!(Patch::parse(patch.to_string().as_bytes()).unwrap(), patch);
+    }
+
+    #[test]
+    fn streams_disjoint_paths() {
+        let input = br#"{
+  "keep": [1, 2],
+  "gone": {"x": 1},
+  "n": 1.0,
+  "nested": {"first": true, "second": false}
+}"#;
+        let patch = Patch::parse(
+            br#"[{"op": "replace", "path": "/keep/1", "value": {"b": null}},
+                 {"op": "remove", "path": "/gone"},
+                 {"op": "test", "path": "/n", "value": 1},
+                 {"op": "remove", "path": "/nested/first"},
+                 {"op": "add", "path": "/nested/third", "value": "3"},
+                 {"op": "add", "path": "/nested/second", "value": 2}]"#,
+        )
+        .unwrap();
+        assert!(patch.is_streamable());
+        let output = apply_bytes(input, &patch).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{
+  "keep": [1, {"b":null}],
+  "n": 1.0,
+  "nested": { "second": 2,"third":"3"}
+}"#
+        );
+
+        let mut doc = Value::parse(input).unwrap();
+        patch.apply(&mut doc).unwrap();
+        let output = apply_bytes(input, &patch).unwrap();
+        assert!(Value::parse(&output).unwrap().equivalent(&doc));
+
+        let overlapping = Patch::parse(
+            br#"[{"op": "add", "path": "/nested/x", "value": 1},
+                 {"op": "test", "path": "/nested", "value": {}}]"#,
+        )
+        .unwrap();
+        assert!(!overlapping.is_streamable());
+        let missing =
+            Patch::parse(br#"[{"op": "replace", "path": "/missing", "value": 1}]"#).unwrap();
+        assert!(matches!(
+            apply_bytes(input, &missing),
+            Err(Error::Patch(PatchError::NotFound(0)))
+        ));
+        let failing = Patch::parse(br#"[{"op": "test", "path": "/n", "value": 2}]"#).unwrap();
+        assert!(matches!(
+            apply_bytes(input, &failing),
+            Err(Error::Patch(PatchError::TestFailed(0)))
+        ));
+    }
+
+    #[test]
+    fn duplicate_keys_agree() {
+        let input = br#"{"a": {"x": 1}, "b": 1, "a": {"y": 2}, "b": 2, "c": 3, "c": 4}"#;
+        for patch in &[
+            r#"[{"op": "replace", "path": "/b", "value": 0}]"#,
+            r#"[{"op": "remove", "path": "/b"}]"#,
+            r#"[{"op": "test", "path": "/b", "value": 2}]"#,
+            r#"[{"op": "test", "path": "/b", "value": 1}]"#,
+            r#"[{"op": "add", "path": "/a/z", "value": 0}]"#,
+            r#"[{"op": "replace", "path": "/a/x", "value": 0}]"#,
+            r#"[{"op": "replace", "path": "/a/y", "value": 0}]"#,
+            r#"[{"op": "remove", "path": "/d"}, {"op": "test", "path": "/c", "value": 3}]"#,
+        ] {
+            let patch = Patch::parse(patch.as_bytes()).unwrap();
+            assert!(patch.is_streamable());
+            let mut doc = Value::parse(input).unwrap();
+            let expected = patch.apply(&mut doc).map(|()| doc);
+            let output = apply_bytes(input, &patch).map(|out| Value::parse(&out).unwrap());
+            match (expected, output) {
+                (Ok(expected), Ok(output)) => assert!(output.equivalent(&expected), "{}", patch),
+                (Err(expected), Err(Error::Patch(error))) => {
+                    assert_eq!(error, expected, "{}", patch)
+                }
+                (expected, output) => panic!("{}: {:?} != {:?}", patch, output, expected),
+            }
+        }
+
+        // Duplicates off the patched paths still stream.
+        let patch = Patch::parse(br#"[{"op": "replace", "path": "/a/x", "value": 0}]"#).unwrap();
+        let unique_a = br#"{"a": {"x": 1}, "c": 3, "c": 4}"#;
+        assert_eq!(
+            apply_streaming(unique_a, &patch).unwrap().unwrap(),
+            br#"{"a": {"x": 0}, "c": 3, "c": 4}"#.to_vec()
+        );
+        assert_eq!(apply_streaming(input, &patch).unwrap(), None);
+    }
+}
@@ -0,0 +1,235 @@
+//! Tracking the path to the current token without allocating per token.
+//!
+//! `PathStack` stores keys as byte ranges into the source buffer and only
+//! decodes them when the path is snapshotted with `to_path` or
+//! `to_owned_path`, so pushing, popping and updating segments are all O(1).
+
+use crate::string::{self, StringError};
+use crate::tokenizer::Token;
+use crate::validator::ValidationContext;
+use crate::{JsonPath, JsonPathSegment};
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackSegment {
+    Index(usize),
+    /// The byte range of the raw key token (including quotation marks) in the
+    /// source. An empty range is the placeholder pushed at the start of an object.
+    Key(Range<usize>),
+}
+
+/// A mutable path into a document.
+///
+/// `update` follows the same rules as the hand-written traversals in the
+/// examples: call `pop` when a container closes (before any post-order
+/// processing) and `update` after handling each token.
+#[derive(Clone, Debug)]
+pub struct PathStack<'a> {
+    source: &'a [u8],
+    segments: Vec<StackSegment>,
+}
+
+impl<'a> PathStack<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        PathStack {
+            source,
+            segments: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    #[inline]
+    pub fn segments(&self) -> &[StackSegment] {
+        &self.segments
+    }
+
+    #[inline]
+    pub fn last(&self) -> Option<&StackSegment> {
+        self.segments.last()
+    }
+
+    #[inline]
+    pub fn push_index(&mut self, index: usize) {
+        self.segments.push(StackSegment::Index(index));
+    }
+
+    #[inline]
+    pub fn push_key(&mut self, key: Range<usize>) {
+        self.segments.push(StackSegment::Key(key));
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<StackSegment> {
+        self.segments.pop()
+    }
+
+    /// Replace the last segment's key. Does nothing if the last segment is an index.
+    #[inline]
+    pub fn set_key(&mut self, key: Range<usize>) {
+        if let Some(StackSegment::Key(ref mut range)) = self.segments.last_mut() {
+            *range = key;
+        }
+    }
+
+    /// Increment the last segment's index. Does nothing if the last segment is a key.
+    #[inline]
+    pub fn increment_index(&mut self) {
+        if let Some(StackSegment::Index(ref mut n)) = self.segments.last_mut() {
+            *n += 1;
+        }
+    }
+
+    /// The raw key token for a key segment, including the quotation marks.
+    #[inline]
+    pub fn raw_key(&self, segment: &StackSegment) -> Option<&'a [u8]> {
+        match segment {
+            StackSegment::Key(range) => Some(&self.source[range.clone()]),
+            StackSegment::Index(_) => None,
+        }
+    }
+
+    /// True if the last segment is a key which decodes to `key`.
+    pub fn last_key_is(&self, key: &str) -> bool {
+        match self.last().and_then(|segment| self.raw_key(segment)) {
+            Some([]) => key.is_empty(),
+            Some(raw) => match string::strip_quotes(raw) {
+                // Fast path: no escapes means the raw bytes are the key.
+                Ok(inner) if !inner.contains(&b'\\') => inner == key.as_bytes(),
+                _ => matches!(string::decode(raw), Ok(ref k) if k == key),
+            },
+            None => false,
+        }
+    }
+
+    /// Decode a single segment, borrowing from the source when possible.
+    pub fn decode_segment(
+        &self,
+        segment: &StackSegment,
+    ) -> Result<JsonPathSegment<'a>, StringError> {
+        Ok(match segment {
+            StackSegment::Index(n) => JsonPathSegment::Index(*n),
+            StackSegment::Key(range) if range.start == range.end => crate::EMPTY_KEY,
+            StackSegment::Key(range) => {
+                JsonPathSegment::Key(string::decode(&self.source[range.clone()])?)
+            }
+        })
+    }
+
+    /// Snapshot the first `len` segments, borrowing keys from the source when possible.
+    pub fn prefix_path(&self, len: usize) -> Result<JsonPath<'a>, StringError> {
+        let segments = self.segments[..len]
+            .iter()
+            .map(|segment| self.decode_segment(segment))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(JsonPath::new(Cow::Owned(segments)))
+    }
+
+    /// Snapshot the whole path, borrowing keys from the source when possible.
+    pub fn to_path(&self) -> Result<JsonPath<'a>, StringError> {
+        self.prefix_path(self.len())
+    }
+
+    /// Snapshot the whole path into one which doesn't borrow the source.
+    pub fn to_owned_path(&self) -> Result<JsonPath<'static>, StringError> {
+        self.to_path().map(JsonPath::into_owned)
+    }
+
+    /// Update the path after processing a token, where `start` is the token's
+    /// offset in the source and `context` is the validator's context after
+    /// processing it.
+    ///
+    /// Path changes occur:
+    /// - At the start of an array, push 0
+    /// - After an array value, increment
+    /// - At the start of an object, push an empty key
+    /// - At an object key, change the key
+    ///
+    /// Closing a container is left to the caller's `pop`.
+    #[inline]
+    pub fn update(&mut self, token: &Token<'a>, start: usize, context: Option<ValidationContext>) {
+        match context {
+            Some(ValidationContext::ObjectStart) => self.push_key(0..0),
+            Some(ValidationContext::ObjectEntryKey) => {
+                if let Token::String(raw) = token {
+                    self.set_key(start..start + raw.len());
+                }
+            }
+            Some(ValidationContext::ArrayStart) => self.push_index(0),
+            Some(ValidationContext::ArrayValue) => self.increment_index(),
+            _ => (),
+        }
+    }
+}
+
+impl<'a> fmt::Display for PathStack<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@")?;
+        for segment in &self.segments {
+            match segment {
+                StackSegment::Index(n) => write!(f, ".{}", n)?,
+                StackSegment::Key(range) => write!(
+                    f,
+                    ".{}",
+                    String::from_utf8_lossy(&self.source[range.clone()])
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::TokenStream;
+    use crate::Error;
+
+    #[test]
+    fn tracks_paths() -> Result<(), Error> {
+        let input = br#"{"a": [1, {"b\/c": 2}], "d": 3}"#;
+        let mut stack = PathStack::new(input);
+        let mut seen = Vec::new();
+        for token in TokenStream::new(input) {
+            let token = token?;
+            if token.token.is_close() {
+                stack.pop();
+            }
+            if token.is_value_start() && token.token.is_complete_value() {
+                seen.push(stack.to_owned_path()?.to_pointer());
+            }
+            stack.update(&token.token, token.start, token.context);
+        }
+        assert_eq!(seen, vec!["/a/0", "/a/1/b~1c", "/d"]);
+        assert!(stack.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn last_key() {
+        let input = br#""url" "\u0075rl""#;
+        let mut stack = PathStack::new(input);
+        stack.push_key(0..5);
+        assert!(stack.last_key_is("url"));
+        stack.set_key(6..16);
+        assert!(stack.last_key_is("url"));
+        assert!(!stack.last_key_is("title"));
+        assert_eq!(stack.to_string(), r#"@."\u0075rl""#);
+    }
+}
@@ -0,0 +1,556 @@
+//! Wildcard patterns over `JsonPath`s.
+//!
+//! Pattern syntax, with segments separated by `.`:
+//! - `name` or `"name"` matches an object key (quoted keys are JSON strings,
+//!   so they may contain `.` or escapes)
+//! - `*` matches any object key
+//! - `[]` matches any array index, `[3]` a single index and `[2..5]` a
+//!   half-open range of indices. Brackets may follow a key directly: `items[].id`
+//! - `**` matches zero or more segments of any kind
+//!
+//! A leading `@` (as printed by `JsonPath`'s `Display`) is ignored.
+//!
+//! Keys are compared against the decoded key, not the raw token text.
+
+use crate::string;
+use crate::JsonPathSegment;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatternError {
+    UnexpectedEndOfInput,
+    UnexpectedCharacter { position: usize, found: char },
+    InvalidIndex(usize),
+    InvalidKey(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PatternSegment {
+    Key(String),
+    AnyKey,
+    AnyIndex,
+    Index(Range<usize>),
+    Recursive,
+}
+
+impl PatternSegment {
+    pub fn matches(&self, segment: &JsonPathSegment<'_>) -> bool {
+        match (self, segment) {
+            (PatternSegment::Key(key), JsonPathSegment::Key(k)) => key == k,
+            (PatternSegment::AnyKey, JsonPathSegment::Key(_)) => true,
+            (PatternSegment::AnyIndex, JsonPathSegment::Index(_)) => true,
+            (PatternSegment::Index(range), JsonPathSegment::Index(i)) => range.contains(i),
+            (PatternSegment::Recursive, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for PatternSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternSegment::Key(key) => {
+                // A leading `@` would be skipped by the parser.
+                let is_plain = !key.is_empty()
+                    && !key.starts_with('@')
+                    && key != "*"
+                    && key != "**"
+                    && !key.contains(&['.', '[', ']', '"', '\\'][..]);
+                if is_plain {
+                    return write!(f, "{}", key);
+                }
+                write!(f, "\"")?;
+                for c in key.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            PatternSegment::AnyKey => write!(f, "*"),
+            PatternSegment::AnyIndex => write!(f, "[]"),
+            PatternSegment::Index(range) if range.start.checked_add(1) == Some(range.end) => {
+                write!(f, "[{}]", range.start)
+            }
+            PatternSegment::Index(range) => write!(f, "[{}..{}]", range.start, range.end),
+            PatternSegment::Recursive => write!(f, "**"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PathPattern {
+    segments: Vec<PatternSegment>,
+}
+
+impl PathPattern {
+    pub fn new(segments: Vec<PatternSegment>) -> Self {
+        PathPattern { segments }
+    }
+
+    pub fn parse(pattern: &str) -> Result<PathPattern, PatternError> {
+        Parser { src: pattern, n: 0 }.parse()
+    }
+
+    pub fn segments(&self) -> &[PatternSegment] {
+        &self.segments
+    }
+
+    /// True if the whole of `path` matches this pattern.
+    pub fn matches(&self, path: &[JsonPathSegment<'_>]) -> bool {
+        Self::matches_from(&self.segments, path)
+    }
+
+    fn matches_from(pattern: &[PatternSegment], path: &[JsonPathSegment<'_>]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((PatternSegment::Recursive, rest)) => {
+                (0..=path.len()).any(|skip| Self::matches_from(rest, &path[skip..]))
+            }
+            Some((segment, rest)) => match path.split_first() {
+                Some((first, path)) => segment.matches(first) && Self::matches_from(rest, path),
+                None => false,
+            },
+        }
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PathPattern::parse(s)
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            let is_index = matches!(segment, PatternSegment::AnyIndex | PatternSegment::Index(_));
+            if i > 0 && !is_index {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'s> {
+    src: &'s str,
+    n: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.n..].chars().next()
+    }
+
+    fn unexpected(&self) -> PatternError {
+        match self.peek() {
+            Some(found) => PatternError::UnexpectedCharacter {
+                position: self.n,
+                found,
+            },
+            None => PatternError::UnexpectedEndOfInput,
+        }
+    }
+
+    fn parse(mut self) -> Result<PathPattern, PatternError> {
+        let mut segments = Vec::new();
+        if self.src.starts_with('@') {
+            self.n += 1;
+            if self.peek() == Some('.') {
+                self.n += 1;
+            }
+        }
+        if self.n == self.src.len() {
+            return Ok(PathPattern::new(segments));
+        }
+        loop {
+            match self.peek() {
+                Some('[') => {}
+                Some('"') => segments.push(PatternSegment::Key(self.parse_quoted()?)),
+                Some(_) => segments.push(self.parse_bare()?),
+                None => return Err(self.unexpected()),
+            }
+            while self.peek() == Some('[') {
+                segments.push(self.parse_index()?);
+            }
+            match self.peek() {
+                Some('.') => self.n += 1,
+                None => return Ok(PathPattern::new(segments)),
+                Some(_) => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn parse_bare(&mut self) -> Result<PatternSegment, PatternError> {
+        let start = self.n;
+        while let Some(c) = self.peek() {
+            if c == '.' || c == '[' {
+                break;
+            }
+            if c == ']' || c == '"' {
+                return Err(self.unexpected());
+            }
+            self.n += c.len_utf8();
+        }
+        Ok(match &self.src[start..self.n] {
+            "" => return Err(self.unexpected()),
+            "*" => PatternSegment::AnyKey,
+            "**" => PatternSegment::Recursive,
+            key => PatternSegment::Key(key.to_owned()),
+        })
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, PatternError> {
+        let start = self.n;
+        let bytes = self.src.as_bytes();
+        let mut end = start + 1;
+        loop {
+            match bytes.get(end) {
+                Some(b'"') => break,
+                Some(b'\\') => end += 2,
+                Some(_) => end += 1,
+                None => return Err(PatternError::UnexpectedEndOfInput),
+            }
+        }
+        self.n = end + 1;
+        string::decode(&bytes[start..self.n])
+            .map(|key| key.into_owned())
+            .map_err(|_| PatternError::InvalidKey(start))
+    }
+
+    fn parse_number(&mut self) -> Result<usize, PatternError> {
+        let start = self.n;
+        while let Some('0'..='9') = self.peek() {
+            self.n += 1;
+        }
+        self.src[start..self.n]
+            .parse()
+            .map_err(|_| PatternError::InvalidIndex(start))
+    }
+
+    fn parse_index(&mut self) -> Result<PatternSegment, PatternError> {
+        // Skip the '['
+        self.n += 1;
+        if self.src[self.n..].starts_with(']') {
+            self.n += 1;
+            return Ok(PatternSegment::AnyIndex);
+        }
+        let position = self.n;
+        let start = self.parse_number()?;
+        let end = if self.src[self.n..].starts_with("..") {
+            self.n += 2;
+            self.parse_number()?
+        } else {
+            start
+                .checked_add(1)
+                .ok_or(PatternError::InvalidIndex(position))?
+        };
+        if self.peek() != Some(']') {
+            return Err(self.unexpected());
+        }
+        self.n += 1;
+        Ok(PatternSegment::Index(start..end))
+    }
+}
+
+type NodeId = usize;
+
+#[derive(Debug, Default)]
+struct Node {
+    keys: HashMap<String, NodeId>,
+    indices: Vec<(Range<usize>, NodeId)>,
+    any_key: Option<NodeId>,
+    any_index: Option<NodeId>,
+    /// The node reached by `**`. It consumes zero segments, so it is always
+    /// active alongside this node.
+    recursive: Option<NodeId>,
+    /// True for nodes reached by `**`, which stay active for every segment.
+    self_loop: bool,
+    /// The ids of the patterns which end at this node.
+    accepts: Vec<usize>,
+}
+
+impl Node {
+    fn has_edges(&self) -> bool {
+        self.self_loop
+            || !self.keys.is_empty()
+            || !self.indices.is_empty()
+            || self.any_key.is_some()
+            || self.any_index.is_some()
+    }
+}
+
+/// Many `PathPattern`s compiled into a single trie, which is walked one path
+/// segment at a time. Checking `could_match_descendant` as the path grows
+/// lets a caller skip subtrees where nothing can match.
+#[derive(Debug)]
+pub struct PathSet {
+    nodes: Vec<Node>,
+    patterns: Vec<PathPattern>,
+}
+
+/// The set of trie nodes active at a path. Obtain one from `PathSet::root`
+/// and advance it with `PathSet::step` as the path grows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathState {
+    nodes: Vec<NodeId>,
+}
+
+impl PathState {
+    /// True if no pattern can match this path or any of its descendants.
+    pub fn is_dead(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl Default for PathSet {
+    fn default() -> Self {
+        PathSet {
+            nodes: vec![Node::default()],
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl PathSet {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<PathSet, PatternError> {
+        let mut set = PathSet::default();
+        for pattern in patterns {
+            set.insert(pattern.as_ref().parse()?);
+        }
+        Ok(set)
+    }
+
+    /// Add a pattern and return its id.
+    pub fn insert(&mut self, pattern: PathPattern) -> usize {
+        let id = self.patterns.len();
+        let mut node = 0;
+        for segment in pattern.segments() {
+            node = self.child(node, segment);
+        }
+        self.nodes[node].accepts.push(id);
+        self.patterns.push(pattern);
+        id
+    }
+
+    pub fn patterns(&self) -> &[PathPattern] {
+        &self.patterns
+    }
+
+    fn new_node(&mut self, self_loop: bool) -> NodeId {
+        self.nodes.push(Node {
+            self_loop,
+            ..Node::default()
+        });
+        self.nodes.len() - 1
+    }
+
+    fn child(&mut self, node: NodeId, segment: &PatternSegment) -> NodeId {
+        let existing = match segment {
+            PatternSegment::Key(key) => self.nodes[node].keys.get(key).cloned(),
+            PatternSegment::AnyKey => self.nodes[node].any_key,
+            PatternSegment::AnyIndex => self.nodes[node].any_index,
+            PatternSegment::Index(range) => self.nodes[node]
+                .indices
+                .iter()
+                .find(|(r, _)| r == range)
+                .map(|(_, id)| *id),
+            PatternSegment::Recursive => self.nodes[node].recursive,
+        };
+        if let Some(id) = existing {
+            return id;
+        }
+        let id = self.new_node(*segment == PatternSegment::Recursive);
+        let parent = &mut self.nodes[node];
+        match segment {
+            PatternSegment::Key(key) => {
+                parent.keys.insert(key.clone(), id);
+            }
+            PatternSegment::AnyKey => parent.any_key = Some(id),
+            PatternSegment::AnyIndex => parent.any_index = Some(id),
+            PatternSegment::Index(range) => parent.indices.push((range.clone(), id)),
+            PatternSegment::Recursive => parent.recursive = Some(id),
+        }
+        id
+    }
+
+    /// Add the nodes reachable through `**` without consuming a segment.
+    fn close(&self, mut nodes: Vec<NodeId>) -> PathState {
+        let mut i = 0;
+        while i < nodes.len() {
+            if let Some(recursive) = self.nodes[nodes[i]].recursive {
+                nodes.push(recursive);
+            }
+            i += 1;
+        }
+        nodes.sort_unstable();
+        nodes.dedup();
+        PathState { nodes }
+    }
+
+    /// The state for the empty path.
+    pub fn root(&self) -> PathState {
+        self.close(vec![0])
+    }
+
+    /// Advance `state` by one path segment.
+    pub fn step(&self, state: &PathState, segment: &JsonPathSegment<'_>) -> PathState {
+        let mut next = Vec::new();
+        for &id in &state.nodes {
+            let node = &self.nodes[id];
+            if node.self_loop {
+                next.push(id);
+            }
+            match segment {
+                JsonPathSegment::Key(key) => {
+                    next.extend(node.keys.get(key.as_ref()));
+                    next.extend(node.any_key);
+                }
+                JsonPathSegment::Index(index) => {
+                    next.extend(node.any_index);
+                    next.extend(
+                        node.indices
+                            .iter()
+                            .filter(|(range, _)| range.contains(index))
+                            .map(|(_, id)| *id),
+                    );
+                }
+            }
+        }
+        self.close(next)
+    }
+
+    /// Walk `path` from the root.
+    pub fn state_for(&self, path: &[JsonPathSegment<'_>]) -> PathState {
+        path.iter()
+            .fold(self.root(), |state, segment| self.step(&state, segment))
+    }
+
+    /// True if any pattern matches the path `state` was built from.
+    pub fn is_match(&self, state: &PathState) -> bool {
+        state
+            .nodes
+            .iter()
+            .any(|&id| !self.nodes[id].accepts.is_empty())
+    }
+
+    /// The ids of every pattern which matches the path `state` was built from.
+    pub fn matching_patterns(&self, state: &PathState) -> Vec<usize> {
+        let mut ids: Vec<usize> = state
+            .nodes
+            .iter()
+            .flat_map(|&id| self.nodes[id].accepts.iter().cloned())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// True if any pattern could match a strict descendant of the path
+    /// `state` was built from. When this is false the subtree can be skipped.
+    pub fn could_match_descendant(&self, state: &PathState) -> bool {
+        state.nodes.iter().any(|&id| self.nodes[id].has_edges())
+    }
+
+    /// Convenience for `is_match(&state_for(path))`.
+    pub fn matches(&self, path: &[JsonPathSegment<'_>]) -> bool {
+        self.is_match(&self.state_for(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(segments: &[&str]) -> Vec<JsonPathSegment<'static>> {
+        segments
+            .iter()
+            .map(|s| match s.parse::<usize>() {
+                Ok(i) => JsonPathSegment::Index(i),
+                Err(_) => JsonPathSegment::Key(s.to_string().into()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for pattern in &[
+            "a.b",
+            "items[].id",
+            "**.url",
+            "*[2..5]",
+            "a[0]",
+            "\"a.b\".c",
+            "\"@a\".b",
+            "a.\"@b\"",
+            "[18446744073709551614]",
+            "",
+        ] {
+            let parsed = PathPattern::parse(pattern).unwrap();
+            assert_eq!(&parsed.to_string(), pattern);
+        }
+        assert_eq!(
+            PathPattern::parse("@.cookies[]").unwrap().segments(),
+            &[
+                PatternSegment::Key("cookies".into()),
+                PatternSegment::AnyIndex
+            ]
+        );
+        assert!(PathPattern::parse("a..b").is_err());
+        assert!(PathPattern::parse("a[x]").is_err());
+        assert_eq!(
+            PathPattern::parse("[18446744073709551615]"),
+            Err(PatternError::InvalidIndex(1))
+        );
+        let pattern = PathPattern::new(vec![PatternSegment::Key("@a".into())]);
+        assert_eq!(pattern.to_string().parse(), Ok(pattern));
+    }
+
+    #[test]
+    fn pattern_matches() {
+        let pattern = PathPattern::parse("**.cookies[].name").unwrap();
+        assert!(pattern.matches(&path(&["cookies", "0", "name"])));
+        assert!(pattern.matches(&path(&["a", "3", "cookies", "1", "name"])));
+        assert!(!pattern.matches(&path(&["cookies", "name"])));
+
+        let pattern = PathPattern::parse("items[1..3].*").unwrap();
+        assert!(pattern.matches(&path(&["items", "2", "x"])));
+        assert!(!pattern.matches(&path(&["items", "3", "x"])));
+    }
+
+    #[test]
+    fn path_set() {
+        let set = PathSet::new(&["**.cookies", "items[].id", "items[0]"]).unwrap();
+        assert!(set.matches(&path(&["cookies"])));
+        assert!(set.matches(&path(&["a", "b", "cookies"])));
+        assert!(set.matches(&path(&["items", "4", "id"])));
+
+        let items = set.state_for(&path(&["items"]));
+        assert!(!set.is_match(&items));
+        assert!(set.could_match_descendant(&items));
+
+        let first = set.step(&items, &JsonPathSegment::Index(0));
+        assert_eq!(set.matching_patterns(&first), vec![2]);
+
+        let id = set.step(&first, &JsonPathSegment::Key("id".into()));
+        assert_eq!(set.matching_patterns(&id), vec![1]);
+
+        // Only `**.cookies` could still match below an id.
+        assert!(set.could_match_descendant(&id));
+        let set = PathSet::new(&["items[].id"]).unwrap();
+        let id = set.state_for(&path(&["items", "0", "id"]));
+        assert!(set.is_match(&id));
+        assert!(!set.could_match_descendant(&id));
+        assert!(set.state_for(&path(&["other"])).is_dead());
+    }
+}
@@ -0,0 +1,334 @@
+//! RFC 6901 JSON Pointer support for `JsonPath`.
+//!
+//! Keys in a `JsonPath` produced here are the decoded key values, not the
+//! raw (quoted) token text. Because a pointer doesn't say whether a segment
+//! is an array index or an object key, segments which look like an array
+//! index (`0` or a number without leading zeros) become `JsonPathSegment::Index`
+//! and everything else becomes `JsonPathSegment::Key`. Lookups treat an index
+//! segment against an object as the equivalent key, so both resolve the same way.
+
+use crate::stream::TokenStream;
+use crate::string;
+use crate::tokenizer::Token;
+use crate::{Error, JsonPath, JsonPathSegment};
+
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PointerError {
+    /// A non-empty pointer must start with `/`.
+    MissingLeadingSlash,
+    /// A `~` which wasn't followed by `0` or `1`.
+    InvalidEscape(usize),
+}
+
+impl fmt::Display for PointerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PointerError::MissingLeadingSlash => f.write_str("missing leading '/'"),
+            PointerError::InvalidEscape(offset) => {
+                write!(f, "invalid '~' escape at offset {}", offset)
+            }
+        }
+    }
+}
+
+/// Escape a key for use as a reference token: `~` becomes `~0` and `/` becomes `~1`.
+pub fn escape_token(key: &str) -> Cow<'_, str> {
+    if !key.contains(&['~', '/'][..]) {
+        return Cow::Borrowed(key);
+    }
+    let mut out = String::with_capacity(key.len() + 2);
+    for c in key.chars() {
+        match c {
+            '~' => out.push_str("~0"),
+            '/' => out.push_str("~1"),
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// The inverse of `escape_token`. `~1` is replaced before `~0` so that `~01`
+/// decodes to `~1` and not `/`.
+pub fn unescape_token(token: &str) -> Result<Cow<'_, str>, PointerError> {
+    let first = match token.find('~') {
+        Some(n) => n,
+        None => return Ok(Cow::Borrowed(token)),
+    };
+    let mut out = String::with_capacity(token.len());
+    out.push_str(&token[..first]);
+    let mut chars = token[first..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some((_, '0')) => out.push('~'),
+            Some((_, '1')) => out.push('/'),
+            _ => return Err(PointerError::InvalidEscape(first + i)),
+        }
+    }
+    Ok(Cow::Owned(out))
+}
+
+/// True if `token` follows the array-index grammar of RFC 6901: `0` or a
+/// number without leading zeros.
+fn is_array_index(token: &str) -> bool {
+    match token.as_bytes() {
+        [b'0'] => true,
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+impl<'a> JsonPathSegment<'a> {
+    /// Returns true if this segment refers to the object member named `key`.
+    /// Index segments match their decimal representation.
+    pub fn matches_key(&self, key: &str) -> bool {
+        match self {
+            JsonPathSegment::Key(k) => k == key,
+            JsonPathSegment::Index(index) => {
+                is_array_index(key) && key.parse::<usize>().ok() == Some(*index)
+            }
+        }
+    }
+
+    /// Returns true if this segment refers to the array element at `index`.
+    pub fn matches_index(&self, index: usize) -> bool {
+        self.as_index() == Some(index)
+    }
+
+    fn from_pointer_token(token: &str) -> Result<JsonPathSegment<'static>, PointerError> {
+        if is_array_index(token) {
+            if let Ok(index) = token.parse() {
+                return Ok(JsonPathSegment::Index(index));
+            }
+        }
+        Ok(JsonPathSegment::Key(
+            unescape_token(token)?.into_owned().into(),
+        ))
+    }
+}
+
+impl<'a> JsonPath<'a> {
+    /// Parse a JSON Pointer such as `/a/0/b~1c`. The empty string refers to
+    /// the whole document.
+    pub fn from_pointer(pointer: &str) -> Result<JsonPath<'static>, PointerError> {
+        if pointer.is_empty() {
+            return Ok(JsonPath::new(Cow::Owned(Vec::new())));
+        }
+        if !pointer.starts_with('/') {
+            return Err(PointerError::MissingLeadingSlash);
+        }
+        let mut segments = Vec::new();
+        let mut offset = 1;
+        for token in pointer[1..].split('/') {
+            segments.push(
+                JsonPathSegment::from_pointer_token(token).map_err(|e| match e {
+                    PointerError::InvalidEscape(n) => PointerError::InvalidEscape(offset + n),
+                    e => e,
+                })?,
+            );
+            offset += token.len() + 1;
+        }
+        Ok(JsonPath::new(Cow::Owned(segments)))
+    }
+
+    pub fn to_pointer(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        self.write_pointer(&mut out).unwrap();
+        out
+    }
+
+    pub fn write_pointer<W: Write>(&self, mut out: W) -> fmt::Result {
+        for segment in self.iter() {
+            match segment {
+                JsonPathSegment::Index(index) => write!(out, "/{}", index)?,
+                JsonPathSegment::Key(key) => write!(out, "/{}", escape_token(key))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolve `path` against the document at the start of `input`, returning
+/// the byte range of the value it refers to.
+///
+/// This streams over the tokens and skips every value which isn't on the
+/// path. With duplicate keys the last one wins, as it does for `Value`, so
+/// a match inside an object is only final once that object closes; a match
+/// reached only through arrays is returned straight away. Nothing after the
+/// outermost object on the path is read or validated.
+pub fn lookup(input: &[u8], path: &JsonPath<'_>) -> Result<Option<Range<usize>>, Error> {
+    let mut stream = TokenStream::new(input);
+    // The containers which the leading segments of `path` lead to, outermost
+    // first: whether each is an array, and how many elements it has had so
+    // far. Only the innermost one can contain the next segment.
+    let mut containers: Vec<(bool, usize)> = Vec::new();
+    // The last key of the innermost container.
+    let mut key: Option<Cow<'_, str>> = None;
+    let mut found = None;
+    // A later duplicate key could still replace the match if any container
+    // on the path is an object.
+    let is_final = |containers: &[(bool, usize)]| containers.iter().all(|&(array, _)| array);
+
+    while let Some(token) = stream.next() {
+        let token = token?;
+        let depth = containers.len();
+        if token.token.is_close() && token.depth + 1 == depth {
+            containers.pop();
+            if is_final(&containers) {
+                return Ok(found);
+            }
+            continue;
+        }
+        if token.depth != depth {
+            continue;
+        }
+        if token.is_key() {
+            if let Token::String(raw) = token.token {
+                key = Some(string::decode_cow(raw)?);
+            }
+            continue;
+        }
+        if !token.is_value_start() {
+            continue;
+        }
+
+        let is_match = match containers.last_mut() {
+            None => true,
+            Some((true, index)) => {
+                *index += 1;
+                path[depth - 1].matches_index(*index - 1)
+            }
+            Some((false, _)) => match key {
+                Some(ref key) => path[depth - 1].matches_key(key),
+                None => false,
+            },
+        };
+
+        if !is_match {
+            stream.skip_value(&token)?;
+            continue;
+        }
+        if depth == path.len() {
+            let end = stream.skip_value(&token)?;
+            found = Some(token.start..end);
+        } else if token.token.is_open() {
+            // Anything found under an earlier duplicate is replaced.
+            found = None;
+            containers.push((token.token == Token::ArrayOpen, 0));
+            key = None;
+            continue;
+        } else {
+            found = None;
+        }
+        if is_final(&containers) {
+            return Ok(found);
+        }
+    }
+    Ok(found)
+}
+
+/// Parse `pointer` and resolve it with `lookup`.
+pub fn lookup_pointer(input: &[u8], pointer: &str) -> Result<Option<Range<usize>>, Error> {
+    lookup(input, &JsonPath::from_pointer(pointer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pointer_round_trip() {
+        for pointer in &["", "/a/0/b~1c", "/~0~1", "/", "//", "/m~0n/01/10"] {
+            let path = JsonPath::from_pointer(pointer).unwrap();
+            assert_eq!(&path.to_pointer(), pointer);
+        }
+        let path = JsonPath::from_pointer("/a/0/b~1c/01").unwrap();
+        assert_eq!(
+            &path[..],
+            &[
+                JsonPathSegment::Key("a".into()),
+                JsonPathSegment::Index(0),
+                JsonPathSegment::Key("b/c".into()),
+                JsonPathSegment::Key("01".into()),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn pointer_errors() {
+        assert_eq!(
+            JsonPath::from_pointer("a"),
+            Err(PointerError::MissingLeadingSlash)
+        );
+        assert_eq!(
+            JsonPath::from_pointer("/a/b~2"),
+            Err(PointerError::InvalidEscape(4))
+        );
+        assert_eq!(unescape_token("~01").unwrap(), "~1");
+    }
+
+    #[test]
+    fn rfc6901_examples() -> Result<(), Error> {
+        let input = br#"{
+            "foo": ["bar", "baz"],
+            "": 0,
+            "a/b": 1,
+            "c%d": 2,
+            "e^f": 3,
+            "g|h": 4,
+            "i\\j": 5,
+            "k\"l": 6,
+            " ": 7,
+            "m~n": 8
+        }"#;
+        for (pointer, expected) in &[
+            ("/foo", r#"["bar", "baz"]"#),
+            ("/foo/0", r#""bar""#),
+            ("/", "0"),
+            ("/a~1b", "1"),
+            ("/c%d", "2"),
+            ("/e^f", "3"),
+            ("/g|h", "4"),
+            ("/i\\j", "5"),
+            ("/k\"l", "6"),
+            ("/ ", "7"),
+            ("/m~0n", "8"),
+        ] {
+            let range = lookup_pointer(input, pointer)?.expect(pointer);
+            assert_eq!(&input[range], expected.as_bytes(), "{}", pointer);
+        }
+        let range = lookup_pointer(input, "")?.unwrap();
+        assert_eq!(range, 0..input.len());
+        Ok(())
+    }
+
+    #[test]
+    fn lookup_missing_stops_early() -> Result<(), Error> {
+        // The trailing garbage is never reached.
+        let input = br#"{"a": {"b": 1}, "c": 2} !!!"#;
+        assert_eq!(lookup_pointer(input, "/a/x")?, None);
+        assert_eq!(lookup_pointer(input, "/a/b/c")?, None);
+        assert_eq!(lookup_pointer(input, "/c")?, Some(21..22));
+        assert_eq!(lookup_pointer(br#"{"0": [5, 6]}"#, "/0/1")?, Some(10..11));
+        assert_eq!(lookup_pointer(b"[[1, 2], 3] !!!", "/0/1")?, Some(5..6));
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_keys() -> Result<(), Error> {
+        let input = br#"{"a": {"b": 1}, "a": {"c": 2}, "d": [{"e": 3, "e": 4}]}"#;
+        assert_eq!(lookup_pointer(input, "/a/b")?, None);
+        assert_eq!(lookup_pointer(input, "/a/c")?, Some(27..28));
+        assert_eq!(lookup_pointer(input, "/d/0/e")?, Some(51..52));
+        assert_eq!(lookup_pointer(br#"{"a": {"b": 1}, "a": 2}"#, "/a/b")?, None);
+        Ok(())
+    }
+}
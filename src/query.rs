@@ -0,0 +1,1041 @@
+//! Streaming JSONPath evaluation.
+//!
+//! A `Query` is compiled once and then evaluated in a single pass over the
+//! token stream. Each open container remembers which steps of the query
+//! could still match beneath it, and subtrees where nothing can match are
+//! skipped without tracking paths. Filters (`[?(...)]`) are evaluated by
+//! scanning only the candidate element, so the only buffered state is the
+//! stack of open containers and the matches which haven't closed yet.
+//! `for_each_reader` does the same over a reader, for inputs which don't fit
+//! in memory.
+//!
+//! Supported syntax:
+//! - `$` root, `.name`, `['name']`, `["name"]`
+//! - `.*`, `[*]`, `..name`, `..*`, `..[...]`
+//! - `[0]`, `[0,2]`, `['a','b']`, `[start:end:step]`
+//! - `[?(@.price < 10 && @.tag == 'x')]`, `[?(@.isbn)]`, `[?(!(@.a))]`
+//!
+//! Negative indices and slice bounds need the length of the array, which
+//! isn't known until it closes, so they're rejected with `QueryError::Unsupported`.
+
+use crate::pointer;
+use crate::stream::{ReaderStream, TokenStream};
+use crate::string;
+use crate::tokenizer::Token;
+use crate::value::Value;
+use crate::{Error, JsonPath, JsonPathSegment};
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    UnexpectedEndOfInput,
+    UnexpectedCharacter { position: usize, found: char },
+    InvalidNumber(usize),
+    Unsupported(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    Key(String),
+    Index(usize),
+    Wildcard,
+    Slice {
+        start: usize,
+        end: Option<usize>,
+        step: usize,
+    },
+    Filter(FilterExpr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// True for `..`, which matches the selectors at any depth below.
+    pub descendant: bool,
+    /// A union of selectors, any of which may match.
+    pub selectors: Vec<Selector>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    /// A path relative to the current element (`@`).
+    Path(JsonPath<'static>),
+    Literal(Literal),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterExpr {
+    Exists(JsonPath<'static>),
+    Compare(Operand, CompareOp, Operand),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+}
+
+/// A value matched by a `Query`.
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    pub path: JsonPath<'a>,
+    pub range: Range<usize>,
+    /// The exact bytes of the value from the input.
+    pub raw: &'a [u8],
+}
+
+impl<'a> Match<'a> {
+    /// Decode the matched value.
+    pub fn value(&self) -> Result<Value, Error> {
+        Value::parse(self.raw)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Query::compile(s)
+    }
+}
+
+/// A scalar read back from the input for filter comparisons.
+enum Scalar<'b> {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(Cow<'b, str>),
+    Container,
+}
+
+impl<'b> Scalar<'b> {
+    fn read(raw: &'b [u8]) -> Result<Scalar<'b>, Error> {
+        Ok(match raw.first() {
+            Some(b'"') => Scalar::String(string::decode(raw)?),
+            Some(b't') => Scalar::Bool(true),
+            Some(b'f') => Scalar::Bool(false),
+            Some(b'n') => Scalar::Null,
+            Some(b'{') | Some(b'[') => Scalar::Container,
+            _ => Scalar::Number(
+                std::str::from_utf8(raw)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(f64::NAN),
+            ),
+        })
+    }
+
+    fn from_literal(literal: &Literal) -> Scalar<'_> {
+        match literal {
+            Literal::Null => Scalar::Null,
+            Literal::Bool(b) => Scalar::Bool(*b),
+            Literal::Number(n) => Scalar::Number(*n),
+            Literal::String(s) => Scalar::String(Cow::Borrowed(s)),
+        }
+    }
+
+    fn compare(&self, op: CompareOp, other: &Scalar<'_>) -> bool {
+        use std::cmp::Ordering;
+        let ordering = match (self, other) {
+            (Scalar::Number(a), Scalar::Number(b)) => a.partial_cmp(b),
+            (Scalar::String(a), Scalar::String(b)) => Some(a.cmp(b)),
+            (Scalar::Bool(a), Scalar::Bool(b)) if a == b => Some(Ordering::Equal),
+            (Scalar::Null, Scalar::Null) => Some(Ordering::Equal),
+            _ => None,
+        };
+        match op {
+            CompareOp::Eq => ordering == Some(Ordering::Equal),
+            CompareOp::Ne => ordering != Some(Ordering::Equal),
+            CompareOp::Lt => ordering == Some(Ordering::Less),
+            CompareOp::Le => ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal),
+            CompareOp::Gt => ordering == Some(Ordering::Greater),
+            CompareOp::Ge => {
+                ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal)
+            }
+        }
+    }
+}
+
+impl FilterExpr {
+    /// Evaluate the filter against the element starting at the beginning of `element`.
+    pub fn matches(&self, element: &[u8]) -> Result<bool, Error> {
+        Ok(match self {
+            FilterExpr::Exists(path) => pointer::lookup(element, path)?.is_some(),
+            FilterExpr::Compare(left, op, right) => {
+                let left = match Self::operand(left, element)? {
+                    Some(x) => x,
+                    None => return Ok(*op == CompareOp::Ne),
+                };
+                let right = match Self::operand(right, element)? {
+                    Some(x) => x,
+                    None => return Ok(*op == CompareOp::Ne),
+                };
+                left.compare(*op, &right)
+            }
+            FilterExpr::And(a, b) => a.matches(element)? && b.matches(element)?,
+            FilterExpr::Or(a, b) => a.matches(element)? || b.matches(element)?,
+            FilterExpr::Not(a) => !a.matches(element)?,
+        })
+    }
+
+    fn operand<'b>(operand: &'b Operand, element: &'b [u8]) -> Result<Option<Scalar<'b>>, Error> {
+        match operand {
+            Operand::Literal(literal) => Ok(Some(Scalar::from_literal(literal))),
+            Operand::Path(path) => match pointer::lookup(element, path)? {
+                Some(range) => Scalar::read(&element[range]).map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+impl Selector {
+    fn matches(&self, segment: &JsonPathSegment<'_>, element: &[u8]) -> Result<bool, Error> {
+        Ok(match (self, segment) {
+            (Selector::Wildcard, _) => true,
+            (Selector::Key(key), JsonPathSegment::Key(k)) => key == k,
+            (Selector::Index(index), JsonPathSegment::Index(i)) => index == i,
+            (Selector::Slice { start, end, step }, JsonPathSegment::Index(i)) => {
+                let before_end = match end {
+                    Some(end) => i < end,
+                    None => true,
+                };
+                i >= start && before_end && (i - start) % step == 0
+            }
+            (Selector::Filter(filter), _) => filter.matches(element)?,
+            _ => false,
+        })
+    }
+}
+
+struct Frame<'a> {
+    /// Positions in the query which are active for the children of this container.
+    states: Vec<usize>,
+    is_array: bool,
+    index: usize,
+    key: Option<Cow<'a, str>>,
+    /// The id of this container's pending match, if it matched.
+    pending: Option<usize>,
+}
+
+/// Matches are emitted in document order, so a matched container holds
+/// back the matches inside it until it closes.
+struct Pending<P> {
+    queue: VecDeque<(P, usize, Option<usize>)>,
+    base: usize,
+}
+
+impl<P> Pending<P> {
+    fn push(&mut self, path: P, start: usize, end: Option<usize>) -> usize {
+        self.queue.push_back((path, start, end));
+        self.base + self.queue.len() - 1
+    }
+
+    fn complete(&mut self, id: usize, end: usize) {
+        self.queue[id - self.base].2 = Some(end);
+    }
+
+    /// The start of the first match which hasn't been emitted.
+    fn first_start(&self) -> Option<usize> {
+        self.queue.front().map(|&(_, start, _)| start)
+    }
+
+    /// Emit every match up to the first one which hasn't closed.
+    fn flush<F: FnMut(P, Range<usize>)>(&mut self, mut f: F) {
+        while let Some(&(_, _, Some(_))) = self.queue.front() {
+            let (path, start, end) = self.queue.pop_front().unwrap();
+            self.base += 1;
+            f(path, start..end.unwrap());
+        }
+    }
+}
+
+impl Query {
+    pub fn compile(expr: &str) -> Result<Query, QueryError> {
+        Parser::new(expr).parse_query()
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    fn advance(
+        &self,
+        states: &[usize],
+        segment: &JsonPathSegment<'_>,
+        element: &[u8],
+    ) -> Result<Vec<usize>, Error> {
+        let mut next = Vec::new();
+        for &s in states {
+            let step = match self.steps.get(s) {
+                Some(step) => step,
+                None => continue,
+            };
+            if step.descendant {
+                next.push(s);
+            }
+            for selector in &step.selectors {
+                if selector.matches(segment, element)? {
+                    next.push(s + 1);
+                    break;
+                }
+            }
+        }
+        next.sort_unstable();
+        next.dedup();
+        Ok(next)
+    }
+
+    /// Evaluate the query over every document in `input`, calling `f` with
+    /// each match in document order.
+    pub fn for_each<'a, F: FnMut(Match<'a>)>(&self, input: &'a [u8], f: F) -> Result<(), Error> {
+        self.evaluate(input, &[0], Vec::new(), f)
+    }
+
+    /// Evaluate the query starting from `root`, the states of each document
+    /// in `input`, which is found at `path`.
+    fn evaluate<'a, F: FnMut(Match<'a>)>(
+        &self,
+        input: &'a [u8],
+        root: &[usize],
+        mut path: Vec<JsonPathSegment<'a>>,
+        mut f: F,
+    ) -> Result<(), Error> {
+        let n = self.steps.len();
+        let mut stream = TokenStream::new(input);
+        let mut frames: Vec<Frame<'a>> = Vec::new();
+        let mut pending = Pending {
+            queue: VecDeque::new(),
+            base: 0,
+        };
+
+        while let Some(token) = stream.next() {
+            let token = token?;
+            if token.is_key() {
+                if let (Some(frame), Token::String(raw)) = (frames.last_mut(), token.token) {
+                    frame.key = Some(string::decode_cow(raw)?);
+                }
+                continue;
+            }
+            if token.token.is_close() {
+                let frame = frames.pop().expect("validator guarantees a matching open");
+                if let Some(id) = frame.pending {
+                    pending.complete(id, token.end);
+                }
+                if !frames.is_empty() {
+                    path.pop();
+                }
+                pending.flush(|path, range| {
+                    f(Match {
+                        path,
+                        raw: &input[range.clone()],
+                        range,
+                    })
+                });
+                continue;
+            }
+            if !token.is_value_start() {
+                continue;
+            }
+
+            let states = match frames.last_mut() {
+                None => root.to_vec(),
+                Some(parent) => {
+                    let segment = if parent.is_array {
+                        parent.index += 1;
+                        JsonPathSegment::Index(parent.index - 1)
+                    } else {
+                        JsonPathSegment::Key(parent.key.take().unwrap_or_default())
+                    };
+                    let states = self.advance(&parent.states, &segment, &input[token.start..])?;
+                    path.push(segment);
+                    states
+                }
+            };
+            let is_match = states.contains(&n);
+            let is_live = states.iter().any(|&s| s < n);
+            let has_segment = !frames.is_empty();
+
+            if token.token.is_open() && is_live {
+                let id = if is_match {
+                    Some(pending.push(JsonPath::new(path.clone().into()), token.start, None))
+                } else {
+                    None
+                };
+                frames.push(Frame {
+                    states,
+                    is_array: token.token == Token::ArrayOpen,
+                    index: 0,
+                    key: None,
+                    pending: id,
+                });
+                continue;
+            }
+
+            if is_match {
+                let end = stream.skip_value(&token)?;
+                pending.push(JsonPath::new(path.clone().into()), token.start, Some(end));
+            } else {
+                stream.skip_value(&token)?;
+            }
+            if has_segment {
+                path.pop();
+            }
+            pending.flush(|path, range| {
+                f(Match {
+                    path,
+                    raw: &input[range.clone()],
+                    range,
+                })
+            });
+        }
+        Ok(())
+    }
+
+    /// Evaluate the query over every document read from `reader`, calling
+    /// `f` with each match in document order.
+    ///
+    /// Only the matches which haven't been passed to `f` yet are kept in
+    /// memory, along with the element being tested when a filter applies:
+    /// filters need the whole element, so it's read before anything beneath it
+    /// is evaluated.
+    pub fn for_each_reader<R: Read, F: FnMut(Match<'_>)>(
+        &self,
+        reader: R,
+        mut f: F,
+    ) -> Result<(), Error> {
+        let n = self.steps.len();
+        let mut stream = ReaderStream::new(reader);
+        let mut frames: Vec<Frame<'static>> = Vec::new();
+        let mut path: Vec<JsonPathSegment<'static>> = Vec::new();
+        let mut pending = Pending {
+            queue: VecDeque::new(),
+            base: 0,
+        };
+
+        // Emit what can be, and stop retaining the input before the rest.
+        macro_rules! flush {
+            () => {
+                pending.flush(|path, range| {
+                    f(Match {
+                        path: JsonPath::new(Cow::Owned(path)),
+                        raw: stream.retained(range.clone()),
+                        range,
+                    })
+                });
+                stream.retain_from(pending.first_start());
+            };
+        }
+
+        while let Some(token) = stream.next_token()? {
+            if token.is_key() {
+                if let (Some(frame), Token::String(raw)) = (frames.last_mut(), &token.token) {
+                    frame.key = Some(Cow::Owned(string::decode(raw)?.into_owned()));
+                }
+                continue;
+            }
+            let (start, end, depth) = (token.start, token.end, token.depth);
+            if token.token.is_close() {
+                let frame = frames.pop().expect("validator guarantees a matching open");
+                if let Some(id) = frame.pending {
+                    pending.complete(id, end);
+                }
+                if !frames.is_empty() {
+                    path.pop();
+                }
+                flush!();
+                continue;
+            }
+            if !token.is_value_start() {
+                continue;
+            }
+            let is_open = token.token.is_open();
+            let is_array = token.token == Token::ArrayOpen;
+
+            let states = match frames.last_mut() {
+                None => vec![0],
+                Some(parent) => {
+                    let segment = if parent.is_array {
+                        parent.index += 1;
+                        JsonPathSegment::Index(parent.index - 1)
+                    } else {
+                        JsonPathSegment::Key(parent.key.take().unwrap_or_default())
+                    };
+                    if self.has_filter(&parent.states) {
+                        // Read the whole element and evaluate it in memory.
+                        stream.retain_from(pending.first_start().or(Some(start)));
+                        let end = if is_open {
+                            stream.skip_container(depth)?
+                        } else {
+                            end
+                        };
+                        let element = stream.retained(start..end);
+                        let states = self.advance(&parent.states, &segment, element)?;
+                        let mut prefix = path.clone();
+                        prefix.push(segment);
+                        self.evaluate(element, &states, prefix, |m| {
+                            let path = m.path.iter().cloned().map(JsonPathSegment::into_owned);
+                            let range = start + m.range.start..start + m.range.end;
+                            pending.push(path.collect(), range.start, Some(range.end));
+                        })?;
+                        flush!();
+                        continue;
+                    }
+                    let states = self.advance(&parent.states, &segment, &[])?;
+                    path.push(segment);
+                    states
+                }
+            };
+            let is_match = states.contains(&n);
+            let is_live = states.iter().any(|&s| s < n);
+            let has_segment = !frames.is_empty();
+
+            if is_match {
+                stream.retain_from(pending.first_start().or(Some(start)));
+            }
+            if is_open && is_live {
+                let id = if is_match {
+                    Some(pending.push(path.clone(), start, None))
+                } else {
+                    None
+                };
+                frames.push(Frame {
+                    states,
+                    is_array,
+                    index: 0,
+                    key: None,
+                    pending: id,
+                });
+                continue;
+            }
+
+            let end = if is_open {
+                stream.skip_container(depth)?
+            } else {
+                end
+            };
+            if is_match {
+                pending.push(path.clone(), start, Some(end));
+            }
+            if has_segment {
+                path.pop();
+            }
+            flush!();
+        }
+        Ok(())
+    }
+
+    /// True if a child of a container in `states` needs to be read in full
+    /// to tell whether it matches.
+    fn has_filter(&self, states: &[usize]) -> bool {
+        states.iter().any(|&s| match self.steps.get(s) {
+            Some(step) => step
+                .selectors
+                .iter()
+                .any(|selector| matches!(selector, Selector::Filter(_))),
+            None => false,
+        })
+    }
+
+    /// Collect all of the matches in `input`.
+    pub fn matches<'a>(&self, input: &'a [u8]) -> Result<Vec<Match<'a>>, Error> {
+        let mut matches = Vec::new();
+        self.for_each(input, |m| matches.push(m))?;
+        Ok(matches)
+    }
+}
+
+struct Parser<'s> {
+    src: &'s str,
+    n: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn new(src: &'s str) -> Self {
+        Parser { src, n: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.n..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.n += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(' ') = self.peek() {
+            self.n += 1;
+        }
+    }
+
+    fn check_next(&mut self, target: &str) -> bool {
+        if self.src[self.n..].starts_with(target) {
+            self.n += target.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected(&self) -> QueryError {
+        match self.peek() {
+            Some(found) => QueryError::UnexpectedCharacter {
+                position: self.n,
+                found,
+            },
+            None => QueryError::UnexpectedEndOfInput,
+        }
+    }
+
+    fn expect(&mut self, target: char) -> Result<(), QueryError> {
+        if self.peek() == Some(target) {
+            self.n += target.len_utf8();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn parse_query(&mut self) -> Result<Query, QueryError> {
+        self.skip_spaces();
+        self.expect('$')?;
+        let steps = self.parse_steps(false)?;
+        self.skip_spaces();
+        if self.peek().is_some() {
+            return Err(self.unexpected());
+        }
+        Ok(Query { steps })
+    }
+
+    /// In a filter, only plain keys and indices are allowed.
+    fn parse_steps(&mut self, relative: bool) -> Result<Vec<Step>, QueryError> {
+        let mut steps = Vec::new();
+        loop {
+            let descendant = if self.check_next("..") {
+                true
+            } else if self.check_next(".") {
+                false
+            } else if self.peek() == Some('[') {
+                steps.push(Step {
+                    descendant: false,
+                    selectors: self.parse_brackets()?,
+                });
+                continue;
+            } else {
+                return Ok(steps);
+            };
+            if descendant && relative {
+                return Err(QueryError::Unsupported("descendants in filters"));
+            }
+            let selectors = match self.peek() {
+                Some('[') if descendant => self.parse_brackets()?,
+                Some('*') => {
+                    self.n += 1;
+                    vec![Selector::Wildcard]
+                }
+                _ => vec![Selector::Key(self.parse_name()?)],
+            };
+            steps.push(Step {
+                descendant,
+                selectors,
+            });
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<String, QueryError> {
+        let start = self.n;
+        while let Some(c) = self.peek() {
+            match c {
+                '.' | '[' | ']' | ' ' | '(' | ')' | '=' | '!' | '<' | '>' | '&' | '|' | ',' => {
+                    break
+                }
+                c => self.n += c.len_utf8(),
+            }
+        }
+        if start == self.n {
+            return Err(self.unexpected());
+        }
+        Ok(self.src[start..self.n].to_owned())
+    }
+
+    fn parse_brackets(&mut self) -> Result<Vec<Selector>, QueryError> {
+        self.expect('[')?;
+        self.skip_spaces();
+        let mut selectors = Vec::new();
+        if self.check_next("*") {
+            selectors.push(Selector::Wildcard);
+        } else if self.check_next("?(") {
+            let filter = self.parse_or()?;
+            self.skip_spaces();
+            self.expect(')')?;
+            selectors.push(Selector::Filter(filter));
+        } else {
+            loop {
+                self.skip_spaces();
+                selectors.push(match self.peek() {
+                    Some('\'') | Some('"') => Selector::Key(self.parse_quoted()?),
+                    _ => self.parse_index_or_slice()?,
+                });
+                self.skip_spaces();
+                if !self.check_next(",") {
+                    break;
+                }
+            }
+        }
+        self.skip_spaces();
+        self.expect(']')?;
+        Ok(selectors)
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, QueryError> {
+        let quote = self.next().ok_or(QueryError::UnexpectedEndOfInput)?;
+        let mut out = String::new();
+        loop {
+            match self.next().ok_or(QueryError::UnexpectedEndOfInput)? {
+                '\\' => out.push(self.next().ok_or(QueryError::UnexpectedEndOfInput)?),
+                c if c == quote => return Ok(out),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn parse_usize(&mut self) -> Result<Option<usize>, QueryError> {
+        if self.peek() == Some('-') {
+            return Err(QueryError::Unsupported("negative indices"));
+        }
+        let start = self.n;
+        while let Some('0'..='9') = self.peek() {
+            self.n += 1;
+        }
+        if start == self.n {
+            return Ok(None);
+        }
+        self.src[start..self.n]
+            .parse()
+            .map(Some)
+            .map_err(|_| QueryError::InvalidNumber(start))
+    }
+
+    fn parse_index_or_slice(&mut self) -> Result<Selector, QueryError> {
+        let start = self.parse_usize()?;
+        if !self.check_next(":") {
+            return start.map(Selector::Index).ok_or_else(|| self.unexpected());
+        }
+        let end = self.parse_usize()?;
+        let step = if self.check_next(":") {
+            self.parse_usize()?.unwrap_or(1)
+        } else {
+            1
+        };
+        if step == 0 {
+            return Err(QueryError::Unsupported("slice step of 0"));
+        }
+        Ok(Selector::Slice {
+            start: start.unwrap_or(0),
+            end,
+            step,
+        })
+    }
+
+    fn parse_or(&mut self) -> Result<FilterExpr, QueryError> {
+        let mut left = self.parse_and()?;
+        loop {
+            self.skip_spaces();
+            if !self.check_next("||") {
+                return Ok(left);
+            }
+            left = FilterExpr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpr, QueryError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            self.skip_spaces();
+            if !self.check_next("&&") {
+                return Ok(left);
+            }
+            left = FilterExpr::And(Box::new(left), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<FilterExpr, QueryError> {
+        self.skip_spaces();
+        if self.peek() == Some('!') && !self.src[self.n..].starts_with("!=") {
+            self.n += 1;
+            return Ok(FilterExpr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.check_next("(") {
+            let expr = self.parse_or()?;
+            self.skip_spaces();
+            self.expect(')')?;
+            return Ok(expr);
+        }
+        let left = self.parse_operand()?;
+        self.skip_spaces();
+        let op = if self.check_next("==") {
+            CompareOp::Eq
+        } else if self.check_next("!=") {
+            CompareOp::Ne
+        } else if self.check_next("<=") {
+            CompareOp::Le
+        } else if self.check_next(">=") {
+            CompareOp::Ge
+        } else if self.check_next("<") {
+            CompareOp::Lt
+        } else if self.check_next(">") {
+            CompareOp::Gt
+        } else {
+            return match left {
+                Operand::Path(path) => Ok(FilterExpr::Exists(path)),
+                Operand::Literal(_) => Err(self.unexpected()),
+            };
+        };
+        self.skip_spaces();
+        let right = self.parse_operand()?;
+        Ok(FilterExpr::Compare(left, op, right))
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, QueryError> {
+        let literal = match self.peek() {
+            Some('@') => {
+                self.n += 1;
+                return Ok(Operand::Path(self.parse_relative_path()?));
+            }
+            Some('$') => return Err(QueryError::Unsupported("root references in filters")),
+            Some('\'') | Some('"') => Literal::String(self.parse_quoted()?),
+            Some('-') | Some('0'..='9') => {
+                let start = self.n;
+                self.n += 1;
+                while let Some('0'..='9') | Some('.') | Some('e') | Some('E') | Some('+')
+                | Some('-') = self.peek()
+                {
+                    self.n += 1;
+                }
+                Literal::Number(
+                    self.src[start..self.n]
+                        .parse()
+                        .map_err(|_| QueryError::InvalidNumber(start))?,
+                )
+            }
+            _ if self.check_next("true") => Literal::Bool(true),
+            _ if self.check_next("false") => Literal::Bool(false),
+            _ if self.check_next("null") => Literal::Null,
+            _ => return Err(self.unexpected()),
+        };
+        Ok(Operand::Literal(literal))
+    }
+
+    fn parse_relative_path(&mut self) -> Result<JsonPath<'static>, QueryError> {
+        let mut segments = Vec::new();
+        for step in self.parse_steps(true)? {
+            match step.selectors.as_slice() {
+                [Selector::Key(key)] => segments.push(JsonPathSegment::Key(key.clone().into())),
+                [Selector::Index(index)] => segments.push(JsonPathSegment::Index(*index)),
+                _ => return Err(QueryError::Unsupported("wildcards in filter paths")),
+            }
+        }
+        Ok(JsonPath::new(segments.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORE: &[u8] = br#"{ "store": {
+        "book": [
+          { "category": "reference", "author": "Nigel Rees",
+            "title": "Sayings of the Century", "price": 8.95 },
+          { "category": "fiction", "author": "Evelyn Waugh",
+            "title": "Sword of Honour", "price": 12.99 },
+          { "category": "fiction", "author": "Herman Melville",
+            "title": "Moby Dick", "isbn": "0-553-21311-3", "price": 8.99 },
+          { "category": "fiction", "author": "J. R. R. Tolkien",
+            "title": "The Lord of the Rings", "isbn": "0-395-19395-8", "price": 22.99 }
+        ],
+        "bicycle": { "color": "red", "price": 19.95 }
+    } }"#;
+
+    fn raw(query: &str) -> Vec<String> {
+        Query::compile(query)
+            .unwrap()
+            .matches(STORE)
+            .unwrap()
+            .into_iter()
+            .map(|m| String::from_utf8(m.raw.to_vec()).unwrap())
+            .collect()
+    }
+
+    fn paths(query: &str) -> Vec<String> {
+        Query::compile(query)
+            .unwrap()
+            .matches(STORE)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.path.to_pointer())
+            .collect()
+    }
+
+    #[test]
+    fn child_and_wildcard() {
+        assert_eq!(
+            raw("$.store.book[*].author"),
+            vec![
+                r#""Nigel Rees""#,
+                r#""Evelyn Waugh""#,
+                r#""Herman Melville""#,
+                r#""J. R. R. Tolkien""#
+            ]
+        );
+        assert_eq!(raw("$['store']['bicycle'].color"), vec![r#""red""#]);
+        assert_eq!(paths("$.store.*"), vec!["/store/book", "/store/bicycle"]);
+    }
+
+    #[test]
+    fn descendants() {
+        assert_eq!(
+            raw("$..price"),
+            vec!["8.95", "12.99", "8.99", "22.99", "19.95"]
+        );
+        assert_eq!(paths("$..book[2]"), vec!["/store/book/2"]);
+        // Containers are emitted before the matches inside of them.
+        let all = paths("$..*");
+        assert_eq!(all[0], "/store");
+        assert_eq!(all[1], "/store/book");
+        assert_eq!(all[2], "/store/book/0");
+        assert_eq!(all.len(), 27);
+    }
+
+    #[test]
+    fn indices_and_slices() {
+        assert_eq!(
+            paths("$.store.book[0,2].price"),
+            vec!["/store/book/0/price", "/store/book/2/price"]
+        );
+        assert_eq!(
+            paths("$.store.book[1:3]"),
+            vec!["/store/book/1", "/store/book/2"]
+        );
+        assert_eq!(
+            paths("$.store.book[::2]"),
+            vec!["/store/book/0", "/store/book/2"]
+        );
+        assert_eq!(
+            Query::compile("$.a[-1]"),
+            Err(QueryError::Unsupported("negative indices"))
+        );
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            raw("$.store.book[?(@.price < 10)].title"),
+            vec![r#""Sayings of the Century""#, r#""Moby Dick""#]
+        );
+        assert_eq!(
+            paths("$..book[?(@.isbn)]"),
+            vec!["/store/book/2", "/store/book/3"]
+        );
+        assert_eq!(
+            paths("$..book[?(@.category == 'fiction' && !(@.isbn))]"),
+            vec!["/store/book/1"]
+        );
+        assert_eq!(
+            paths("$..book[?(@.price > 20 || @.author == \"Nigel Rees\")]"),
+            vec!["/store/book/0", "/store/book/3"]
+        );
+        let numbers = br#"[1, 5, 10, "a"]"#;
+        let matches = Query::compile("$[?(@ >= 5)]")
+            .unwrap()
+            .matches(numbers)
+            .unwrap();
+        assert_eq!(
+            matches.iter().map(|m| m.raw).collect::<Vec<_>>(),
+            vec![&b"5"[..], &b"10"[..]]
+        );
+    }
+
+    #[test]
+    fn root() {
+        let matches = Query::compile("$")
+            .unwrap()
+            .matches(b"[1] {\"a\": 2}")
+            .unwrap();
+        assert_eq!(
+            matches.iter().map(|m| m.raw).collect::<Vec<_>>(),
+            vec![&b"[1]"[..], &b"{\"a\": 2}"[..]]
+        );
+    }
+
+    /// Reads a few bytes at a time, so that tokens are split between reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn reader() {
+        for &(query, input) in &[
+            ("$..*", STORE),
+            ("$.store.book[?(@.price < 10)].title", STORE),
+            ("$..book[?(@.isbn)]", STORE),
+            ("$..price", STORE),
+            ("$.store", STORE),
+            ("$[?(@ >= 5)]", &b"[1, 5, 10, \"a\"]"[..]),
+            ("$", &b"[1] {\"a\": 2}"[..]),
+        ] {
+            let query = Query::compile(query).unwrap();
+            let expected: Vec<_> = query
+                .matches(input)
+                .unwrap()
+                .into_iter()
+                .map(|m| (m.path.to_pointer(), m.range, m.raw.to_vec()))
+                .collect();
+            let mut matches = Vec::new();
+            query
+                .for_each_reader(Trickle(input), |m| {
+                    matches.push((m.path.to_pointer(), m.range, m.raw.to_vec()))
+                })
+                .unwrap();
+            assert_eq!(matches, expected);
+        }
+
+        let query = Query::compile("$.store.bicycle").unwrap();
+        let bicycle = query.matches(STORE).unwrap()[0].value().unwrap();
+        assert_eq!(bicycle["color"], Value::from("red"));
+    }
+}
@@ -0,0 +1,245 @@
+//! Capturing values as their exact source text.
+//!
+//! Forwarding a sub-document unchanged doesn't need it to be decoded and
+//! serialized again: `value_at` finds the byte range of a value in an
+//! in-memory buffer, `RawCapture` copies a value's tokens into a buffer as
+//! they stream past, and `RawValue` does the same for serde types.
+
+use crate::stream::TokenStream;
+use crate::tokenizer::Token;
+use crate::Error;
+
+use std::ops::Range;
+
+/// The byte range of the value which starts at `offset`, which must be the
+/// first byte of a value (not whitespace or punctuation).
+pub fn value_range(input: &[u8], offset: usize) -> Result<Range<usize>, Error> {
+    match input.get(offset) {
+        Some(b'{' | b'[' | b'"' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n') => (),
+        _ => return Err(Error::NotAValue(offset)),
+    }
+    let mut stream = TokenStream::new(&input[offset..]);
+    let first = stream.next().ok_or(Error::UnexpectedEndOfInput)??;
+    let end = stream.skip_value(&first)?;
+    Ok(offset + first.start..offset + end)
+}
+
+/// The source bytes of the value which starts at `offset`.
+pub fn value_at(input: &[u8], offset: usize) -> Result<&[u8], Error> {
+    value_range(input, offset).map(|range| &input[range])
+}
+
+/// Copies the tokens of a value into a buffer as they're processed, for when
+/// the input is read in chunks and can't be sliced afterwards.
+///
+/// Call `begin` before pushing the token which starts the value, then `push`
+/// every token (including whitespace) until it returns true.
+#[derive(Clone, Debug, Default)]
+pub struct RawCapture {
+    buffer: Vec<u8>,
+    depth: usize,
+    capturing: bool,
+}
+
+impl RawCapture {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Start capturing a new value, discarding anything already captured.
+    pub fn begin(&mut self) {
+        self.buffer.clear();
+        self.depth = 0;
+        self.capturing = true;
+    }
+
+    #[inline]
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    /// Copy a token if a value is being captured. Returns true when the
+    /// token finished the value.
+    pub fn push(&mut self, token: &Token<'_>) -> bool {
+        if !self.capturing {
+            return false;
+        }
+        // Whitespace before the value starts isn't part of it.
+        if self.buffer.is_empty() && token.is_whitespace() {
+            return false;
+        }
+        token
+            .print(&mut self.buffer)
+            .expect("writing to a Vec can't fail");
+        if token.is_open() {
+            self.depth += 1;
+        } else if token.is_close() {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        if self.depth == 0 && (token.is_close() || token.is_complete_value()) {
+            self.capturing = false;
+            return true;
+        }
+        false
+    }
+
+    /// The bytes captured so far.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Take the captured bytes, leaving the buffer empty.
+    pub fn take(&mut self) -> Vec<u8> {
+        self.capturing = false;
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use self::serde_raw::RawValue;
+#[cfg(feature = "serde")]
+pub(crate) use self::serde_raw::TOKEN;
+
+#[cfg(feature = "serde")]
+mod serde_raw {
+    use serde::de::{self, Deserialize, Deserializer, Visitor};
+    use serde::ser::{Serialize, Serializer};
+
+    use std::borrow::Cow;
+    use std::fmt;
+
+    /// The name which `de::Deserializer` and `ser::Serializer` recognize to
+    /// pass raw text through.
+    pub(crate) const TOKEN: &str = "$parser::private::RawValue";
+
+    /// A value kept as its source text, with whitespace intact.
+    ///
+    /// With this crate's `Deserializer`, a `RawValue<'de>` borrows from the
+    /// input. With its `Serializer`, the text is checked by the validator and
+    /// written as it is. Other serializers write it as a string.
+    #[derive(Clone, PartialEq, Eq, Hash)]
+    pub struct RawValue<'a> {
+        json: Cow<'a, str>,
+    }
+
+    impl<'a> RawValue<'a> {
+        /// Wrap text which is already known to be a single JSON value.
+        pub(crate) fn new_unchecked(json: Cow<'a, str>) -> Self {
+            RawValue { json }
+        }
+
+        /// Check that `json` is a single value, and wrap it.
+        pub fn from_json(json: &'a str) -> Result<Self, crate::Error> {
+            let trimmed = json.trim_matches(&[' ', '\t', '\n', '\r'][..]);
+            crate::value_ref::ValueRef::parse(trimmed.as_bytes())?;
+            Ok(RawValue::new_unchecked(Cow::Borrowed(trimmed)))
+        }
+
+        #[inline]
+        pub fn get(&self) -> &str {
+            &self.json
+        }
+
+        pub fn into_owned(self) -> RawValue<'static> {
+            RawValue::new_unchecked(Cow::Owned(self.json.into_owned()))
+        }
+    }
+
+    impl<'a> fmt::Display for RawValue<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.json)
+        }
+    }
+
+    impl<'a> fmt::Debug for RawValue<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "RawValue({})", self.json)
+        }
+    }
+
+    impl<'a> Serialize for RawValue<'a> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_newtype_struct(TOKEN, &*self.json)
+        }
+    }
+
+    impl<'de: 'a, 'a> Deserialize<'de> for RawValue<'a> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct RawVisitor;
+
+            impl<'de> Visitor<'de> for RawVisitor {
+                type Value = RawValue<'de>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("any valid JSON value")
+                }
+
+                fn visit_borrowed_str<E: de::Error>(
+                    self,
+                    json: &'de str,
+                ) -> Result<Self::Value, E> {
+                    Ok(RawValue::new_unchecked(Cow::Borrowed(json)))
+                }
+
+                fn visit_str<E: de::Error>(self, json: &str) -> Result<Self::Value, E> {
+                    Ok(RawValue::new_unchecked(Cow::Owned(json.to_owned())))
+                }
+
+                fn visit_string<E: de::Error>(self, json: String) -> Result<Self::Value, E> {
+                    Ok(RawValue::new_unchecked(Cow::Owned(json)))
+                }
+            }
+
+            deserializer.deserialize_newtype_struct(TOKEN, RawVisitor)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::ByteSection;
+    use crate::tokenizer::{compress_next_token, utils::is_whitespace};
+
+    const INPUT: &[u8] = br#"{"keep": {"a": [1, 2] , "b": "A"}, "n": 10}"#;
+
+    #[test]
+    fn ranges() -> Result<(), Error> {
+        assert_eq!(value_at(INPUT, 9)?, br#"{"a": [1, 2] , "b": "A"}"#);
+        assert_eq!(value_at(INPUT, 15)?, b"[1, 2]");
+        assert_eq!(value_at(INPUT, 40)?, b"10");
+        assert!(matches!(value_range(INPUT, 7), Err(Error::NotAValue(7))));
+        assert!(matches!(value_range(INPUT, 8), Err(Error::NotAValue(8))));
+        assert!(matches!(value_range(INPUT, 99), Err(Error::NotAValue(99))));
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_capture() -> Result<(), Error> {
+        let mut section = ByteSection::new(INPUT);
+        let mut capture = RawCapture::new();
+        let mut captured = Vec::new();
+        let mut last_was_key = false;
+        while !section.is_empty() {
+            let start = section.n;
+            let token = compress_next_token(&mut section, is_whitespace)?;
+            if last_was_key && start >= 9 && !capture.is_capturing() && !token.is_whitespace() {
+                capture.begin();
+            }
+            if token == Token::Colon {
+                last_was_key = true;
+            } else if !token.is_whitespace() {
+                last_was_key = false;
+            }
+            if capture.push(&token) {
+                captured.push(capture.take());
+            }
+        }
+        assert_eq!(
+            captured,
+            vec![br#"{"a": [1, 2] , "b": "A"}"#.to_vec(), b"10".to_vec()]
+        );
+        Ok(())
+    }
+}
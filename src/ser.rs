@@ -0,0 +1,845 @@
+//! A serde `Serializer` which writes through `Token::print`.
+//!
+//! Every token is run through a `Validator` before it's written, so a
+//! `Serialize` implementation can't produce invalid JSON (such as a map key
+//! which isn't a string). By default strings get only the escapes JSON
+//! requires and numbers are formatted like `value::Number`. `SerializeOptions`
+//! can choose an `EscapeMode` and a `NumberFormat`, with the same rules as
+//! `FormatOptions` has for the reformatter.
+
+use crate::number::NumberFormat;
+use crate::raw;
+use crate::section::ByteSection;
+use crate::string::{self, EscapeMode};
+use crate::tokenizer::{compress_next_token, utils::is_whitespace, Token};
+use crate::validator::{ValidationError, Validator};
+use crate::value::Number;
+
+use serde::ser::{self, Serialize};
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Validation(ValidationError),
+    /// NaN and infinities have no JSON representation.
+    NonFiniteFloat,
+    /// Map keys must serialize to strings or integers.
+    KeyMustBeString,
+    Custom(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(error: ValidationError) -> Self {
+        Error::Validation(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Validation(error) => write!(f, "invalid output: {:?}", error),
+            Error::NonFiniteFloat => f.write_str("NaN and infinities can't be serialized"),
+            Error::KeyMustBeString => f.write_str("map keys must be strings"),
+            Error::Custom(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Error::Custom(message.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SerializeOptions {
+    /// The indentation for one level, or `None` for compact output.
+    pub indent: Option<Vec<u8>>,
+    /// Which characters strings escape.
+    pub escape: EscapeMode,
+    /// Rewrite numbers into this canonical form, rather than writing them
+    /// like `value::Number`.
+    pub numbers: Option<NumberFormat>,
+}
+
+impl Default for SerializeOptions {
+    fn default() -> Self {
+        SerializeOptions {
+            indent: None,
+            escape: EscapeMode::Minimal,
+            numbers: None,
+        }
+    }
+}
+
+pub struct Serializer<W> {
+    writer: W,
+    validator: Validator,
+    options: SerializeOptions,
+    /// For each open container, whether anything has been written to it yet.
+    stack: Vec<bool>,
+}
+
+impl<W: io::Write> Serializer<W> {
+    /// A serializer for compact output.
+    pub fn new(writer: W) -> Self {
+        Serializer::with_options(writer, SerializeOptions::default())
+    }
+
+    /// A serializer which puts each element and member on its own line,
+    /// indented by two spaces.
+    pub fn pretty(writer: W) -> Self {
+        Serializer::with_indent(writer, b"  ")
+    }
+
+    /// A pretty serializer with a custom indentation, such as a tab.
+    pub fn with_indent(writer: W, indent: &[u8]) -> Self {
+        let options = SerializeOptions {
+            indent: Some(indent.to_vec()),
+            ..Default::default()
+        };
+        Serializer::with_options(writer, options)
+    }
+
+    pub fn with_options(writer: W, options: SerializeOptions) -> Self {
+        Serializer {
+            writer,
+            validator: Validator::new(),
+            options,
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn emit(&mut self, token: &Token<'_>) -> Result<()> {
+        self.validator.process_token(token)?;
+        token.print(&mut self.writer)?;
+        Ok(())
+    }
+
+    fn newline(&mut self) -> Result<()> {
+        if let Some(ref indent) = self.options.indent {
+            self.writer.write_all(b"\n")?;
+            for _ in 0..self.stack.len() {
+                self.writer.write_all(indent)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, token: Token<'static>) -> Result<()> {
+        self.emit(&token)?;
+        self.stack.push(false);
+        Ok(())
+    }
+
+    fn close(&mut self, token: Token<'static>) -> Result<()> {
+        if self.stack.pop() == Some(true) {
+            self.newline()?;
+        }
+        self.emit(&token)
+    }
+
+    /// Called before each array element and object key.
+    fn begin_item(&mut self) -> Result<()> {
+        if let Some(has_items) = self.stack.last_mut() {
+            let first = !*has_items;
+            *has_items = true;
+            if !first {
+                self.emit(&Token::Comma)?;
+            }
+            self.newline()?;
+        }
+        Ok(())
+    }
+
+    fn colon(&mut self) -> Result<()> {
+        self.emit(&Token::Colon)?;
+        if self.options.indent.is_some() {
+            self.writer.write_all(b" ")?;
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, s: &str) -> Result<()> {
+        let mut quoted = String::with_capacity(s.len() + 2);
+        string::write_escaped_with(&mut quoted, s, self.options.escape)
+            .expect("writing to a String can't fail");
+        self.emit(&Token::String(Cow::Owned(quoted.into_bytes())))
+    }
+
+    fn number(&mut self, n: Number) -> Result<()> {
+        match self.options.numbers {
+            Some(format) => {
+                // Integers and finite floats always fit in a `Decimal`.
+                let text = format
+                    .apply(n.as_str())
+                    .expect("a serialized number is valid");
+                self.emit(&Token::Number(Cow::Owned(text.into_bytes())))
+            }
+            None => self.emit(&Token::Number(Cow::Borrowed(n.as_str().as_bytes()))),
+        }
+    }
+
+    /// Write the tokens of a complete value, checking them with the validator.
+    fn raw(&mut self, json: &[u8]) -> Result<()> {
+        let mut section = ByteSection::new(json);
+        while !section.is_empty() {
+            let token = compress_next_token(&mut section, is_whitespace)
+                .map_err(|e| Error::Custom(format!("{:?}", e)))?;
+            self.emit(&token)?;
+        }
+        Ok(())
+    }
+
+    fn float(&mut self, n: f64) -> Result<()> {
+        let n = Number::from_f64(n).ok_or(Error::NonFiniteFloat)?;
+        self.number(n)
+    }
+}
+
+/// Serialize compact JSON.
+pub fn to_writer<W: io::Write, T: Serialize + ?Sized>(writer: W, value: &T) -> Result<()> {
+    value.serialize(&mut Serializer::new(writer))
+}
+
+/// Serialize indented JSON.
+pub fn to_writer_pretty<W: io::Write, T: Serialize + ?Sized>(writer: W, value: &T) -> Result<()> {
+    value.serialize(&mut Serializer::pretty(writer))
+}
+
+pub fn to_writer_with_options<W: io::Write, T: Serialize + ?Sized>(
+    writer: W,
+    value: &T,
+    options: SerializeOptions,
+) -> Result<()> {
+    value.serialize(&mut Serializer::with_options(writer, options))
+}
+
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    to_writer(&mut out, value)?;
+    Ok(out)
+}
+
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    // Only valid UTF-8 is ever written.
+    Ok(String::from_utf8(to_vec(value)?).expect("serialized JSON is utf-8"))
+}
+
+pub fn to_string_pretty<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let mut out = Vec::new();
+    to_writer_pretty(&mut out, value)?;
+    Ok(String::from_utf8(out).expect("serialized JSON is utf-8"))
+}
+
+macro_rules! serialize_integers {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, n: $ty) -> Result<()> {
+                self.number(n.into())
+            }
+        )*
+    };
+}
+
+impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Compound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    fn serialize_bool(self, b: bool) -> Result<()> {
+        self.emit(if b { &Token::True } else { &Token::False })
+    }
+
+    serialize_integers! {
+        serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+        serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64
+    }
+
+    fn serialize_f32(self, n: f32) -> Result<()> {
+        // Go through the shortest f32 representation, so that 0.1f32 isn't
+        // written as 0.10000000149011612.
+        self.float(n.to_string().parse().unwrap_or(f64::NAN))
+    }
+
+    fn serialize_f64(self, n: f64) -> Result<()> {
+        self.float(n)
+    }
+
+    fn serialize_char(self, c: char) -> Result<()> {
+        self.string(c.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, s: &str) -> Result<()> {
+        self.string(s)
+    }
+
+    /// Bytes are written as an array of numbers.
+    fn serialize_bytes(self, bytes: &[u8]) -> Result<()> {
+        use serde::ser::SerializeSeq;
+        let mut seq = self.serialize_seq(Some(bytes.len()))?;
+        for byte in bytes {
+            seq.serialize_element(byte)?;
+        }
+        seq.end()
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.emit(&Token::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.emit(&Token::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.string(variant)
+    }
+
+    /// `RawValue` passes its text through here to be written as it is.
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if name == raw::TOKEN {
+            // Recover the text from the string the value serializes to.
+            let quoted = to_vec(value)?;
+            let json = string::decode(&quoted).map_err(|e| Error::Custom(format!("{:?}", e)))?;
+            return self.raw(json.as_bytes());
+        }
+        value.serialize(self)
+    }
+
+    /// `{"Variant": value}`
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.open(Token::ObjectOpen)?;
+        self.begin_item()?;
+        self.string(variant)?;
+        self.colon()?;
+        value.serialize(&mut *self)?;
+        self.close(Token::ObjectClose)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a, W>> {
+        self.open(Token::ArrayOpen)?;
+        Ok(Compound {
+            ser: self,
+            variant: false,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, W>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+        self.serialize_seq(Some(len))
+    }
+
+    /// `{"Variant": [values...]}`
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, W>> {
+        self.open(Token::ObjectOpen)?;
+        self.begin_item()?;
+        self.string(variant)?;
+        self.colon()?;
+        self.open(Token::ArrayOpen)?;
+        Ok(Compound {
+            ser: self,
+            variant: true,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a, W>> {
+        self.open(Token::ObjectOpen)?;
+        Ok(Compound {
+            ser: self,
+            variant: false,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+        self.serialize_map(Some(len))
+    }
+
+    /// `{"Variant": {fields...}}`
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, W>> {
+        self.open(Token::ObjectOpen)?;
+        self.begin_item()?;
+        self.string(variant)?;
+        self.colon()?;
+        self.open(Token::ObjectOpen)?;
+        Ok(Compound {
+            ser: self,
+            variant: true,
+        })
+    }
+}
+
+pub struct Compound<'a, W> {
+    ser: &'a mut Serializer<W>,
+    /// True if this is the inside of an enum variant, which has an extra
+    /// object to close.
+    variant: bool,
+}
+
+impl<'a, W: io::Write> Compound<'a, W> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.ser.begin_item()?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.ser.begin_item()?;
+        self.ser.string(key)?;
+        self.ser.colon()?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn finish(self, close: Token<'static>) -> Result<()> {
+        self.ser.close(close)?;
+        if self.variant {
+            self.ser.close(Token::ObjectClose)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeSeq for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ArrayClose)
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeTuple for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ArrayClose)
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeTupleStruct for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ArrayClose)
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeTupleVariant for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ArrayClose)
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeMap for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.ser.begin_item()?;
+        key.serialize(KeySerializer {
+            ser: &mut *self.ser,
+        })
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.ser.colon()?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ObjectClose)
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeStruct for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ObjectClose)
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeStructVariant for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish(Token::ObjectClose)
+    }
+}
+
+/// Serializes map keys. Strings are written as they are, and integers and
+/// chars are quoted. Anything else is an error.
+struct KeySerializer<'a, W> {
+    ser: &'a mut Serializer<W>,
+}
+
+macro_rules! serialize_integer_keys {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, n: $ty) -> Result<()> {
+                self.ser.string(&n.to_string())
+            }
+        )*
+    };
+}
+
+macro_rules! reject_keys {
+    ($($method:ident($($arg:ty),*)),*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<()> {
+                Err(Error::KeyMustBeString)
+            }
+        )*
+    };
+}
+
+impl<'a, W: io::Write> ser::Serializer for KeySerializer<'a, W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<(), Error>;
+    type SerializeTuple = ser::Impossible<(), Error>;
+    type SerializeTupleStruct = ser::Impossible<(), Error>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = ser::Impossible<(), Error>;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_str(self, s: &str) -> Result<()> {
+        self.ser.string(s)
+    }
+
+    fn serialize_char(self, c: char) -> Result<()> {
+        self.ser.string(c.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.ser.string(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    serialize_integer_keys! {
+        serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+        serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64
+    }
+
+    reject_keys! {
+        serialize_bool(bool), serialize_f32(f32), serialize_f64(f64), serialize_bytes(&[u8]),
+        serialize_none(), serialize_unit(), serialize_unit_struct(&'static str)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<()> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(Error::KeyMustBeString)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(Error::KeyMustBeString)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Link<'a> {
+        url: &'a str,
+        title: String,
+        tags: Vec<&'a str>,
+        rank: Option<f64>,
+        meta: BTreeMap<u32, ()>,
+    }
+
+    #[derive(Serialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Point(i32, i32),
+        Rect { w: u8, h: u8 },
+    }
+
+    fn link() -> Link<'static> {
+        let mut meta = BTreeMap::new();
+        meta.insert(7, ());
+        Link {
+            url: "http://a",
+            title: "say \"hi\"\n".into(),
+            tags: vec![],
+            rank: Some(0.1),
+            meta,
+        }
+    }
+
+    #[test]
+    fn compact() {
+        assert_eq!(
+            to_string(&link()).unwrap(),
+            r#"{"url":"http://a","title":"say \"hi\"\n","tags":[],"rank":0.1,"meta":{"7":null}}"#
+        );
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(1.0),
+            Shape::Point(-1, 2),
+            Shape::Rect { w: 3, h: 4 },
+        ];
+        assert_eq!(
+            to_string(&shapes).unwrap(),
+            r#"["Empty",{"Circle":1.0},{"Point":[-1,2]},{"Rect":{"w":3,"h":4}}]"#
+        );
+    }
+
+    #[test]
+    fn pretty() {
+        let expected = r#"{
+  "url": "http://a",
+  "title": "say \"hi\"\n",
+  "tags": [],
+  "rank": 0.1,
+  "meta": {
+    "7": null
+  }
+}"#;
+        assert_eq!(to_string_pretty(&link()).unwrap(), expected);
+        assert_eq!(
+            to_string_pretty(&Shape::Point(1, 2)).unwrap(),
+            "{\n  \"Point\": [\n    1,\n    2\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn escapes_and_numbers() {
+        use crate::number::NumberStyle;
+
+        let options = SerializeOptions {
+            escape: EscapeMode::Html,
+            numbers: Some(NumberFormat {
+                style: NumberStyle::Shortest,
+                negative_zero: false,
+            }),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let value = ("<é>", [1e21, -0.0, 0.5]);
+        to_writer_with_options(&mut out, &value, options.clone()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"["\u003cé\u003e",[1e+21,0,0.5]]"#
+        );
+        let mut map = BTreeMap::new();
+        map.insert("ü", 1);
+        let mut out = Vec::new();
+        let options = SerializeOptions {
+            escape: EscapeMode::Ascii,
+            ..options
+        };
+        to_writer_with_options(&mut out, &map, options).unwrap();
+        assert_eq!(out, br#"{"\u00fc":1}"#.to_vec());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(to_string(&f64::NAN), Err(Error::NonFiniteFloat)));
+        let mut map = BTreeMap::new();
+        map.insert(vec![1], 2);
+        assert!(matches!(to_string(&map), Err(Error::KeyMustBeString)));
+        assert_eq!(to_string(&0.1f32).unwrap(), "0.1");
+    }
+
+    #[test]
+    fn output_parses() {
+        let json = to_string_pretty(&link()).unwrap();
+        let parsed = crate::value::Value::parse(json.as_bytes()).unwrap();
+        assert_eq!(parsed.to_string(), to_string(&link()).unwrap());
+    }
+
+    #[test]
+    fn raw_values() {
+        use crate::raw::RawValue;
+
+        #[derive(Serialize, serde::Deserialize)]
+        struct Envelope<'a> {
+            id: u32,
+            #[serde(borrow)]
+            body: RawValue<'a>,
+        }
+
+        let input = r#"{"id": 1, "body": {"a": [1, 2],  "b": null}}"#;
+        let envelope: Envelope = crate::de::from_str(input).unwrap();
+        assert_eq!(envelope.body.get(), r#"{"a": [1, 2],  "b": null}"#);
+        assert_eq!(
+            to_string(&envelope).unwrap(),
+            r#"{"id":1,"body":{"a": [1, 2],  "b": null}}"#
+        );
+
+        let spaced = Envelope {
+            id: 2,
+            body: RawValue::from_json("[1, 2]").unwrap(),
+        };
+        assert_eq!(to_string(&spaced).unwrap(), r#"{"id":2,"body":[1, 2]}"#);
+        assert!(RawValue::from_json("[1,").is_err());
+    }
+}
@@ -0,0 +1,544 @@
+//! Reformatting with object keys sorted.
+//!
+//! `KeySorter` sits in front of a `Formatter`. Arrays and scalars outside
+//! objects stream straight through, but an object's members have to be held
+//! until it closes so they can be sorted. Each member's value is held as
+//! compact JSON text with its own objects already sorted.
+//!
+//! Once the complete members held in memory pass `SortOptions::memory_limit`
+//! bytes, the object holding the most of them has them sorted and written to
+//! a temporary file as a run, and the runs are merged when the object closes.
+//! An object with `MAX_RUNS` runs merges them into one before writing
+//! another, which bounds the number of open files. Only the member being
+//! read in each open object has to fit in memory however large the objects
+//! are.
+
+use crate::format::{FormatOptions, Formatter};
+use crate::stream::TokenStream;
+use crate::string;
+use crate::tokenizer::Token;
+use crate::Error;
+
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The most runs an object keeps before merging them into one.
+pub const MAX_RUNS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// Compare keys as written, escapes included. Cheapest, but `"\u0062"`
+    /// sorts before `"a"`.
+    Bytes,
+    /// Compare decoded keys by code point.
+    CodePoints,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortOptions {
+    pub order: KeyOrder,
+    /// Spill complete object members to temporary files once more than this
+    /// many bytes of them are held in memory.
+    pub memory_limit: usize,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions {
+            order: KeyOrder::Bytes,
+            memory_limit: 64 * 1024 * 1024,
+        }
+    }
+}
+
+struct Member {
+    sort_key: Vec<u8>,
+    /// The key as written, with quotes.
+    key: Vec<u8>,
+    /// The sorted value as compact JSON.
+    value: Vec<u8>,
+}
+
+impl Member {
+    #[inline]
+    fn size(&self) -> usize {
+        self.sort_key.len() + self.key.len() + self.value.len()
+    }
+
+    fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for field in &[&self.sort_key, &self.key, &self.value] {
+            writer.write_all(&(field.len() as u64).to_le_bytes())?;
+            writer.write_all(field)?;
+        }
+        Ok(())
+    }
+
+    /// Read the next member from a run, or `None` at its end.
+    fn read_from<R: Read>(mut reader: R) -> io::Result<Option<Member>> {
+        let mut fields = Vec::with_capacity(3);
+        for i in 0..3 {
+            let mut len = [0; 8];
+            match reader.read_exact(&mut len) {
+                Err(ref e) if i == 0 && e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                result => result?,
+            }
+            let mut field = vec![0; u64::from_le_bytes(len) as usize];
+            reader.read_exact(&mut field)?;
+            fields.push(field);
+        }
+        let value = fields.pop().unwrap_or_default();
+        let key = fields.pop().unwrap_or_default();
+        let sort_key = fields.pop().unwrap_or_default();
+        Ok(Some(Member {
+            sort_key,
+            key,
+            value,
+        }))
+    }
+}
+
+/// A file in the system's temporary directory, deleted on drop.
+struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    fn create() -> io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "parser-sort-{}-{}.tmp",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(TempFile { path, file })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Write sorted members to a new run.
+fn write_run<I: Iterator<Item = io::Result<Member>>>(members: I) -> io::Result<TempFile> {
+    let run = TempFile::create()?;
+    let mut writer = BufWriter::new(&run.file);
+    for member in members {
+        member?.write_to(&mut writer)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    Ok(run)
+}
+
+/// Merges runs and a sorted batch into key order. Ties go to the earliest
+/// source, keeping duplicate keys in their original order.
+struct Merge<'a> {
+    readers: Vec<BufReader<&'a File>>,
+    batch: std::vec::IntoIter<Member>,
+    /// The next member of each run, then of the batch.
+    heads: Vec<Option<Member>>,
+}
+
+impl<'a> Merge<'a> {
+    fn new(runs: &'a [TempFile], batch: Vec<Member>) -> io::Result<Self> {
+        let mut readers = Vec::with_capacity(runs.len());
+        let mut heads = Vec::with_capacity(runs.len() + 1);
+        for run in runs {
+            (&run.file).seek(SeekFrom::Start(0))?;
+            let mut reader = BufReader::new(&run.file);
+            heads.push(Member::read_from(&mut reader)?);
+            readers.push(reader);
+        }
+        let mut batch = batch.into_iter();
+        heads.push(batch.next());
+        Ok(Merge {
+            readers,
+            batch,
+            heads,
+        })
+    }
+}
+
+impl<'a> Iterator for Merge<'a> {
+    type Item = io::Result<Member>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|member| (i, member)))
+            .min_by(|(i, a), (j, b)| a.sort_key.cmp(&b.sort_key).then(i.cmp(j)))
+            .map(|(i, _)| i)?;
+        let refill = match self.readers.get_mut(i) {
+            Some(reader) => match Member::read_from(reader) {
+                Ok(member) => member,
+                Err(e) => return Some(Err(e)),
+            },
+            None => self.batch.next(),
+        };
+        std::mem::replace(&mut self.heads[i], refill).map(Ok)
+    }
+}
+
+enum Frame {
+    Array,
+    Object {
+        /// Members held in memory, the last one possibly still incomplete.
+        batch: Vec<Member>,
+        /// The size of the complete members in `batch`.
+        size: usize,
+        /// Sorted batches written out to temporary files.
+        runs: Vec<TempFile>,
+        expect_key: bool,
+    },
+}
+
+pub struct KeySorter<W> {
+    formatter: Formatter<W>,
+    options: SortOptions,
+    stack: Vec<Frame>,
+    /// The total `size` of the objects on the stack.
+    buffered: usize,
+}
+
+impl<W: io::Write> KeySorter<W> {
+    pub fn new(formatter: Formatter<W>, options: SortOptions) -> Self {
+        KeySorter {
+            formatter,
+            options,
+            stack: Vec::new(),
+            buffered: 0,
+        }
+    }
+
+    /// True if no container is open, i.e. between documents.
+    #[inline]
+    pub fn is_idle(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// The value of the innermost object's last member, if any object is
+    /// open.
+    fn target(&mut self) -> Option<&mut Vec<u8>> {
+        let batch = self.stack.iter_mut().rev().find_map(|frame| match frame {
+            Frame::Object { batch, .. } => Some(batch),
+            Frame::Array => None,
+        });
+        batch
+            .and_then(|batch| batch.last_mut())
+            .map(|member| &mut member.value)
+    }
+
+    fn emit(&mut self, token: &Token<'_>) -> Result<(), Error> {
+        match self.target() {
+            Some(value) => token.print(value)?,
+            None => self.formatter.write_token(token)?,
+        }
+        Ok(())
+    }
+
+    /// Emit a value held as compact JSON text.
+    fn emit_text(&mut self, text: &[u8]) -> Result<(), Error> {
+        match self.target() {
+            Some(value) => value.extend_from_slice(text),
+            None => {
+                for token in TokenStream::new(text) {
+                    self.formatter.write_token(&token?.token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn sort_key(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(match self.options.order {
+            KeyOrder::Bytes => raw[1..raw.len() - 1].to_vec(),
+            // UTF-8 preserves code point order.
+            KeyOrder::CodePoints => string::decode(raw)?.into_owned().into_bytes(),
+        })
+    }
+
+    /// Process the next token, which must already have been validated.
+    pub fn write_token(&mut self, token: &Token<'_>) -> Result<(), Error> {
+        match token {
+            Token::ObjectOpen => self.stack.push(Frame::Object {
+                batch: Vec::new(),
+                size: 0,
+                runs: Vec::new(),
+                expect_key: true,
+            }),
+            Token::ArrayOpen => {
+                self.emit(token)?;
+                self.stack.push(Frame::Array);
+            }
+            Token::ObjectClose => {
+                if let Some(Frame::Object {
+                    batch, size, runs, ..
+                }) = self.stack.pop()
+                {
+                    self.buffered -= size;
+                    self.write_object(batch, runs)?;
+                }
+            }
+            Token::ArrayClose => {
+                self.stack.pop();
+                self.emit(token)?;
+            }
+            Token::Comma => match self.stack.last_mut() {
+                Some(Frame::Object {
+                    batch,
+                    size,
+                    expect_key,
+                    ..
+                }) => {
+                    *expect_key = true;
+                    let completed = batch.last().map_or(0, Member::size);
+                    *size += completed;
+                    self.buffered += completed;
+                    while self.buffered > self.options.memory_limit && self.spill()? {}
+                }
+                _ => self.emit(token)?,
+            },
+            Token::String(raw) => {
+                if let Some(Frame::Object { expect_key, .. }) = self.stack.last() {
+                    if *expect_key {
+                        let member = Member {
+                            sort_key: self.sort_key(raw)?,
+                            key: raw.to_vec(),
+                            value: Vec::new(),
+                        };
+                        if let Some(Frame::Object {
+                            batch, expect_key, ..
+                        }) = self.stack.last_mut()
+                        {
+                            *expect_key = false;
+                            batch.push(member);
+                        }
+                        return Ok(());
+                    }
+                }
+                self.emit(token)?;
+            }
+            Token::Colon | Token::Spaces(_) | Token::Whitespace(_) => (),
+            _ => self.emit(token)?,
+        }
+        Ok(())
+    }
+
+    /// Write the complete members of the object holding the most of them
+    /// out as a run. Returns false if no complete members are held.
+    fn spill(&mut self) -> Result<bool, Error> {
+        let largest = self
+            .stack
+            .iter()
+            .enumerate()
+            .filter_map(|(i, frame)| match frame {
+                Frame::Object { size, .. } if *size > 0 => Some((i, *size)),
+                _ => None,
+            })
+            .max_by_key(|&(_, size)| size);
+        let (i, spilled) = match largest {
+            Some(largest) => largest,
+            None => return Ok(false),
+        };
+        // Spilling happens at a comma in the innermost object, so only the
+        // objects around it have a member which is still being read.
+        let is_innermost = i + 1 == self.stack.len();
+        if let Frame::Object {
+            batch, size, runs, ..
+        } = &mut self.stack[i]
+        {
+            let complete = if is_innermost {
+                batch.len()
+            } else {
+                batch.len() - 1
+            };
+            let mut members: Vec<Member> = batch.drain(..complete).collect();
+            members.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+            if runs.len() >= MAX_RUNS {
+                let merged = write_run(Merge::new(runs, Vec::new())?)?;
+                *runs = vec![merged];
+            }
+            runs.push(write_run(members.into_iter().map(Ok))?);
+            *size = 0;
+        }
+        self.buffered -= spilled;
+        Ok(true)
+    }
+
+    /// Merge the runs and the batch of a closed object, and emit it.
+    fn write_object(&mut self, mut batch: Vec<Member>, runs: Vec<TempFile>) -> Result<(), Error> {
+        batch.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+        self.emit(&Token::ObjectOpen)?;
+        for (i, member) in Merge::new(&runs, batch)?.enumerate() {
+            let member = member?;
+            if i > 0 {
+                self.emit(&Token::Comma)?;
+            }
+            self.emit(&Token::String(Cow::Borrowed(&member.key)))?;
+            self.emit(&Token::Colon)?;
+            self.emit_text(&member.value)?;
+        }
+        self.emit(&Token::ObjectClose)
+    }
+
+    /// Check that no object is left open, and return the writer.
+    pub fn finish(self) -> Result<W, Error> {
+        if !self.is_idle() {
+            return Err(Error::UnexpectedEndOfInput);
+        }
+        Ok(self.formatter.finish()?)
+    }
+}
+
+/// Reformat every document in `input` with object keys sorted.
+pub fn sort_keys(
+    input: &[u8],
+    format: FormatOptions,
+    options: SortOptions,
+) -> Result<Vec<u8>, Error> {
+    let formatter = Formatter::new(Vec::with_capacity(input.len()), format);
+    let mut sorter = KeySorter::new(formatter, options);
+    for token in TokenStream::new(input) {
+        sorter.write_token(&token?.token)?;
+    }
+    sorter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str =
+        r#"{"c": [3, {"z": 1, "b": 2}], "a": {}, "\u0062": {"y": null, "x": true}} 5"#;
+
+    fn sorted(order: KeyOrder, memory_limit: usize) -> String {
+        let options = SortOptions {
+            order,
+            memory_limit,
+        };
+        let output = sort_keys(INPUT.as_bytes(), FormatOptions::compact(), options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn sorts_recursively() {
+        assert_eq!(
+            sorted(KeyOrder::Bytes, usize::MAX),
+            r#"{"\u0062":{"x":true,"y":null},"a":{},"c":[3,{"b":2,"z":1}]}"#.to_owned() + "\n5\n"
+        );
+        assert_eq!(
+            sorted(KeyOrder::CodePoints, usize::MAX),
+            r#"{"a":{},"\u0062":{"x":true,"y":null},"c":[3,{"b":2,"z":1}]}"#.to_owned() + "\n5\n"
+        );
+        let pretty = sort_keys(
+            br#"{"b": 1, "a": [2]}"#,
+            Default::default(),
+            Default::default(),
+        );
+        assert_eq!(
+            pretty.unwrap(),
+            b"{\n  \"a\": [\n    2\n  ],\n  \"b\": 1\n}\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn spills_to_runs() {
+        for &order in &[KeyOrder::Bytes, KeyOrder::CodePoints] {
+            assert_eq!(sorted(order, 0), sorted(order, usize::MAX));
+            assert_eq!(sorted(order, 16), sorted(order, usize::MAX));
+        }
+        let input = br#"{"b": 1, "a": 2, "b": 3, "a": 4, "c": 5}"#;
+        let options = SortOptions {
+            order: KeyOrder::Bytes,
+            memory_limit: 0,
+        };
+        assert_eq!(
+            sort_keys(input, FormatOptions::compact(), options).unwrap(),
+            br#"{"a":2,"a":4,"b":1,"b":3,"c":5}
+"#
+            .to_vec()
+        );
+    }
+
+    /// The number of runs of each open object after writing `input`.
+    fn runs_after(input: &str, memory_limit: usize) -> Vec<usize> {
+        let options = SortOptions {
+            order: KeyOrder::Bytes,
+            memory_limit,
+        };
+        let mut sorter = KeySorter::new(
+            Formatter::new(Vec::new(), FormatOptions::compact()),
+            options,
+        );
+        // The input stops inside an object, which the stream reports at the
+        // end.
+        for token in TokenStream::new(input.as_bytes()).map_while(Result::ok) {
+            sorter.write_token(&token.token).unwrap();
+        }
+        sorter
+            .stack
+            .iter()
+            .filter_map(|frame| match frame {
+                Frame::Object { runs, .. } => Some(runs.len()),
+                Frame::Array => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spills_the_object_holding_the_bytes() {
+        // The outer member is still being read, so its bytes don't count
+        // against the inner object, which spills runs of several members.
+        let members: Vec<String> = (0..40)
+            .map(|i| format!("\"k{:02}\": {}", 39 - i, i))
+            .collect();
+        let input = format!(r#"{{"a": ["{}", {{{}"#, "x".repeat(100), members.join(", "));
+        assert_eq!(runs_after(&input, 40), vec![0, 7]);
+
+        let input = format!(
+            r#"{{"a": "{}", "b": {{{}"#,
+            "x".repeat(100),
+            members.join(", ")
+        );
+        assert_eq!(runs_after(&input, 40), vec![1, 7]);
+    }
+
+    #[test]
+    fn merges_runs() {
+        let members: Vec<String> = (0..200)
+            .map(|i| format!("\"k{:03}\": {}", 199 - i, i))
+            .collect();
+        let input = format!("{{{}", members.join(", "));
+        assert!(runs_after(&input, 0)[0] <= MAX_RUNS);
+
+        let options = SortOptions {
+            order: KeyOrder::Bytes,
+            memory_limit: 0,
+        };
+        let output =
+            sort_keys((input + "}").as_bytes(), FormatOptions::compact(), options).unwrap();
+        let expected: Vec<String> = (0..200)
+            .map(|i| format!("\"k{:03}\":{}", i, 199 - i))
+            .collect();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{{{}}}\n", expected.join(","))
+        );
+    }
+}
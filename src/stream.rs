@@ -0,0 +1,452 @@
+//! Validated token streams.
+//!
+//! `TokenStream` runs the tokenizer and a `Validator` over an in-memory
+//! buffer, yielding each non-whitespace token with its byte offsets and
+//! nesting depth.
+//!
+//! `ReaderStream` does the same over an `io::Read`, a chunk at a time, for
+//! inputs which don't fit in memory.
+
+use crate::section::ByteSection;
+use crate::tokenizer::{compress_next_token, utils::is_whitespace, Token};
+use crate::validator::{ValidationContext, ValidationState, Validator};
+use crate::Error;
+
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// A non-whitespace token which has been run through the `Validator`, along
+/// with where it was found in the input.
+#[derive(Debug)]
+pub struct StreamToken<'a> {
+    pub token: Token<'a>,
+    /// Byte offset of the first byte of the token.
+    pub start: usize,
+    /// Byte offset one past the last byte of the token.
+    pub end: usize,
+    pub state: ValidationState,
+    /// The validator's context after processing this token.
+    pub context: Option<ValidationContext>,
+    /// The number of containers enclosing this token. Open and close tokens
+    /// have the depth of the container they belong to, so the top level
+    /// `{` and `}` both have a depth of 0.
+    pub depth: usize,
+}
+
+impl<'a> StreamToken<'a> {
+    /// True if this token is an object key rather than a value.
+    #[inline]
+    pub fn is_key(&self) -> bool {
+        self.context == Some(ValidationContext::ObjectEntryKey)
+    }
+
+    /// True if this token starts a value (including containers).
+    #[inline]
+    pub fn is_value_start(&self) -> bool {
+        self.token.is_value_start() && !self.is_key()
+    }
+
+    /// True if this token finishes a value (including containers).
+    #[inline]
+    pub fn is_value_end(&self) -> bool {
+        let is_context_in_value = match self.context {
+            None => true,
+            Some(context) => context.in_value(),
+        };
+        (self.token.is_close() || self.token.is_complete_value()) && is_context_in_value
+    }
+}
+
+/// Drives `compress_next_token` and a `Validator` over an in-memory buffer,
+/// skipping whitespace.
+///
+/// This yields the tokens of every document in the input in sequence. When
+/// the input is exhausted, the validator is checked to ensure the last
+/// document was complete.
+pub struct TokenStream<'a> {
+    section: ByteSection<'a>,
+    validator: Validator,
+    depth: usize,
+    finished: bool,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        TokenStream {
+            section: ByteSection::new(input),
+            validator: Validator::new(),
+            depth: 0,
+            finished: false,
+        }
+    }
+
+    /// The byte offset of the next unprocessed byte.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.section.n
+    }
+
+    #[inline]
+    pub fn source(&self) -> &'a [u8] {
+        self.section.src
+    }
+
+    #[inline]
+    pub fn validator(&self) -> &Validator {
+        &self.validator
+    }
+
+    /// True if only whitespace remains in the input.
+    pub fn is_exhausted(&self) -> bool {
+        self.section.src[self.section.n..]
+            .iter()
+            .all(|&c| is_whitespace(c))
+    }
+
+    /// Consume the rest of the value which `token` started and return its end offset.
+    /// If `token` is a scalar, this is just `token.end`.
+    pub fn skip_value(&mut self, token: &StreamToken<'a>) -> Result<usize, Error> {
+        if !token.token.is_open() {
+            return Ok(token.end);
+        }
+        for next in self.by_ref() {
+            let next = next?;
+            if next.token.is_close() && next.depth == token.depth {
+                return Ok(next.end);
+            }
+        }
+        Err(Error::UnexpectedEndOfInput)
+    }
+
+    fn next_token(&mut self) -> Result<Option<StreamToken<'a>>, Error> {
+        loop {
+            if self.section.is_empty() {
+                self.finished = true;
+                self.validator.finish()?;
+                return Ok(None);
+            }
+            let start = self.section.n;
+            let token = compress_next_token(&mut self.section, is_whitespace)?;
+            if token.is_whitespace() {
+                continue;
+            }
+            let state = self.validator.process_token(&token)?;
+            if token.is_close() {
+                self.depth -= 1;
+            }
+            let depth = self.depth;
+            if token.is_open() {
+                self.depth += 1;
+            }
+            return Ok(Some(StreamToken {
+                token,
+                start,
+                end: self.section.n,
+                state,
+                context: self.validator.current_context(),
+                depth,
+            }));
+        }
+    }
+}
+
+impl<'a> Iterator for TokenStream<'a> {
+    type Item = Result<StreamToken<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.next_token();
+        if result.is_err() {
+            self.finished = true;
+        }
+        result.transpose()
+    }
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Like `TokenStream`, but reading the input in chunks, for inputs which
+/// don't fit in memory.
+///
+/// Only the token being read is kept, along with anything the caller asks
+/// to `retain`. A token which doesn't fit in the buffer grows it. Offsets
+/// are from the start of the input. Tokens borrow the buffer, so this has a
+/// `next_token` method rather than being an `Iterator`.
+pub struct ReaderStream<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// The unprocessed input is `buffer[start..end]`.
+    start: usize,
+    end: usize,
+    /// The offset in the input of `buffer[0]`.
+    base: usize,
+    retain: Option<usize>,
+    eof: bool,
+    validator: Validator,
+    depth: usize,
+    finished: bool,
+}
+
+impl<R: Read> ReaderStream<R> {
+    pub fn new(reader: R) -> Self {
+        ReaderStream::with_capacity(reader, CHUNK_SIZE)
+    }
+
+    pub fn with_capacity(reader: R, capacity: usize) -> Self {
+        ReaderStream {
+            reader,
+            buffer: vec![0; capacity.max(1)],
+            start: 0,
+            end: 0,
+            base: 0,
+            retain: None,
+            eof: false,
+            validator: Validator::new(),
+            depth: 0,
+            finished: false,
+        }
+    }
+
+    /// The byte offset of the next unprocessed byte.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.base + self.start
+    }
+
+    #[inline]
+    pub fn validator(&self) -> &Validator {
+        &self.validator
+    }
+
+    /// Keep the input from `offset` onwards in memory so that it can be read
+    /// back with `retained`, or stop keeping it with `None`. The offset can't
+    /// be before the start of the last token returned.
+    pub fn retain_from(&mut self, offset: Option<usize>) {
+        if let Some(offset) = offset {
+            debug_assert!(offset >= self.base);
+        }
+        self.retain = offset;
+    }
+
+    /// The input in `range`, which must be retained or be part of the last
+    /// token returned.
+    pub fn retained(&self, range: Range<usize>) -> &[u8] {
+        &self.buffer[range.start - self.base..range.end - self.base]
+    }
+
+    /// Consume the rest of the container at `depth` whose open token was just
+    /// returned, and return its end offset.
+    pub fn skip_container(&mut self, depth: usize) -> Result<usize, Error> {
+        while let Some(next) = self.next_token()? {
+            if next.token.is_close() && next.depth == depth {
+                return Ok(next.end);
+            }
+        }
+        Err(Error::UnexpectedEndOfInput)
+    }
+
+    /// The next non-whitespace token, or `None` once the input is exhausted
+    /// and the last document was complete.
+    pub fn next_token(&mut self) -> Result<Option<StreamToken<'_>>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let len = match self.next_len() {
+            Ok(Some(len)) => len,
+            Ok(None) => {
+                self.finished = true;
+                self.validator.finish()?;
+                return Ok(None);
+            }
+            Err(e) => {
+                self.finished = true;
+                return Err(e);
+            }
+        };
+        let start = self.start;
+        self.start += len;
+        let mut section = ByteSection::new(&self.buffer[start..self.start]);
+        let token = match compress_next_token(&mut section, is_whitespace) {
+            Ok(token) => token,
+            Err(e) => {
+                self.finished = true;
+                return Err(e.into());
+            }
+        };
+        let state = match self.validator.process_token(&token) {
+            Ok(state) => state,
+            Err(e) => {
+                self.finished = true;
+                return Err(e.into());
+            }
+        };
+        if token.is_close() {
+            self.depth -= 1;
+        }
+        let depth = self.depth;
+        if token.is_open() {
+            self.depth += 1;
+        }
+        Ok(Some(StreamToken {
+            token,
+            start: self.base + start,
+            end: self.base + self.start,
+            state,
+            context: self.validator.current_context(),
+            depth,
+        }))
+    }
+
+    /// Skip whitespace and find the length of the next token, reading more
+    /// input until the token is known to be complete.
+    fn next_len(&mut self) -> Result<Option<usize>, Error> {
+        loop {
+            if self.start == self.end {
+                if self.eof || !self.fill()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+            let mut section = ByteSection::new(&self.buffer[self.start..self.end]);
+            let complete = match compress_next_token(&mut section, is_whitespace) {
+                Ok(ref token) if token.is_whitespace() => {
+                    self.start += section.n;
+                    continue;
+                }
+                // A number could continue in the next chunk.
+                Ok(ref token) => {
+                    self.eof || !section.is_empty() || !token.potential_false_positive()
+                }
+                Err(ref e) if e.is_eof() && !self.eof => false,
+                Err(e) => return Err(e.into()),
+            };
+            if complete {
+                return Ok(Some(section.n));
+            }
+            self.fill()?;
+        }
+    }
+
+    /// Read more input after the unprocessed and retained bytes. Returns
+    /// false at the end of the input.
+    fn fill(&mut self) -> io::Result<bool> {
+        let keep = match self.retain {
+            Some(offset) => (offset - self.base).min(self.start),
+            None => self.start,
+        };
+        if keep > 0 {
+            self.buffer.copy_within(keep..self.end, 0);
+            self.base += keep;
+            self.start -= keep;
+            self.end -= keep;
+        }
+        if self.end == self.buffer.len() {
+            let len = self.buffer.len();
+            self.buffer.resize(len * 2, 0);
+        }
+        loop {
+            match self.reader.read(&mut self.buffer[self.end..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(false);
+                }
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(true);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_and_depths() -> Result<(), Error> {
+        let input = br#" {"a": [1, true]} "#;
+        let tokens = TokenStream::new(input).collect::<Result<Vec<_>, Error>>()?;
+        let summary: Vec<_> = tokens
+            .iter()
+            .map(|t| (&input[t.start..t.end], t.depth, t.is_key()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (&b"{"[..], 0, false),
+                (&b"\"a\""[..], 1, true),
+                (&b":"[..], 1, false),
+                (&b"["[..], 1, false),
+                (&b"1"[..], 2, false),
+                (&b","[..], 2, false),
+                (&b"true"[..], 2, false),
+                (&b"]"[..], 1, false),
+                (&b"}"[..], 0, false),
+            ]
+        );
+        assert_eq!(tokens.last().unwrap().state, ValidationState::Complete);
+        Ok(())
+    }
+
+    #[test]
+    fn skip_value() -> Result<(), Error> {
+        let input = br#"[{"a": [1, {}]}, 2]"#;
+        let mut stream = TokenStream::new(input);
+        stream.next().unwrap()?;
+        let object = stream.next().unwrap()?;
+        let end = stream.skip_value(&object)?;
+        assert_eq!(&input[object.start..end], br#"{"a": [1, {}]}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn incomplete_input() {
+        let result = TokenStream::new(b"[1, 2").collect::<Result<Vec<_>, Error>>();
+        assert!(result.is_err());
+        // Nothing is read after an error.
+        for input in &[&b"[1, 2"[..], b"[1, x]", b"[1 2]"] {
+            let mut stream = ReaderStream::new(*input);
+            loop {
+                match stream.next_token() {
+                    Ok(Some(_)) => (),
+                    Ok(None) => panic!("expected an error"),
+                    Err(_) => break,
+                }
+            }
+            assert!(matches!(stream.next_token(), Ok(None)));
+        }
+    }
+
+    #[test]
+    fn reads_in_chunks() -> Result<(), Error> {
+        let input = br#"{"key": "a longer string", "n": [12345, -0.5e10, true]} 7"#;
+        let expected: Vec<_> = TokenStream::new(input)
+            .map(|t| t.map(|t| (t.token.into_owned(), t.start, t.end, t.depth)))
+            .collect::<Result<_, Error>>()?;
+        let mut stream = ReaderStream::with_capacity(&input[..], 4);
+        let mut tokens = Vec::new();
+        while let Some(t) = stream.next_token()? {
+            tokens.push((t.token.into_owned(), t.start, t.end, t.depth));
+        }
+        assert_eq!(tokens, expected);
+
+        let mut stream = ReaderStream::with_capacity(&input[..], 4);
+        stream.next_token()?;
+        stream.next_token()?;
+        stream.next_token()?;
+        let value = stream.next_token()?.unwrap().start;
+        stream.retain_from(Some(value));
+        stream.next_token()?;
+        stream.next_token()?;
+        stream.next_token()?;
+        let open = stream.next_token()?.unwrap().depth;
+        let end = stream.skip_container(open)?;
+        assert_eq!(stream.retained(value..end), &input[value..end]);
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use crate::tokenizer::utils::is_hexdigit;
+
+use std::borrow::Cow;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StringError {
+    /// The token wasn't wrapped in quotation marks.
+    MissingQuotes,
+    InvalidUtf8(std::str::Utf8Error),
+    InvalidEscape(u8),
+    InvalidUnicodeEscape(Vec<u8>),
+    /// A `\uXXXX` escape in the surrogate range which wasn't part of a valid pair.
+    LoneSurrogate(u16),
+    UnexpectedEndOfInput,
+}
+
+impl fmt::Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StringError::MissingQuotes => f.write_str("missing quotation marks"),
+            StringError::InvalidUtf8(error) => write!(f, "{}", error),
+            StringError::InvalidEscape(byte) => write!(
+                f,
+                "invalid escape '\\{}'",
+                std::ascii::escape_default(*byte)
+            ),
+            StringError::InvalidUnicodeEscape(digits) => write!(
+                f,
+                "invalid unicode escape '\\u{}'",
+                String::from_utf8_lossy(digits)
+            ),
+            StringError::LoneSurrogate(unit) => write!(f, "lone surrogate '\\u{:04x}'", unit),
+            StringError::UnexpectedEndOfInput => f.write_str("unexpected end of string"),
+        }
+    }
+}
+
+pub type StringResult<T> = std::result::Result<T, StringError>;
+
+/// Strip the quotation marks from a `Token::String`'s raw bytes.
+#[inline]
+pub fn strip_quotes(raw: &[u8]) -> StringResult<&[u8]> {
+    if raw.len() >= 2 && raw[0] == b'"' && raw[raw.len() - 1] == b'"' {
+        Ok(&raw[1..raw.len() - 1])
+    } else {
+        Err(StringError::MissingQuotes)
+    }
+}
+
+/// Decode the raw bytes of a `Token::String` (including the quotation marks)
+/// into its unescaped value.
+///
+/// This only allocates if the string contains escape sequences.
+#[inline]
+pub fn decode(raw: &[u8]) -> StringResult<Cow<'_, str>> {
+    decode_inner(strip_quotes(raw)?)
+}
+
+/// Same as `decode`, but keeps the lifetime of a borrowed token.
+pub fn decode_cow(raw: Cow<'_, [u8]>) -> StringResult<Cow<'_, str>> {
+    match raw {
+        Cow::Borrowed(raw) => decode(raw),
+        Cow::Owned(raw) => decode(&raw).map(|s| Cow::Owned(s.into_owned())),
+    }
+}
+
+/// Same as `decode`, but for the contents of a string without the quotation marks.
+pub fn decode_inner(inner: &[u8]) -> StringResult<Cow<'_, str>> {
+    let first_escape = match inner.iter().position(|&c| c == b'\\') {
+        Some(n) => n,
+        None => {
+            return std::str::from_utf8(inner)
+                .map(Cow::Borrowed)
+                .map_err(StringError::InvalidUtf8)
+        }
+    };
+
+    let mut out = Vec::with_capacity(inner.len());
+    out.extend_from_slice(&inner[..first_escape]);
+
+    let mut i = first_escape;
+    while i < inner.len() {
+        let c = inner[i];
+        if c != b'\\' {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        let escape = *inner.get(i + 1).ok_or(StringError::UnexpectedEndOfInput)?;
+        i += 2;
+        let unescaped = match escape {
+            b'"' => b'"',
+            b'\\' => b'\\',
+            b'/' => b'/',
+            b'b' => 0x08,
+            b'f' => 0x0C,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'u' => {
+                let high = parse_unicode_escape(&inner[i..])?;
+                i += 4;
+                let codepoint = match high {
+                    0xD800..=0xDBFF => {
+                        if inner.get(i) != Some(&b'\\') || inner.get(i + 1) != Some(&b'u') {
+                            return Err(StringError::LoneSurrogate(high));
+                        }
+                        let low = parse_unicode_escape(&inner[i + 2..])?;
+                        if !(0xDC00..=0xDFFF).contains(&low) {
+                            return Err(StringError::LoneSurrogate(high));
+                        }
+                        i += 6;
+                        0x10000 + (((high as u32) - 0xD800) << 10) + ((low as u32) - 0xDC00)
+                    }
+                    0xDC00..=0xDFFF => return Err(StringError::LoneSurrogate(high)),
+                    _ => high as u32,
+                };
+                // Surrogates were handled above, so this is always a valid char.
+                let ch = std::char::from_u32(codepoint).ok_or(StringError::LoneSurrogate(high))?;
+                let mut buf = [0u8; 4];
+                out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+            e => return Err(StringError::InvalidEscape(e)),
+        };
+        out.push(unescaped);
+    }
+
+    String::from_utf8(out)
+        .map(Cow::Owned)
+        .map_err(|e| StringError::InvalidUtf8(e.utf8_error()))
+}
+
+#[inline]
+fn parse_unicode_escape(buf: &[u8]) -> StringResult<u16> {
+    if buf.len() < 4 {
+        return Err(StringError::UnexpectedEndOfInput);
+    }
+    let digits = &buf[..4];
+    if !digits.iter().all(|&c| is_hexdigit(c)) {
+        return Err(StringError::InvalidUnicodeEscape(digits.to_vec()));
+    }
+    let mut n = 0u16;
+    for &c in digits {
+        let digit = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            _ => c - b'A' + 10,
+        };
+        n = (n << 4) | digit as u16;
+    }
+    Ok(n)
+}
+
+/// Which characters `write_escaped_with` escapes beyond what JSON requires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscapeMode {
+    /// Only quotation marks, backslashes and control characters.
+    Minimal,
+    /// Also everything outside ASCII, as `\uXXXX` (with surrogate pairs for
+    /// characters outside the Basic Multilingual Plane).
+    Ascii,
+    /// Also `<`, `>`, `&`, U+2028 and U+2029, so the output can be embedded in
+    /// HTML `<script>` elements and in JavaScript source.
+    Html,
+}
+
+/// The character after the backslash in a two character escape sequence.
+/// These are a subset of the tokenizer's `SINGLE_ESCAPE_CHARACTERS`.
+#[inline]
+fn short_escape(c: char) -> Option<char> {
+    match c {
+        '"' => Some('"'),
+        '\\' => Some('\\'),
+        '\n' => Some('n'),
+        '\r' => Some('r'),
+        '\t' => Some('t'),
+        '\u{08}' => Some('b'),
+        '\u{0C}' => Some('f'),
+        _ => None,
+    }
+}
+
+#[inline]
+fn needs_unicode_escape(c: char, mode: EscapeMode) -> bool {
+    match mode {
+        _ if c <= '\u{1F}' => true,
+        EscapeMode::Minimal => false,
+        EscapeMode::Ascii => !c.is_ascii(),
+        EscapeMode::Html => matches!(c, '<' | '>' | '&' | '\u{2028}' | '\u{2029}'),
+    }
+}
+
+/// Write `s` as a quoted JSON string, escaping only what JSON requires:
+/// quotation marks, backslashes and control characters.
+pub fn write_escaped<W: fmt::Write>(out: &mut W, s: &str) -> fmt::Result {
+    write_escaped_with(out, s, EscapeMode::Minimal)
+}
+
+/// Write `s` as a quoted JSON string, escaping what `mode` requires.
+pub fn write_escaped_with<W: fmt::Write>(out: &mut W, s: &str, mode: EscapeMode) -> fmt::Result {
+    out.write_char('"')?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if let Some(escape) = short_escape(c) {
+            out.write_str(&s[start..i])?;
+            out.write_char('\\')?;
+            out.write_char(escape)?;
+        } else if needs_unicode_escape(c, mode) {
+            out.write_str(&s[start..i])?;
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                write!(out, "\\u{:04x}", unit)?;
+            }
+        } else {
+            continue;
+        }
+        start = i + c.len_utf8();
+    }
+    out.write_str(&s[start..])?;
+    out.write_char('"')
+}
+
+/// `write_escaped_with` to a new `String`.
+pub fn escape(s: &str, mode: EscapeMode) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    write_escaped_with(&mut out, s, mode).expect("writing to a String can't fail");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_borrows_without_escapes() {
+        let decoded = decode(br#""hello world""#).unwrap();
+        assert!(match decoded {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        });
+        assert_eq!(decoded, "hello world");
+    }
+
+    #[test]
+    fn decode_escapes() {
+        for (input, expected) in &[
+            (r#""a\"b""#, "a\"b"),
+            (r#""\\\/\b\f\n\r\t""#, "\\/\u{8}\u{c}\n\r\t"),
+            (r#""\u00e9t\u00E9""#, "été"),
+            (r#""\ud83d\ude00""#, "😀"),
+            (r#""¥\n""#, "¥\n"),
+        ] {
+            assert_eq!(decode(input.as_bytes()).unwrap(), *expected);
+        }
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert_eq!(decode(b"abc"), Err(StringError::MissingQuotes));
+        assert_eq!(
+            decode(br#""\ud83d""#),
+            Err(StringError::LoneSurrogate(0xD83D))
+        );
+        assert_eq!(
+            decode(br#""\ude00""#),
+            Err(StringError::LoneSurrogate(0xDE00))
+        );
+        assert_eq!(decode(br#""\x""#), Err(StringError::InvalidEscape(b'x')));
+        assert_eq!(
+            decode(br#""\u12G4""#),
+            Err(StringError::InvalidUnicodeEscape(b"12G4".to_vec()))
+        );
+    }
+
+    #[test]
+    fn write_escaped_round_trips() {
+        let original = "a\"b\\c\n\u{1}\u{e9}";
+        let mut out = String::new();
+        write_escaped(&mut out, original).unwrap();
+        assert_eq!(out, "\"a\\\"b\\\\c\\n\\u0001\u{e9}\"");
+        assert_eq!(decode(out.as_bytes()).unwrap(), original);
+    }
+
+    #[test]
+    fn escape_modes() {
+        let original = "<a href=\"x\">&\u{e9}\u{1F600}\u{2028}\t</a>";
+        for &(mode, expected) in &[
+            (
+                EscapeMode::Minimal,
+                "\"<a href=\\\"x\\\">&\u{e9}\u{1F600}\u{2028}\\t</a>\"",
+            ),
+            (
+                EscapeMode::Ascii,
+                "\"<a href=\\\"x\\\">&\\u00e9\\ud83d\\ude00\\u2028\\t</a>\"",
+            ),
+            (
+                EscapeMode::Html,
+                "\"\\u003ca href=\\\"x\\\"\\u003e\\u0026\u{e9}\u{1F600}\\u2028\\t\\u003c/a\\u003e\"",
+            ),
+        ] {
+            let escaped = escape(original, mode);
+            assert_eq!(escaped, expected);
+            assert_eq!(decode(escaped.as_bytes()).unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn short_escapes_are_single_escape_characters() {
+        use crate::lookup_tables::SINGLE_ESCAPE_CHARACTERS;
+
+        for c in (0..=0x7Fu8).map(char::from) {
+            if let Some(escape) = short_escape(c) {
+                assert!(SINGLE_ESCAPE_CHARACTERS[escape as usize]);
+            }
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! A flat document representation.
+//!
+//! The tape is one `Vec<u64>` with an entry per value, plus one per container
+//! close and one per object key. Each entry has a tag in its top byte and a
+//! payload in the rest:
+//!
+//! | tag | payload                                                          |
+//! |-----|------------------------------------------------------------------|
+//! | `n` `t` `f` | unused                                                   |
+//! | `d` | offset of the number's source text in the string arena            |
+//! | `s` | offset of the decoded string in the string arena                 |
+//! | `{` `[` | bits 0..32: index after the matching close, bits 32..56: count (saturating) |
+//! | `}` `]` | index of the matching open                                   |
+//!
+//! Arena entries are a little endian `u32` length followed by the bytes.
+//! Skipping a container is a single jump to the entry after its close, so
+//! reaching the N-th element of an array or a member of an object never
+//! looks inside the values in between. This is the same layout simdjson uses.
+
+use crate::stream::TokenStream;
+use crate::string;
+use crate::tokenizer::utils::is_whitespace;
+use crate::tokenizer::Token;
+use crate::value::{Map, Number, Value};
+use crate::{Error, JsonPathSegment, JsonType};
+
+use std::convert::TryInto;
+use std::fmt;
+
+const TAG_SHIFT: u32 = 56;
+const PAYLOAD_MASK: u64 = (1 << TAG_SHIFT) - 1;
+const JUMP_MASK: u64 = 0xFFFF_FFFF;
+const COUNT_SHIFT: u32 = 32;
+const MAX_COUNT: u64 = 0xFF_FFFF;
+
+const NULL: u8 = b'n';
+const TRUE: u8 = b't';
+const FALSE: u8 = b'f';
+const NUMBER: u8 = b'd';
+const STRING: u8 = b's';
+const OBJECT_OPEN: u8 = b'{';
+const OBJECT_CLOSE: u8 = b'}';
+const ARRAY_OPEN: u8 = b'[';
+const ARRAY_CLOSE: u8 = b']';
+
+#[inline]
+fn entry(tag: u8, payload: u64) -> u64 {
+    (u64::from(tag) << TAG_SHIFT) | (payload & PAYLOAD_MASK)
+}
+
+/// The payload of an open entry, where `jump` is the index after its close.
+fn open_payload(count: u64, jump: usize) -> Result<u64, Error> {
+    let jump = jump as u64;
+    if jump > JUMP_MASK {
+        return Err(Error::TooLarge);
+    }
+    Ok((count.min(MAX_COUNT) << COUNT_SHIFT) | jump)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tape {
+    entries: Vec<u64>,
+    arena: Vec<u8>,
+}
+
+impl Tape {
+    /// Parse a single document, which may be surrounded by whitespace.
+    pub fn parse(input: &[u8]) -> Result<Tape, Error> {
+        let mut tape = Tape::default();
+        // Tape indices of the open containers, and how many values each has.
+        let mut stack: Vec<(usize, u64)> = Vec::new();
+        let mut stream = TokenStream::new(input);
+        for token in stream.by_ref() {
+            let token = token?;
+            let index = tape.entries.len();
+            let is_value = token.is_value_start();
+            match token.token {
+                Token::ObjectOpen => tape.entries.push(entry(OBJECT_OPEN, 0)),
+                Token::ArrayOpen => tape.entries.push(entry(ARRAY_OPEN, 0)),
+                Token::ObjectClose | Token::ArrayClose => {
+                    let (open, count) = stack.pop().ok_or(Error::UnexpectedEndOfInput)?;
+                    let tag = if token.token == Token::ObjectClose {
+                        OBJECT_CLOSE
+                    } else {
+                        ARRAY_CLOSE
+                    };
+                    tape.entries.push(entry(tag, open as u64));
+                    tape.entries[open] |= open_payload(count, index + 1)?;
+                }
+                Token::Null => tape.entries.push(entry(NULL, 0)),
+                Token::True => tape.entries.push(entry(TRUE, 0)),
+                Token::False => tape.entries.push(entry(FALSE, 0)),
+                Token::Number(ref raw) => {
+                    let offset = tape.push_arena(raw)?;
+                    tape.entries.push(entry(NUMBER, offset));
+                }
+                Token::String(ref raw) => {
+                    let decoded = string::decode(raw)?;
+                    let offset = tape.push_arena(decoded.as_bytes())?;
+                    tape.entries.push(entry(STRING, offset));
+                }
+                // Commas and colons.
+                _ => continue,
+            }
+            if is_value {
+                if let Some((_, count)) = stack.last_mut() {
+                    *count += 1;
+                }
+            }
+            if token.token.is_open() {
+                stack.push((index, 0));
+            } else if stack.is_empty() {
+                break;
+            }
+        }
+        if tape.entries.is_empty() {
+            return Err(Error::UnexpectedEndOfInput);
+        }
+        let end = stream.offset();
+        match input[end..].iter().position(|&c| !is_whitespace(c)) {
+            Some(n) => Err(Error::TrailingInput(end + n)),
+            None => Ok(tape),
+        }
+    }
+
+    /// Both the length and the offset have to fit in their fields.
+    fn push_arena(&mut self, bytes: &[u8]) -> Result<u64, Error> {
+        let offset = self.arena.len() as u64;
+        let len: u32 = bytes.len().try_into().map_err(|_| Error::TooLarge)?;
+        if offset > PAYLOAD_MASK {
+            return Err(Error::TooLarge);
+        }
+        self.arena.extend_from_slice(&len.to_le_bytes());
+        self.arena.extend_from_slice(bytes);
+        Ok(offset)
+    }
+
+    fn arena_str(&self, offset: u64) -> &str {
+        let offset = offset as usize;
+        let (len, rest) = self.arena[offset..].split_at(4);
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let bytes = &rest[..len];
+        // Only ever filled from validated numbers and decoded strings.
+        std::str::from_utf8(bytes).expect("tape arena holds utf-8")
+    }
+
+    /// The raw entries, for debugging or serializing the tape.
+    #[inline]
+    pub fn entries(&self) -> &[u64] {
+        &self.entries
+    }
+
+    /// A cursor at the document's root value.
+    #[inline]
+    pub fn root(&self) -> Cursor<'_> {
+        Cursor {
+            tape: self,
+            index: 0,
+        }
+    }
+}
+
+/// A position on a `Tape`, pointing at the start of a value.
+#[derive(Clone, Copy, Debug)]
+pub struct Cursor<'t> {
+    tape: &'t Tape,
+    index: usize,
+}
+
+impl<'t> Cursor<'t> {
+    #[inline]
+    fn entry(&self) -> u64 {
+        self.tape.entries[self.index]
+    }
+
+    #[inline]
+    fn tag(&self) -> u8 {
+        (self.entry() >> TAG_SHIFT) as u8
+    }
+
+    #[inline]
+    fn payload(&self) -> u64 {
+        self.entry() & PAYLOAD_MASK
+    }
+
+    #[inline]
+    fn at(&self, index: usize) -> Cursor<'t> {
+        Cursor {
+            tape: self.tape,
+            index,
+        }
+    }
+
+    /// The index of this value's entry on the tape.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The index of the entry after this value, skipping containers in one step.
+    #[inline]
+    pub fn next_index(&self) -> usize {
+        match self.tag() {
+            OBJECT_OPEN | ARRAY_OPEN => (self.payload() & JUMP_MASK) as usize,
+            _ => self.index + 1,
+        }
+    }
+
+    pub fn value_type(&self) -> JsonType {
+        match self.tag() {
+            NULL => JsonType::Null,
+            TRUE | FALSE => JsonType::Bool,
+            NUMBER => JsonType::Number,
+            STRING => JsonType::String,
+            ARRAY_OPEN => JsonType::Array,
+            _ => JsonType::Object,
+        }
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.tag() == NULL
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.tag() {
+            TRUE => Some(true),
+            FALSE => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The number's source text.
+    pub fn as_number(&self) -> Option<&'t str> {
+        match self.tag() {
+            NUMBER => Some(self.tape.arena_str(self.payload())),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'t str> {
+        match self.tag() {
+            STRING => Some(self.tape.arena_str(self.payload())),
+            _ => None,
+        }
+    }
+
+    /// The number of elements or members of a container, or `None` for scalars.
+    pub fn len(&self) -> Option<usize> {
+        let count = match self.tag() {
+            OBJECT_OPEN | ARRAY_OPEN => self.payload() >> COUNT_SHIFT,
+            _ => return None,
+        };
+        if count < MAX_COUNT {
+            return Some(count as usize);
+        }
+        // The count saturated, so walk the container.
+        Some(match self.tag() {
+            ARRAY_OPEN => self.elements().count(),
+            _ => self.members().count(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The elements of an array. This is empty for anything else.
+    pub fn elements(&self) -> Elements<'t> {
+        let (next, end) = match self.tag() {
+            ARRAY_OPEN => (self.index + 1, self.next_index() - 1),
+            _ => (0, 0),
+        };
+        Elements {
+            tape: self.tape,
+            next,
+            end,
+        }
+    }
+
+    /// The members of an object. This is empty for anything else.
+    pub fn members(&self) -> Members<'t> {
+        let (next, end) = match self.tag() {
+            OBJECT_OPEN => (self.index + 1, self.next_index() - 1),
+            _ => (0, 0),
+        };
+        Members {
+            tape: self.tape,
+            next,
+            end,
+        }
+    }
+
+    /// The N-th element of an array, jumping over the ones before it.
+    pub fn get_index(&self, index: usize) -> Option<Cursor<'t>> {
+        self.elements().nth(index)
+    }
+
+    /// An object member. If the key is duplicated, the last one wins.
+    pub fn get_key(&self, key: &str) -> Option<Cursor<'t>> {
+        self.members()
+            .filter(|(k, _)| *k == key)
+            .last()
+            .map(|(_, value)| value)
+    }
+
+    /// Look up a child. Keys only match objects, but an index also names the
+    /// object member with that decimal key, the way a JSON Pointer does.
+    pub fn get(&self, segment: &JsonPathSegment<'_>) -> Option<Cursor<'t>> {
+        match segment {
+            JsonPathSegment::Key(key) => self.get_key(key),
+            JsonPathSegment::Index(index) if self.tag() == OBJECT_OPEN => {
+                self.get_key(&index.to_string())
+            }
+            JsonPathSegment::Index(index) => self.get_index(*index),
+        }
+    }
+
+    /// Look up a descendant. The empty path is the value itself.
+    pub fn get_path(&self, path: &[JsonPathSegment<'_>]) -> Option<Cursor<'t>> {
+        path.iter()
+            .try_fold(*self, |cursor, segment| cursor.get(segment))
+    }
+
+    /// Materialize the value as an owned tree.
+    pub fn to_value(&self) -> Value {
+        match self.tag() {
+            NULL => Value::Null,
+            TRUE => Value::Bool(true),
+            FALSE => Value::Bool(false),
+            NUMBER => Value::Number(
+                self.tape
+                    .arena_str(self.payload())
+                    .parse::<Number>()
+                    .expect("validated by the tokenizer"),
+            ),
+            STRING => Value::String(self.tape.arena_str(self.payload()).to_owned()),
+            ARRAY_OPEN => Value::Array(self.elements().map(|c| c.to_value()).collect()),
+            _ => Value::Object(
+                self.members()
+                    .map(|(k, v)| (k.to_owned(), v.to_value()))
+                    .collect::<Map>(),
+            ),
+        }
+    }
+}
+
+impl<'t> fmt::Display for Cursor<'t> {
+    /// Writes compact JSON.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tag() {
+            NULL => f.write_str("null"),
+            TRUE => f.write_str("true"),
+            FALSE => f.write_str("false"),
+            NUMBER => f.write_str(self.tape.arena_str(self.payload())),
+            STRING => string::write_escaped(f, self.tape.arena_str(self.payload())),
+            ARRAY_OPEN => {
+                f.write_str("[")?;
+                for (i, element) in self.elements().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                f.write_str("]")
+            }
+            _ => {
+                f.write_str("{")?;
+                for (i, (key, value)) in self.members().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    string::write_escaped(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.root())
+    }
+}
+
+pub struct Elements<'t> {
+    tape: &'t Tape,
+    next: usize,
+    end: usize,
+}
+
+impl<'t> Iterator for Elements<'t> {
+    type Item = Cursor<'t>;
+
+    fn next(&mut self) -> Option<Cursor<'t>> {
+        if self.next >= self.end {
+            return None;
+        }
+        let cursor = Cursor {
+            tape: self.tape,
+            index: self.next,
+        };
+        self.next = cursor.next_index();
+        Some(cursor)
+    }
+}
+
+pub struct Members<'t> {
+    tape: &'t Tape,
+    next: usize,
+    end: usize,
+}
+
+impl<'t> Iterator for Members<'t> {
+    type Item = (&'t str, Cursor<'t>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let key = Cursor {
+            tape: self.tape,
+            index: self.next,
+        };
+        let value = key.at(self.next + 1);
+        self.next = value.next_index();
+        Some((key.as_str()?, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonPath;
+
+    const INPUT: &[u8] = br#" {"a": [1, {"b": null}, [true, false]], "c\n": "d", "a": "last"} "#;
+
+    #[test]
+    fn layout() -> Result<(), Error> {
+        let tape = Tape::parse(br#"[1, {"k": []}]"#)?;
+        let tags: String = tape
+            .entries()
+            .iter()
+            .map(|&e| (e >> TAG_SHIFT) as u8 as char)
+            .collect();
+        assert_eq!(tags, "[d{s[]}]");
+        let root = tape.root();
+        assert_eq!(root.next_index(), 8);
+        assert_eq!(root.len(), Some(2));
+        assert_eq!(root.get_index(1).map(|c| c.index()), Some(2));
+        assert!(matches!(open_payload(1, 1 << 32), Err(Error::TooLarge)));
+        Ok(())
+    }
+
+    #[test]
+    fn navigation() -> Result<(), Error> {
+        let tape = Tape::parse(INPUT)?;
+        let root = tape.root();
+        assert_eq!(root.len(), Some(3));
+        assert_eq!(root.get_key("a").and_then(|c| c.as_str()), Some("last"));
+        assert_eq!(root.get_key("c\n").and_then(|c| c.as_str()), Some("d"));
+        let first_a = root.members().next().unwrap().1;
+        assert_eq!(first_a.len(), Some(3));
+        let path = JsonPath::from_pointer("/2/0").unwrap();
+        assert_eq!(
+            first_a.get_path(&path).and_then(|c| c.as_bool()),
+            Some(true)
+        );
+        assert!(first_a
+            .get_index(1)
+            .and_then(|c| c.get_key("b"))
+            .unwrap()
+            .is_null());
+        assert!(first_a.get_index(3).is_none());
+        assert_eq!(first_a.get_index(0).and_then(|c| c.as_number()), Some("1"));
+        let tape = Tape::parse(br#"{"0": [7]}"#)?;
+        let path = JsonPath::from_pointer("/0/0").unwrap();
+        let seven = tape.root().get_path(&path);
+        assert_eq!(seven.and_then(|c| c.as_number()), Some("7"));
+        Ok(())
+    }
+
+    #[test]
+    fn matches_value() -> Result<(), Error> {
+        let tape = Tape::parse(INPUT)?;
+        assert_eq!(tape.root().to_value(), Value::parse(INPUT)?);
+        assert_eq!(
+            tape.to_string(),
+            r#"{"a":[1,{"b":null},[true,false]],"c\n":"d","a":"last"}"#
+        );
+        assert_eq!(Tape::parse(b"\"x\"")?.to_string(), "\"x\"");
+        assert!(matches!(Tape::parse(b"1 2"), Err(Error::TrailingInput(2))));
+        Ok(())
+    }
+}
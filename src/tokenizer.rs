@@ -17,6 +17,7 @@ use crate::section::{ByteSection, PeekSeek};
 use crate::JsonType;
 
 use std::borrow::{Cow, ToOwned};
+use std::fmt;
 use std::io;
 
 // #[derive(Debug, PartialEq, Eq, derive_more::From)]
@@ -185,6 +186,53 @@ impl TokenizeError {
     }
 }
 
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TokenizeError::*;
+        match self {
+            UnexpectedByte(byte) => {
+                write!(f, "unexpected byte '{}'", std::ascii::escape_default(*byte))
+            }
+            UnexpectedByteWithContext { byte, context } => write!(
+                f,
+                "unexpected byte '{}' in {}",
+                std::ascii::escape_default(*byte),
+                context
+            ),
+            UnexpectedEndOfInput | UnexpectedEndOfInputWithContext { context: None, .. } => {
+                f.write_str("unexpected end of input")
+            }
+            UnexpectedEndOfInputWithContext {
+                context: Some(context),
+                ..
+            } => write!(f, "unexpected end of input in {}", context),
+            InvalidStringUnicodeEscape(escape) => write!(
+                f,
+                "invalid unicode escape '\\u{}'",
+                String::from_utf8_lossy(escape)
+            ),
+            InvalidStringEscape(byte) => write!(
+                f,
+                "invalid escape '\\{}'",
+                std::ascii::escape_default(*byte)
+            ),
+            InvalidStringCodepoint(c) => write!(f, "invalid code point U+{:04X}", c),
+        }
+    }
+}
+
+impl fmt::Display for TokenContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TokenContext::String => "a string",
+            TokenContext::Number => "a number",
+            TokenContext::True => "`true`",
+            TokenContext::False => "`false`",
+            TokenContext::Null => "`null`",
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct TokenizeErrorContext {
     expected_byte: Option<u8>,
@@ -1,5 +1,7 @@
 use crate::tokenizer::Token;
 
+use std::fmt;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ValidationContext {
     // TODO use Value and combine ArrayValue and ObjectEntryValue
@@ -84,6 +86,38 @@ pub enum ValidationError {
     UnexpectedEndOfInput,
 }
 
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::Invalid(Some(context)) => {
+                write!(f, "unexpected token after {}", context)
+            }
+            ValidationError::Invalid(None) => {
+                f.write_str("unexpected token outside an array or an object")
+            }
+            ValidationError::UnexpectedEndOfInput => f.write_str("unexpected end of input"),
+        }
+    }
+}
+
+impl fmt::Display for ValidationContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ValidationContext::*;
+        f.write_str(match self {
+            ArrayStart => "the start of an array",
+            ArrayValue => "an array element",
+            ArrayComma => "a comma in an array",
+            ArrayEnd => "the end of an array",
+            ObjectStart => "the start of an object",
+            ObjectEntryKey => "an object key",
+            ObjectEntryColon => "a colon",
+            ObjectEntryValue => "an object member's value",
+            ObjectEntryComma => "a comma in an object",
+            ObjectEnd => "the end of an object",
+        })
+    }
+}
+
 impl ValidationContext {
     // TODO self or &self?
     // const fn valid_sequents(&self) -> &'static [ValidationContext] {
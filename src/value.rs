@@ -0,0 +1,687 @@
+//! An owned document tree.
+//!
+//! `Value` is built by driving the tokenizer and `Validator` through a
+//! `TokenStream`, so it accepts exactly what the rest of the crate accepts.
+//! Numbers keep their source text so that nothing is lost converting to and
+//! from the tree, and objects keep their keys in insertion order.
+
+use crate::decimal::Decimal;
+use crate::section::ByteSection;
+use crate::stream::{StreamToken, TokenStream};
+use crate::string::{self, StringError};
+use crate::tokenizer::utils::{is_whitespace, section_number};
+use crate::tokenizer::Token;
+use crate::{Error, JsonPathSegment, JsonType};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Index;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Value>),
+    Object(Map),
+}
+
+static NULL: Value = Value::Null;
+
+/// A JSON number stored as its source text.
+///
+/// Equality is textual, so `1` and `1.0` are different numbers.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Number(String);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidNumber;
+
+impl Number {
+    /// Use the raw bytes of a `Token::Number` without checking them again.
+    fn from_token(raw: &[u8]) -> Result<Number, Error> {
+        std::str::from_utf8(raw)
+            .map(|s| Number(s.to_owned()))
+            .map_err(|e| Error::String(StringError::InvalidUtf8(e)))
+    }
+
+    /// Convert a float, returning `None` for NaN and infinities which JSON can't represent.
+    pub fn from_f64(n: f64) -> Option<Number> {
+        if n.is_finite() {
+            Some(Number(format!("{:?}", n)))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// True if the number has no fraction or exponent.
+    pub fn is_integer(&self) -> bool {
+        !self.0.contains(&['.', 'e', 'E'][..])
+    }
+
+    /// The value as an `i64` if it is an integer in range.
+    pub fn as_i64(&self) -> Option<i64> {
+        if self.is_integer() {
+            self.0.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// The value as a `u64` if it is a non-negative integer in range.
+    pub fn as_u64(&self) -> Option<u64> {
+        if self.is_integer() {
+            self.0.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// The closest `f64`, which may lose precision.
+    pub fn as_f64(&self) -> f64 {
+        // The grammar is a subset of what `f64::from_str` accepts.
+        self.0.parse().unwrap_or(f64::NAN)
+    }
+
+    /// The exact value. Only fails if the number isn't zero and its exponent
+    /// doesn't fit in an `i64`.
+    pub fn to_decimal(&self) -> Result<Decimal, InvalidNumber> {
+        self.0.parse()
+    }
+}
+
+impl FromStr for Number {
+    type Err = InvalidNumber;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut section = ByteSection::new(s.as_bytes());
+        match section_number(&mut section) {
+            Ok(()) if section.is_empty() => Ok(Number(s.to_owned())),
+            _ => Err(InvalidNumber),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+macro_rules! number_from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for Number {
+                fn from(n: $ty) -> Self {
+                    Number(n.to_string())
+                }
+            }
+
+            impl From<$ty> for Value {
+                fn from(n: $ty) -> Self {
+                    Value::Number(n.into())
+                }
+            }
+        )*
+    };
+}
+
+number_from_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// An object's members in insertion order.
+///
+/// The members are kept in a `Vec`, with an index from each key to its
+/// position so that lookups don't scan the whole object.
+#[derive(Clone, Default)]
+pub struct Map {
+    entries: Vec<(String, Value)>,
+    positions: HashMap<String, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let position = *self.positions.get(key)?;
+        Some(&self.entries[position].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        let position = *self.positions.get(key)?;
+        Some(&mut self.entries[position].1)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Insert a member, returning the previous value. A replaced member keeps
+    /// its original position.
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        match self.get_mut(&key) {
+            Some(existing) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Remove a member, keeping the order of the rest.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let position = self.positions.remove(key)?;
+        for (key, _) in &self.entries[position + 1..] {
+            if let Some(p) = self.positions.get_mut(key) {
+                *p -= 1;
+            }
+        }
+        Some(self.entries.remove(position).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Value)> {
+        self.entries.iter_mut().map(|(k, v)| (&*k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+}
+
+/// Maps are equal if they have the same members in the same order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (String, Value);
+    type IntoIter = std::vec::IntoIter<(String, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl std::iter::FromIterator<(String, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        let mut map = Map::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+enum Frame {
+    Array(Vec<Value>),
+    Object(Map, Option<String>),
+}
+
+impl Value {
+    /// Parse a single document, which may be surrounded by whitespace.
+    pub fn parse(input: &[u8]) -> Result<Value, Error> {
+        let mut stream = TokenStream::new(input);
+        let value = Value::from_stream(&mut stream)?.ok_or(Error::UnexpectedEndOfInput)?;
+        let end = stream.offset();
+        match input[end..].iter().position(|&c| !is_whitespace(c)) {
+            Some(n) => Err(Error::TrailingInput(end + n)),
+            None => Ok(value),
+        }
+    }
+
+    /// Read the next document from a stream, or `None` if the stream is finished.
+    /// This can be called repeatedly for newline delimited JSON.
+    pub fn from_stream(stream: &mut TokenStream<'_>) -> Result<Option<Value>, Error> {
+        match stream.next() {
+            Some(first) => Value::from_tokens(first?, stream).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Build the value which `first` starts, consuming the rest of it from `stream`.
+    pub fn from_tokens<'a>(
+        first: StreamToken<'a>,
+        stream: &mut TokenStream<'a>,
+    ) -> Result<Value, Error> {
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut next = Some(first);
+        loop {
+            let token = match next.take() {
+                Some(token) => token,
+                None => stream.next().ok_or(Error::UnexpectedEndOfInput)??,
+            };
+            if token.is_key() {
+                if let (Some(Frame::Object(_, key)), Token::String(raw)) =
+                    (stack.last_mut(), token.token)
+                {
+                    *key = Some(string::decode_cow(raw)?.into_owned());
+                }
+                continue;
+            }
+            let value = match token.token {
+                Token::ObjectOpen => {
+                    stack.push(Frame::Object(Map::new(), None));
+                    continue;
+                }
+                Token::ArrayOpen => {
+                    stack.push(Frame::Array(Vec::new()));
+                    continue;
+                }
+                Token::ObjectClose | Token::ArrayClose => match stack.pop() {
+                    Some(Frame::Object(map, _)) => Value::Object(map),
+                    Some(Frame::Array(values)) => Value::Array(values),
+                    None => return Err(Error::UnexpectedEndOfInput),
+                },
+                Token::Null => Value::Null,
+                Token::True => Value::Bool(true),
+                Token::False => Value::Bool(false),
+                Token::Number(raw) => Value::Number(Number::from_token(&raw)?),
+                Token::String(raw) => Value::String(string::decode_cow(raw)?.into_owned()),
+                // Commas and colons.
+                _ => continue,
+            };
+            match stack.last_mut() {
+                None => return Ok(value),
+                Some(Frame::Array(values)) => values.push(value),
+                Some(Frame::Object(map, key)) => {
+                    map.insert(key.take().unwrap_or_default(), value);
+                }
+            }
+        }
+    }
+
+    pub fn value_type(&self) -> JsonType {
+        match self {
+            Value::Null => JsonType::Null,
+            Value::Bool(_) => JsonType::Bool,
+            Value::Number(_) => JsonType::Number,
+            Value::String(_) => JsonType::String,
+            Value::Array(_) => JsonType::Array,
+            Value::Object(_) => JsonType::Object,
+        }
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<&Number> {
+        match self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Map> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_object_mut(&mut self) -> Option<&mut Map> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Look up a child. Keys only match objects, but an index also names the
+    /// object member with that decimal key, the way a JSON Pointer does.
+    pub fn get(&self, segment: &JsonPathSegment<'_>) -> Option<&Value> {
+        match (self, segment) {
+            (Value::Object(map), JsonPathSegment::Key(key)) => map.get(key),
+            (Value::Object(map), JsonPathSegment::Index(index)) => map.get(&index.to_string()),
+            (Value::Array(values), JsonPathSegment::Index(index)) => values.get(*index),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, segment: &JsonPathSegment<'_>) -> Option<&mut Value> {
+        match (self, segment) {
+            (Value::Object(map), JsonPathSegment::Key(key)) => map.get_mut(key),
+            (Value::Object(map), JsonPathSegment::Index(index)) => map.get_mut(&index.to_string()),
+            (Value::Array(values), JsonPathSegment::Index(index)) => values.get_mut(*index),
+            _ => None,
+        }
+    }
+
+    /// Look up a descendant. The empty path is the value itself.
+    pub fn get_path(&self, path: &[JsonPathSegment<'_>]) -> Option<&Value> {
+        path.iter()
+            .try_fold(self, |value, segment| value.get(segment))
+    }
+
+    pub fn get_path_mut(&mut self, path: &[JsonPathSegment<'_>]) -> Option<&mut Value> {
+        path.iter()
+            .try_fold(self, |value, segment| value.get_mut(segment))
+    }
+
+    /// Replace the value with `Null`, returning what was there.
+    pub fn take(&mut self) -> Value {
+        std::mem::replace(self, Value::Null)
+    }
+
+    /// Equality of the data rather than the text: numbers compare by value
+    /// (`1.0` equals `1`) and object members in any order.
+    pub fn equivalent(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => match (a.to_decimal(), b.to_decimal()) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => a == b,
+            },
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equivalent(b))
+            }
+            (Value::Object(a), Value::Object(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(key, a)| match b.get(key) {
+                        Some(b) => a.equivalent(b),
+                        None => false,
+                    })
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Value::parse(s.as_bytes())
+    }
+}
+
+/// Indexing returns `Null` for missing members, so lookups can be chained.
+impl<'s> Index<&JsonPathSegment<'s>> for Value {
+    type Output = Value;
+
+    fn index(&self, segment: &JsonPathSegment<'s>) -> &Value {
+        self.get(segment).unwrap_or(&NULL)
+    }
+}
+
+impl<'s> Index<&[JsonPathSegment<'s>]> for Value {
+    type Output = Value;
+
+    fn index(&self, path: &[JsonPathSegment<'s>]) -> &Value {
+        self.get_path(path).unwrap_or(&NULL)
+    }
+}
+
+impl<'s> Index<&crate::JsonPath<'s>> for Value {
+    type Output = Value;
+
+    fn index(&self, path: &crate::JsonPath<'s>) -> &Value {
+        self.get_path(path).unwrap_or(&NULL)
+    }
+}
+
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(map) => map.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        match self {
+            Value::Array(values) => values.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Writes compact JSON.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => string::write_escaped(f, s),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(map) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    string::write_escaped(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl<'s> From<&'s str> for Value {
+    fn from(s: &'s str) -> Self {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+impl From<Map> for Value {
+    fn from(map: Map) -> Self {
+        Value::Object(map)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonPath;
+
+    #[test]
+    fn parse_and_display() -> Result<(), Error> {
+        let input =
+            r#" {"b": [1, 2.50, -3e+10], "a": {"x\ty": null, "z": true}, "s": "é\"", "b": 0} "#;
+        let value: Value = input.parse()?;
+        assert_eq!(
+            value.to_string(),
+            r#"{"b":0,"a":{"x\ty":null,"z":true},"s":"é\""}"#
+        );
+        assert_eq!(value.to_string().parse::<Value>()?, value);
+        Ok(())
+    }
+
+    #[test]
+    fn numbers_are_lossless() -> Result<(), Error> {
+        let value = Value::parse(b"[12345678901234567890123, 1.10, 9007199254740993]")?;
+        assert_eq!(
+            value[0].as_number().unwrap().as_str(),
+            "12345678901234567890123"
+        );
+        assert_eq!(value[1].to_string(), "1.10");
+        assert_eq!(
+            value[2].as_number().and_then(Number::as_u64),
+            Some(9007199254740993)
+        );
+        assert_eq!(value[0].as_number().and_then(Number::as_u64), None);
+        assert_eq!("01".parse::<Number>(), Err(InvalidNumber));
+        assert_eq!(Number::from_f64(0.1).unwrap().as_str(), "0.1");
+        assert_eq!(Number::from_f64(f64::INFINITY), None);
+
+        let a = Value::parse(br#"{"x": [1.0, 2e1], "y": null}"#)?;
+        let b = Value::parse(br#"{"y": null, "x": [1, 20]}"#)?;
+        assert_ne!(a, b);
+        assert!(a.equivalent(&b));
+        assert!(!a.equivalent(&Value::parse(br#"{"x": [1, 20]}"#)?));
+        Ok(())
+    }
+
+    #[test]
+    fn indexing() -> Result<(), Error> {
+        let value = Value::parse(br#"{"a": [{"b": "c"}], "": 1, "0": {"0": 2}}"#)?;
+        let path = JsonPath::from_pointer("/a/0/b").unwrap();
+        assert_eq!(value[&path].as_str(), Some("c"));
+        assert_eq!(value["a"][0]["b"], Value::from("c"));
+        assert_eq!(value[&JsonPathSegment::Key("".into())], Value::from(1));
+        assert!(value["missing"][3].is_null());
+        assert_eq!(
+            value[&JsonPath::from_pointer("/0/0").unwrap()],
+            Value::from(2)
+        );
+        assert_eq!(
+            value.get_path(&JsonPath::from_pointer("/a/1").unwrap()),
+            None
+        );
+        assert_eq!(
+            value.get_path(&JsonPath::from_pointer("").unwrap()),
+            Some(&value)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Value::parse(b"[1, 2").is_err());
+        assert!(matches!(
+            Value::parse(b"[1] [2]"),
+            Err(Error::TrailingInput(4))
+        ));
+        assert!(matches!(
+            Value::parse(b"  "),
+            Err(Error::UnexpectedEndOfInput)
+        ));
+    }
+
+    #[test]
+    fn documents_in_sequence() -> Result<(), Error> {
+        let mut stream = TokenStream::new(b"1\n{\"a\": []}\n\"x\"\n");
+        let mut values = Vec::new();
+        while let Some(value) = Value::from_stream(&mut stream)? {
+            values.push(value.to_string());
+        }
+        assert_eq!(values, vec!["1", r#"{"a":[]}"#, r#""x""#]);
+        Ok(())
+    }
+
+    #[test]
+    fn large_objects() -> Result<(), Error> {
+        let members: Vec<String> = (0..100_000).map(|i| format!("\"k{}\": {}", i, i)).collect();
+        let input = format!("{{{}, \"k7\": -1}}", members.join(", "));
+        let mut value = Value::parse(input.as_bytes())?;
+        let map = value.as_object_mut().unwrap();
+        assert_eq!(map.len(), 100_000);
+        assert_eq!(map.get("k7"), Some(&Value::from(-1)));
+        assert_eq!(map.remove("k0"), Some(Value::from(0)));
+        assert_eq!(map.get("k99999"), Some(&Value::from(99999)));
+        assert_eq!(map.keys().next().map(String::as_str), Some("k1"));
+        map.insert("k0".into(), Value::Null);
+        assert_eq!(map.keys().last().map(String::as_str), Some("k0"));
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+//! A read-only document tree which borrows from the input.
+//!
+//! Strings and numbers are kept as slices of the source, the same way the
+//! tokenizer's `Cow::Borrowed` tokens are, and strings are only unescaped when
+//! they're read. Building a `ValueRef` only allocates for the arrays and
+//! objects themselves.
+
+use crate::stream::{StreamToken, TokenStream};
+use crate::string::{self, StringError, StringResult};
+use crate::tokenizer::utils::is_whitespace;
+use crate::tokenizer::Token;
+use crate::value::{Map, Number, Value};
+use crate::{Error, JsonPathSegment, JsonType};
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Index;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Bool(bool),
+    /// The number's source text.
+    Number(&'a str),
+    String(RawStr<'a>),
+    Array(Vec<ValueRef<'a>>),
+    /// Members in source order, including any duplicate keys.
+    Object(Vec<(RawStr<'a>, ValueRef<'a>)>),
+}
+
+static NULL: ValueRef<'static> = ValueRef::Null;
+
+/// A string token's source bytes, including the quotation marks.
+///
+/// Equality is on the raw bytes, so two strings which only differ in how
+/// they're escaped are not equal. Use `decode` or `eq_str` to compare values.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct RawStr<'a>(&'a [u8]);
+
+impl<'a> RawStr<'a> {
+    /// Wrap the raw bytes of a `Token::String`.
+    pub fn new(raw: &'a [u8]) -> StringResult<RawStr<'a>> {
+        string::strip_quotes(raw)?;
+        Ok(RawStr(raw))
+    }
+
+    /// The source bytes, including the quotation marks.
+    #[inline]
+    pub fn raw(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The bytes between the quotation marks, still escaped.
+    #[inline]
+    pub fn inner(&self) -> &'a [u8] {
+        &self.0[1..self.0.len() - 1]
+    }
+
+    #[inline]
+    pub fn has_escapes(&self) -> bool {
+        self.inner().contains(&b'\\')
+    }
+
+    /// Unescape the string, borrowing from the source if it has no escapes.
+    pub fn decode(&self) -> StringResult<Cow<'a, str>> {
+        string::decode_inner(self.inner())
+    }
+
+    /// Compare the decoded value with `s`, without allocating unless the string has escapes.
+    pub fn eq_str(&self, s: &str) -> bool {
+        if self.has_escapes() {
+            matches!(self.decode(), Ok(ref decoded) if decoded == s)
+        } else {
+            self.inner() == s.as_bytes()
+        }
+    }
+}
+
+impl<'a> fmt::Display for RawStr<'a> {
+    /// Writes the string as it appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.0))
+    }
+}
+
+enum Frame<'a> {
+    Array(Vec<ValueRef<'a>>),
+    Object(Vec<(RawStr<'a>, ValueRef<'a>)>, Option<RawStr<'a>>),
+}
+
+impl<'a> ValueRef<'a> {
+    /// Parse a single document, which may be surrounded by whitespace.
+    pub fn parse(input: &'a [u8]) -> Result<ValueRef<'a>, Error> {
+        let mut stream = TokenStream::new(input);
+        let value = ValueRef::from_stream(&mut stream)?.ok_or(Error::UnexpectedEndOfInput)?;
+        let end = stream.offset();
+        match input[end..].iter().position(|&c| !is_whitespace(c)) {
+            Some(n) => Err(Error::TrailingInput(end + n)),
+            None => Ok(value),
+        }
+    }
+
+    /// Read the next document from a stream, or `None` if the stream is finished.
+    pub fn from_stream(stream: &mut TokenStream<'a>) -> Result<Option<ValueRef<'a>>, Error> {
+        match stream.next() {
+            Some(first) => ValueRef::from_tokens(first?, stream).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Build the value which `first` starts, consuming the rest of it from `stream`.
+    pub fn from_tokens(
+        first: StreamToken<'a>,
+        stream: &mut TokenStream<'a>,
+    ) -> Result<ValueRef<'a>, Error> {
+        let source = stream.source();
+        let mut stack: Vec<Frame<'a>> = Vec::new();
+        let mut next = Some(first);
+        loop {
+            let token = match next.take() {
+                Some(token) => token,
+                None => stream.next().ok_or(Error::UnexpectedEndOfInput)??,
+            };
+            // Slice the source rather than the token so the lifetime is always `'a`.
+            let raw = &source[token.start..token.end];
+            if token.is_key() {
+                if let Some(Frame::Object(_, key)) = stack.last_mut() {
+                    *key = Some(RawStr::new(raw)?);
+                }
+                continue;
+            }
+            let value = match token.token {
+                Token::ObjectOpen => {
+                    stack.push(Frame::Object(Vec::new(), None));
+                    continue;
+                }
+                Token::ArrayOpen => {
+                    stack.push(Frame::Array(Vec::new()));
+                    continue;
+                }
+                Token::ObjectClose | Token::ArrayClose => match stack.pop() {
+                    Some(Frame::Object(members, _)) => ValueRef::Object(members),
+                    Some(Frame::Array(values)) => ValueRef::Array(values),
+                    None => return Err(Error::UnexpectedEndOfInput),
+                },
+                Token::Null => ValueRef::Null,
+                Token::True => ValueRef::Bool(true),
+                Token::False => ValueRef::Bool(false),
+                Token::Number(_) => {
+                    ValueRef::Number(std::str::from_utf8(raw).map_err(StringError::InvalidUtf8)?)
+                }
+                Token::String(_) => ValueRef::String(RawStr::new(raw)?),
+                // Commas and colons.
+                _ => continue,
+            };
+            match stack.last_mut() {
+                None => return Ok(value),
+                Some(Frame::Array(values)) => values.push(value),
+                Some(Frame::Object(members, key)) => {
+                    let key = key.take().ok_or(Error::UnexpectedEndOfInput)?;
+                    members.push((key, value));
+                }
+            }
+        }
+    }
+
+    pub fn value_type(&self) -> JsonType {
+        match self {
+            ValueRef::Null => JsonType::Null,
+            ValueRef::Bool(_) => JsonType::Bool,
+            ValueRef::Number(_) => JsonType::Number,
+            ValueRef::String(_) => JsonType::String,
+            ValueRef::Array(_) => JsonType::Array,
+            ValueRef::Object(_) => JsonType::Object,
+        }
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        *self == ValueRef::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ValueRef::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<&'a str> {
+        match self {
+            ValueRef::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Decode a string value. This only allocates if the string has escapes.
+    pub fn as_str(&self) -> Option<StringResult<Cow<'a, str>>> {
+        match self {
+            ValueRef::String(s) => Some(s.decode()),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[ValueRef<'a>]> {
+        match self {
+            ValueRef::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(RawStr<'a>, ValueRef<'a>)]> {
+        match self {
+            ValueRef::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+
+    /// Look up an object member. If the key is duplicated, the last one wins,
+    /// matching `Value`.
+    pub fn get_key(&self, key: &str) -> Option<&ValueRef<'a>> {
+        match self {
+            ValueRef::Object(members) => members
+                .iter()
+                .rev()
+                .find(|(k, _)| k.eq_str(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<&ValueRef<'a>> {
+        match self {
+            ValueRef::Array(values) => values.get(index),
+            _ => None,
+        }
+    }
+
+    /// Look up a child. Keys only match objects, but an index also names the
+    /// object member with that decimal key, the way a JSON Pointer does.
+    pub fn get(&self, segment: &JsonPathSegment<'_>) -> Option<&ValueRef<'a>> {
+        match (self, segment) {
+            (_, JsonPathSegment::Key(key)) => self.get_key(key),
+            (ValueRef::Object(_), JsonPathSegment::Index(index)) => {
+                self.get_key(&index.to_string())
+            }
+            (_, JsonPathSegment::Index(index)) => self.get_index(*index),
+        }
+    }
+
+    /// Look up a descendant. The empty path is the value itself.
+    pub fn get_path(&self, path: &[JsonPathSegment<'_>]) -> Option<&ValueRef<'a>> {
+        path.iter()
+            .try_fold(self, |value, segment| value.get(segment))
+    }
+
+    /// Copy into an owned `Value`, decoding every string.
+    pub fn to_value(&self) -> Result<Value, StringError> {
+        Ok(match self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Bool(b) => Value::Bool(*b),
+            ValueRef::Number(n) => {
+                Value::Number(n.parse::<Number>().expect("validated by the tokenizer"))
+            }
+            ValueRef::String(s) => Value::String(s.decode()?.into_owned()),
+            ValueRef::Array(values) => Value::Array(
+                values
+                    .iter()
+                    .map(ValueRef::to_value)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ValueRef::Object(members) => {
+                let mut map = Map::new();
+                for (key, value) in members {
+                    map.insert(key.decode()?.into_owned(), value.to_value()?);
+                }
+                Value::Object(map)
+            }
+        })
+    }
+}
+
+impl<'a, 's> Index<&JsonPathSegment<'s>> for ValueRef<'a> {
+    type Output = ValueRef<'a>;
+
+    fn index(&self, segment: &JsonPathSegment<'s>) -> &ValueRef<'a> {
+        self.get(segment).unwrap_or(&NULL)
+    }
+}
+
+impl<'a, 's> Index<&crate::JsonPath<'s>> for ValueRef<'a> {
+    type Output = ValueRef<'a>;
+
+    fn index(&self, path: &crate::JsonPath<'s>) -> &ValueRef<'a> {
+        self.get_path(path).unwrap_or(&NULL)
+    }
+}
+
+impl<'a> Index<&str> for ValueRef<'a> {
+    type Output = ValueRef<'a>;
+
+    fn index(&self, key: &str) -> &ValueRef<'a> {
+        self.get_key(key).unwrap_or(&NULL)
+    }
+}
+
+impl<'a> Index<usize> for ValueRef<'a> {
+    type Output = ValueRef<'a>;
+
+    fn index(&self, index: usize) -> &ValueRef<'a> {
+        self.get_index(index).unwrap_or(&NULL)
+    }
+}
+
+impl<'a> fmt::Display for ValueRef<'a> {
+    /// Writes compact JSON. Strings are written with their original escapes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueRef::Null => f.write_str("null"),
+            ValueRef::Bool(b) => write!(f, "{}", b),
+            ValueRef::Number(n) => f.write_str(n),
+            ValueRef::String(s) => write!(f, "{}", s),
+            ValueRef::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            ValueRef::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", key, value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonPath;
+
+    #[test]
+    fn borrows_and_decodes_lazily() -> Result<(), Error> {
+        let input = br#"{"plain": "abc", "escaped": "a\nb", "n": -1.50}"#;
+        let value = ValueRef::parse(input)?;
+        match value["plain"].as_str() {
+            Some(Ok(Cow::Borrowed("abc"))) => (),
+            other => panic!("expected a borrowed string, got {:?}", other),
+        }
+        match value["escaped"].as_str() {
+            Some(Ok(Cow::Owned(ref s))) if s == "a\nb" => (),
+            other => panic!("expected an owned string, got {:?}", other),
+        }
+        assert_eq!(value["n"].as_number(), Some("-1.50"));
+        assert_eq!(
+            value.to_string(),
+            r#"{"plain":"abc","escaped":"a\nb","n":-1.50}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn navigation() -> Result<(), Error> {
+        let input = br#"[{"a": 1, "a": [true, null]}]"#;
+        let value = ValueRef::parse(input)?;
+        assert_eq!(value[0].as_object().map(<[_]>::len), Some(2));
+        let path = JsonPath::from_pointer("/0/a/0").unwrap();
+        assert_eq!(value[&path], ValueRef::Bool(true));
+        assert!(value[1]["missing"].is_null());
+        let value = ValueRef::parse(br#"{"0": [7]}"#)?;
+        let path = JsonPath::from_pointer("/0/0").unwrap();
+        assert_eq!(value[&path].as_number(), Some("7"));
+        Ok(())
+    }
+
+    #[test]
+    fn to_value() -> Result<(), Error> {
+        let input = r#"{"k\"": ["é", 10, {}]}"#.as_bytes();
+        assert_eq!(ValueRef::parse(input)?.to_value()?, Value::parse(input)?);
+        Ok(())
+    }
+}
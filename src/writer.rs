@@ -0,0 +1,200 @@
+//! Writing JSON one call at a time.
+//!
+//! `JsonWriter` inserts commas and colons itself and runs every token through
+//! a `Validator`, so a mistake such as a key inside an array or an `end` with
+//! nothing open is reported as a `ValidationError` rather than producing
+//! invalid output. Strings are escaped with `string::write_escaped`.
+
+use crate::string;
+use crate::tokenizer::Token;
+use crate::validator::{ValidationContext, ValidationError, Validator};
+use crate::value::Number;
+use crate::Error;
+
+use std::borrow::Cow;
+use std::io;
+
+pub struct JsonWriter<W> {
+    writer: W,
+    validator: Validator,
+    /// A complete document has been written, so the next one starts on a new
+    /// line.
+    document_ended: bool,
+}
+
+impl<W: io::Write> JsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonWriter {
+            writer,
+            validator: Validator::new(),
+            document_ended: false,
+        }
+    }
+
+    fn emit(&mut self, token: &Token<'_>) -> Result<(), Error> {
+        self.validator.process_token(token)?;
+        token.print(&mut self.writer)?;
+        Ok(())
+    }
+
+    /// Write the comma before the next key or value if one is needed, and
+    /// check that `allowed` contexts are where the writer is now.
+    fn prepare(&mut self, allowed: &[Option<ValidationContext>]) -> Result<(), Error> {
+        use ValidationContext::*;
+
+        let context = self.validator.current_context();
+        let needs_comma = context == Some(ArrayValue) || context == Some(ObjectEntryValue);
+        let next = match context {
+            Some(ArrayValue) => Some(ArrayComma),
+            Some(ObjectEntryValue) => Some(ObjectEntryComma),
+            context => context,
+        };
+        if !allowed.contains(&next) {
+            return Err(ValidationError::Invalid(context).into());
+        }
+        if needs_comma {
+            self.emit(&Token::Comma)?;
+        }
+        if context.is_none() && self.document_ended {
+            self.document_ended = false;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, token: &Token<'_>) -> Result<(), Error> {
+        use ValidationContext::*;
+
+        self.prepare(&[
+            None,
+            Some(ArrayStart),
+            Some(ArrayComma),
+            Some(ObjectEntryColon),
+        ])?;
+        self.emit(token)?;
+        self.end_value();
+        Ok(())
+    }
+
+    fn end_value(&mut self) {
+        if self.validator.current_context().is_none() {
+            self.document_ended = true;
+        }
+    }
+
+    pub fn begin_object(&mut self) -> Result<(), Error> {
+        self.value(&Token::ObjectOpen)
+    }
+
+    pub fn begin_array(&mut self) -> Result<(), Error> {
+        self.value(&Token::ArrayOpen)
+    }
+
+    /// Write an object key. This is an error anywhere but in an object
+    /// before a member.
+    pub fn key(&mut self, key: &str) -> Result<(), Error> {
+        use ValidationContext::*;
+
+        self.prepare(&[Some(ObjectStart), Some(ObjectEntryComma)])?;
+        let quoted = escaped(key);
+        self.emit(&Token::String(Cow::Borrowed(quoted.as_bytes())))?;
+        self.emit(&Token::Colon)
+    }
+
+    pub fn string(&mut self, s: &str) -> Result<(), Error> {
+        let quoted = escaped(s);
+        self.value(&Token::String(Cow::Borrowed(quoted.as_bytes())))
+    }
+
+    pub fn number<N: Into<Number>>(&mut self, n: N) -> Result<(), Error> {
+        let n = n.into();
+        self.value(&Token::Number(Cow::Borrowed(n.as_str().as_bytes())))
+    }
+
+    pub fn bool(&mut self, b: bool) -> Result<(), Error> {
+        self.value(if b { &Token::True } else { &Token::False })
+    }
+
+    pub fn null(&mut self) -> Result<(), Error> {
+        self.value(&Token::Null)
+    }
+
+    /// Close the innermost open object or array.
+    pub fn end(&mut self) -> Result<(), Error> {
+        let close = match self.validator.current_context() {
+            Some(context) if context.in_array() => Token::ArrayClose,
+            Some(_) => Token::ObjectClose,
+            None => return Err(ValidationError::Invalid(None).into()),
+        };
+        self.emit(&close)?;
+        self.end_value();
+        Ok(())
+    }
+
+    /// Check that every container was closed, and return the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.validator.finish()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn escaped(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    string::write_escaped(&mut quoted, s).expect("writing to a String can't fail");
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_punctuation() -> Result<(), Error> {
+        let mut writer = JsonWriter::new(Vec::new());
+        writer.begin_object()?;
+        writer.key("url")?;
+        writer.string("http://example.com/?q=\"x\"")?;
+        writer.key("tags")?;
+        writer.begin_array()?;
+        writer.number(1)?;
+        writer.begin_object()?;
+        writer.end()?;
+        writer.bool(true)?;
+        writer.null()?;
+        writer.end()?;
+        writer.end()?;
+        writer.begin_array()?;
+        writer.end()?;
+        assert_eq!(
+            String::from_utf8(writer.finish()?).unwrap(),
+            "{\"url\":\"http://example.com/?q=\\\"x\\\"\",\"tags\":[1,{},true,null]}\n[]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_calls() {
+        let invalid = |result: Result<(), Error>| {
+            matches!(result, Err(Error::Validation(ValidationError::Invalid(_))))
+        };
+        let mut writer = JsonWriter::new(Vec::new());
+        writer.begin_array().unwrap();
+        writer.null().unwrap();
+        assert!(invalid(writer.key("a")));
+        writer.end().unwrap();
+        assert_eq!(writer.finish().unwrap(), b"[null]");
+
+        let mut writer = JsonWriter::new(Vec::new());
+        writer.begin_object().unwrap();
+        assert!(invalid(writer.string("a")));
+        writer.key("a").unwrap();
+        assert!(invalid(writer.key("b")));
+        assert!(invalid(writer.end()));
+
+        let mut writer = JsonWriter::new(Vec::new());
+        assert!(invalid(writer.end()));
+        writer.begin_array().unwrap();
+        assert!(writer.finish().is_err());
+    }
+}